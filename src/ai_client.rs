@@ -1,219 +1,638 @@
-use crate::config::{ProviderConfig, CommandAiConfig, GitConfig, Config};
-use crate::git_ops::{DiffSegment, FileSummary, DiffStats};
+use crate::anthropic::AnthropicClient;
+use crate::commit_message::CommitMessage;
+use crate::gemini::GeminiClient;
+use crate::interrupt::Interrupted;
+use crate::config::{ProviderConfig, ProviderKind, CommandAiConfig, GitConfig, Config, MASKED_API_KEY};
+use crate::git_ops::{CommitContext, DiffSegment, FileSummary};
+use crate::ollama_api::OllamaApi;
+use crate::openai_compat::OpenAiClient;
+use crate::retry::{self, RetryPolicy};
+use crate::streaming::{self, TokenStream};
+use crate::tokens::{self, DiffBudget};
+use crate::utils::Utils;
 use anyhow::{anyhow, Result};
-use ai::clients::{ollama, openai};
-use ai::chat_completions::{ChatCompletion, ChatCompletionMessage, ChatCompletionRequestBuilder};
-use tokio::sync::Semaphore;
+use ai::chat_completions::{ChatCompletionMessage, ChatCompletionRequest, ChatCompletionRequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::time::Instant;
+use tokio::sync::{Mutex, Semaphore};
 use tokio::time::{timeout, Duration};
-use std::sync::Arc;
 
+/// System prompt for `ai chat` / `ai ask` unless `[commands.conversation].system_prompt` is set
+const DEFAULT_CHAT_SYSTEM_PROMPT: &str = "You are a helpful assistant running in the user's terminal. \
+Answer concisely and put commands and code in Markdown code blocks.";
+
+/// System prompt for condensing the oldest turns of a long chat
+const CONVERSATION_SUMMARY_PROMPT: &str = "You condense chat transcripts so the conversation can continue \
+without them. Write a concise summary of the conversation you are given, merged with the summary so far \
+if there is one. Keep facts, decisions, file names, commands and code the user may refer back to, and the \
+user's preferences and open questions. Reply only with the summary.";
+
+/// System prompt for commit messages unless `[commands.git_operations].system_prompt` is set.
+/// The configured commit style's rules are always appended.
+const DEFAULT_COMMIT_SYSTEM_PROMPT: &str = "You are an expert software engineer that writes Git commit messages.
+Review the repository context and diffs you are given carefully and describe what changed.
+Write in the imperative mood (e.g., \"add feature\" not \"added feature\" or \"adding feature\").
+IMPORTANT: The description must start with a lowercase letter.
+Reply only with the commit message, without quotes, markdown, explanations or any other text.";
+
+/// Smallest diff budget used even when the prompt leaves less room, so every file
+/// still gets a segment of its own
+const MIN_DIFF_TOKENS: usize = 256;
+
+/// System prompt for `ai fix` unless `[commands.error_analysis].system_prompt` is set
+const DEFAULT_FIX_SYSTEM_PROMPT: &str = r#"You are an expert system administrator and developer that helps fix command line errors.
+
+Analyze the provided context to identify the root cause of errors and provide solutions.
+
+IMPORTANT: Pay attention to the "Analysis Type" field:
+- If it's "Shell startup error", focus on configuration file issues (.zshrc, .bashrc, .gitconfig, etc.)
+- If it's command execution error, focus on the failed command in the history
+
+Please follow this format:
+## Analysis
+[Brief explanation of what went wrong]
+
+## Root Cause  
+[The specific reason for the failure]
+
+## Solution
+[Step by step explanation of how to fix it]
+
+## Commands
+```bash
+# Commands to fix the issue (one per line)
+command1
+command2
+command3
+```
+
+The commands section should only contain the actual shell commands that need to be executed, one per line, without explanations or comments inside the code block.
+
+For shell startup errors, common causes include:
+- Corrupted config files (.zshrc, .bashrc, .gitconfig)
+- Lock files that weren't cleaned up properly
+- Permission issues with config files
+- Path issues or missing dependencies
+- Syntax errors in shell configuration
+"#;
+
+#[derive(Clone)]
 pub enum AiClientType {
-    Ollama(ollama::Client),
-    OpenAi(openai::Client),
+    Ollama(OllamaApi),
+    OpenAi(OpenAiClient),
+    Anthropic(AnthropicClient),
+    Gemini(GeminiClient),
 }
 
-pub struct AiClient {
+/// A provider and model that requests can be sent to
+#[derive(Clone)]
+struct Target {
+    provider: String,
     provider_config: ProviderConfig,
+    model: String,
+    client: AiClientType,
+    /// Resolved API key, kept to scrub it from error messages
+    api_key: String,
+}
+
+impl Target {
+    fn label(&self) -> String {
+        format!("{}/{}", self.provider, self.model)
+    }
+}
+
+pub struct AiClient {
     command_config: CommandAiConfig,
     git_config: GitConfig,
-    client: AiClientType,
+    /// The command's provider followed by its fallbacks, tried in order
+    targets: Vec<Target>,
+    retry: RetryPolicy,
+    http: reqwest::Client,
     full_config: Option<Config>,
+    /// Ollama targets that passed the pre-flight check, with the context window the server reported
+    prepared: Mutex<HashMap<String, Option<usize>>>,
+    usage: std::sync::Mutex<ModelUsage>,
+}
+
+/// Which provider and model answered a command's requests, and how long they took
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModelUsage {
+    pub provider: String,
+    pub model: String,
+    pub requests: usize,
+    /// Time until each reply, retries and fallbacks included; parallel requests add up
+    pub duration_ms: u64,
+}
+
+impl ModelUsage {
+    fn new(target: &Target) -> Self {
+        Self {
+            provider: target.provider.clone(),
+            model: target.model.clone(),
+            ..Self::default()
+        }
+    }
 }
 
 impl AiClient {
-    fn create_client(provider_name: &str, provider_config: &ProviderConfig) -> Result<AiClientType> {
-        match provider_name {
-            "ollama" => {
-                let client = ollama::Client::from_url(&provider_config.base_url)
-                    .map_err(|e| anyhow!("Failed to create Ollama client: {}", e))?;
-                Ok(AiClientType::Ollama(client))
+    fn create_client(provider_name: &str, provider_config: &ProviderConfig, api_key: &str, http: &reqwest::Client) -> Result<AiClientType> {
+        match provider_config.kind(provider_name) {
+            ProviderKind::Ollama => {
+                Ok(AiClientType::Ollama(OllamaApi::new(http.clone(), &provider_config.base_url)))
+            }
+            ProviderKind::OpenaiCompatible => {
+                Ok(AiClientType::OpenAi(OpenAiClient::new(http.clone(), &provider_config.base_url, api_key)))
+            }
+            ProviderKind::Anthropic => {
+                Ok(AiClientType::Anthropic(AnthropicClient::new(
+                    http.clone(),
+                    &provider_config.base_url,
+                    api_key,
+                    provider_config.max_tokens,
+                )))
             }
-            "openai" | "deepseek" => {
-                let client = if provider_config.api_key.is_empty() {
-                    return Err(anyhow!("API key is required for {} provider", provider_name));
-                } else {
-                    openai::Client::from_url(&provider_config.api_key, &provider_config.base_url)
-                        .map_err(|e| anyhow!("Failed to create OpenAI client: {}", e))?
-                };
-                Ok(AiClientType::OpenAi(client))
+            ProviderKind::Gemini => {
+                Ok(AiClientType::Gemini(GeminiClient::new(
+                    http.clone(),
+                    &provider_config.base_url,
+                    api_key,
+                    provider_config.max_tokens,
+                )))
             }
-            _ => Err(anyhow!("Unsupported provider: {}", provider_name)),
         }
     }
+
+    /// The provider's API key from its configured source; an error if it needs one and has none
+    fn api_key(provider_name: &str, provider_config: &ProviderConfig) -> Result<String> {
+        let api_key = provider_config.resolve_api_key(provider_name)?;
+        if api_key.is_empty() && Self::requires_api_key(provider_name, provider_config) {
+            return Err(anyhow!(
+                "API key is required for {} provider (set api_key_env, api_key_file, api_key_cmd or api_key)",
+                provider_name
+            ));
+        }
+        Ok(api_key)
+    }
+
+    /// Whether requests to the provider need an API key; Ollama and local servers
+    /// such as vLLM or LM Studio usually run without one
+    pub fn requires_api_key(provider_name: &str, provider_config: &ProviderConfig) -> bool {
+        provider_config.kind(provider_name) != ProviderKind::Ollama && !Self::is_local_url(&provider_config.base_url)
+    }
+
+    fn is_local_url(url: &str) -> bool {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| matches!(host, "localhost" | "127.0.0.1" | "[::1]" | "0.0.0.0")))
+            .unwrap_or(false)
+    }
+    
+    fn create_target(provider: &str, provider_config: &ProviderConfig, model: &str, http: &reqwest::Client) -> Result<Target> {
+        let api_key = Self::api_key(provider, provider_config)?;
+        Ok(Target {
+            provider: provider.to_string(),
+            provider_config: provider_config.clone(),
+            model: model.to_string(),
+            client: Self::create_client(provider, provider_config, &api_key, http)?,
+            api_key,
+        })
+    }
+
+    /// Targets for the command's `fallback` list; ones that can't be set up are skipped with a warning
+    fn fallback_targets(command_config: &CommandAiConfig, config: &Config, http: &reqwest::Client) -> Vec<Target> {
+        let mut targets = Vec::new();
+        for fallback in &command_config.fallback {
+            let model = fallback.model.as_deref().unwrap_or(&command_config.model);
+            let target = config.providers.get(&fallback.provider)
+                .ok_or_else(|| anyhow!("Provider '{}' not found in config", fallback.provider))
+                .and_then(|provider_config| Self::create_target(&fallback.provider, provider_config, model, http));
+
+            match target {
+                Ok(target) => targets.push(target),
+                Err(e) => eprintln!("⚠️  Skipping fallback {}/{}: {}", fallback.provider, model, e),
+            }
+        }
+        targets
+    }
     
-    #[allow(dead_code)]
     pub fn new(provider_config: ProviderConfig, command_config: CommandAiConfig, git_config: GitConfig) -> Result<Self> {
-        let client = Self::create_client(&command_config.provider, &provider_config)?;
+        let http = reqwest::Client::new();
+        let primary = Self::create_target(&command_config.provider, &provider_config, &command_config.model, &http)?;
         
         Ok(Self { 
-            provider_config, 
             command_config,
             git_config,
-            client,
+            usage: std::sync::Mutex::new(ModelUsage::new(&primary)),
+            targets: vec![primary],
+            retry: RetryPolicy::from_config(&Default::default()),
+            http,
             full_config: None,
+            prepared: Mutex::new(HashMap::new()),
         })
     }
 
     pub fn new_with_full_config(provider_config: ProviderConfig, command_config: CommandAiConfig, git_config: GitConfig, full_config: Config) -> Result<Self> {
-        let client = Self::create_client(&command_config.provider, &provider_config)?;
+        let http = reqwest::Client::new();
+        let mut targets = vec![Self::create_target(&command_config.provider, &provider_config, &command_config.model, &http)?];
+        targets.extend(Self::fallback_targets(&command_config, &full_config, &http));
         
         Ok(Self { 
-            provider_config, 
             command_config,
             git_config,
-            client,
+            usage: std::sync::Mutex::new(ModelUsage::new(&targets[0])),
+            targets,
+            retry: RetryPolicy::from_config(&full_config.retry),
+            http,
             full_config: Some(full_config),
+            prepared: Mutex::new(HashMap::new()),
         })
     }
 
-    pub async fn ask(&self, question: &str) -> Result<String> {
-        let request = ChatCompletionRequestBuilder::default()
-            .model(&self.command_config.model)
-            .messages(vec![
-                ChatCompletionMessage::User(question.into()),
-            ])
-            .build()
-            .map_err(|e| anyhow!("Failed to build chat request: {}", e))?;
+    /// Ask a single question after the `context` messages (such as attached files)
+    pub async fn ask(&self, question: &str, context: &[ChatMessage]) -> Result<String> {
+        self.chat(&self.question_messages(question, context)).await
+    }
 
-        let response = match &self.client {
-            AiClientType::Ollama(client) => {
-                client.chat_completions(&request).await
-                    .map_err(|e| self.handle_ollama_error(e))?
+    fn question_messages(&self, question: &str, context: &[ChatMessage]) -> Vec<ChatMessage> {
+        let mut messages = vec![ChatMessage::system(self.chat_system_prompt())];
+        messages.extend_from_slice(context);
+        messages.push(ChatMessage::user(question));
+        messages
+    }
+
+    /// Send `prompt` as the user turn with `system` as the system message
+    async fn ask_with_system(&self, system: &str, prompt: &str) -> Result<String> {
+        self.chat(&[ChatMessage::system(system), ChatMessage::user(prompt)]).await
+    }
+
+    /// The command's configured system prompt, or `default`
+    fn system_prompt<'a>(&'a self, default: &'a str) -> &'a str {
+        self.command_config.system_prompt.as_deref()
+            .filter(|prompt| !prompt.trim().is_empty())
+            .unwrap_or(default)
+    }
+
+    /// System message for commit generation: the command's prompt plus the style rules
+    fn commit_system_prompt(&self, instructions: &str) -> String {
+        let mut system = self.system_prompt(DEFAULT_COMMIT_SYSTEM_PROMPT).to_string();
+        if !instructions.trim().is_empty() {
+            system.push_str("\n\n");
+            system.push_str(instructions.trim());
+        }
+        system
+    }
+
+    pub async fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        self.complete(messages, None).await
+    }
+
+    /// Send `messages` and return the reply, with retries and fallbacks.
+    /// `attempt_timeout` limits each single request.
+    async fn complete(&self, messages: &[ChatMessage], attempt_timeout: Option<Duration>) -> Result<String> {
+        self.with_fallback(|target| async move {
+            let request = Self::build_request(&target.model, messages, false)?;
+            match attempt_timeout {
+                Some(limit) => timeout(limit, self.send_request(target, &request)).await
+                    .map_err(|_| anyhow!("Request timeout after {}s", limit.as_secs()))?,
+                None => self.send_request(target, &request).await,
+            }
+        }).await
+    }
+
+    /// Send a non-streaming request to `target` and return the reply text
+    async fn send_request(&self, target: &Target, request: &ChatCompletionRequest) -> Result<String> {
+        match &target.client {
+            AiClientType::Ollama(client) => client.chat(request, self.num_ctx(target).await).await,
+            AiClientType::OpenAi(client) => client.chat_completions(request).await,
+            AiClientType::Anthropic(client) => client.messages(request).await,
+            AiClientType::Gemini(client) => client.generate_content(request).await,
+        }
+    }
+
+    /// Run `operation` against each target in turn until one succeeds, retrying
+    /// transient errors on each and logging why every fallback was needed
+    async fn with_fallback<'a, T, F, Fut>(&'a self, operation: F) -> Result<T>
+    where
+        F: Fn(&'a Target) -> Fut,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let started = Instant::now();
+        let mut last_error = None;
+        for (index, target) in self.targets.iter().enumerate() {
+            if let Some(error) = &last_error {
+                eprintln!(
+                    "⚠️  {} failed: {}\n↪️  Falling back to {}...",
+                    self.targets[index - 1].label(),
+                    retry::first_line(error),
+                    target.label()
+                );
+            }
+
+            let result = match self.prepare(target).await {
+                Ok(_) => match self.retry.run(&target.label(), || operation(target)).await {
+                    // Ctrl-C while waiting to retry stops the request instead of falling back
+                    Err(e) if e.is::<Interrupted>() => return Err(e),
+                    result => result.map_err(|e| self.describe_error(target, e)),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(value) => {
+                    self.record_usage(target, started.elapsed());
+                    return Ok(value);
+                }
+                Err(e) => last_error = Some(e),
             }
-            AiClientType::OpenAi(client) => {
-                client.chat_completions(&request).await
-                    .map_err(|e| anyhow!("OpenAI API error: {}", e))?
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No AI provider configured")))
+    }
+
+    /// Count a request `target` answered; the latest target is reported as the one used
+    fn record_usage(&self, target: &Target, elapsed: Duration) {
+        if let Ok(mut usage) = self.usage.lock() {
+            usage.provider = target.provider.clone();
+            usage.model = target.model.clone();
+            usage.requests += 1;
+            usage.duration_ms += elapsed.as_millis() as u64;
+        }
+    }
+
+    /// The provider and model that answered so far (the configured one before any
+    /// request), with the number of requests and their time
+    pub fn usage(&self) -> ModelUsage {
+        self.usage.lock().map(|usage| usage.clone()).unwrap_or_default()
+    }
+
+    /// Pre-flight check for Ollama targets: make sure the model is installed, offering to
+    /// pull it, and ask the server for its context window. Other targets need no check.
+    async fn prepare(&self, target: &Target) -> Result<Option<usize>> {
+        if !matches!(target.client, AiClientType::Ollama(_)) {
+            return Ok(None);
+        }
+
+        // Held for the whole check so parallel requests don't all offer the same pull
+        let mut prepared = self.prepared.lock().await;
+        if let Some(window) = prepared.get(&target.label()) {
+            return Ok(*window);
+        }
+
+        let api = OllamaApi::new(self.http.clone(), &target.provider_config.base_url);
+        let installed = api.is_installed(&target.model).await
+            .map_err(|e| self.describe_error(target, e))?;
+        if !installed {
+            let prompt = format!(
+                "📥 Model '{}' is not installed on {}. Pull it now?",
+                target.model, target.provider_config.base_url
+            );
+            if !std::io::stdin().is_terminal() || !Utils::confirm(&prompt)? {
+                return Err(Self::model_not_installed_error(target));
             }
+            api.pull_with_progress(&target.model).await?;
+            eprintln!("✓ Pulled {}", target.model);
+        }
+
+        let window = api.show(&target.model).await
+            .map_err(|e| self.describe_error(target, e))?
+            .map(|info| info.context_window());
+        prepared.insert(target.label(), window);
+        Ok(window)
+    }
+
+    /// Context window an Ollama target reports, read-only: a model that isn't installed
+    /// or a server that can't be reached reports nothing, and nothing is pulled
+    async fn reported_window(&self, target: &Target) -> Option<usize> {
+        if !matches!(target.client, AiClientType::Ollama(_)) {
+            return None;
+        }
+        if let Some(window) = self.prepared.lock().await.get(&target.label()) {
+            return *window;
+        }
+
+        OllamaApi::new(self.http.clone(), &target.provider_config.base_url)
+            .show(&target.model).await
+            .ok()
+            .flatten()
+            .map(|info| info.context_window())
+    }
+
+    /// `options.num_ctx` for an Ollama target: the window its requests are budgeted for,
+    /// so `[context_windows]` entries are what the server allocates. `None` (the server's
+    /// own choice) until `prepare` has checked the target.
+    async fn num_ctx(&self, target: &Target) -> Option<usize> {
+        let reported = *self.prepared.lock().await.get(&target.label())?;
+        Some(self.context_window(&target.model, reported))
+    }
+
+    /// Turn a target's raw error into the message shown to the user
+    fn describe_error(&self, target: &Target, error: anyhow::Error) -> anyhow::Error {
+        let error = match target.client {
+            AiClientType::Ollama(_) => Self::handle_ollama_error(target, error),
+            _ => error,
         };
 
-        if let Some(choice) = response.choices.first() {
-            Ok(choice.message.content.clone().unwrap_or_default())
-        } else {
-            Err(anyhow!("No response from AI"))
+        // Some APIs echo the key they rejected
+        let message = format!("{:#}", error);
+        if !target.api_key.is_empty() && message.contains(&target.api_key) {
+            return anyhow!(message.replace(&target.api_key, MASKED_API_KEY));
         }
+        error
     }
 
-    pub async fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
-        let ai_messages: Vec<ChatCompletionMessage> = messages.iter()
+    fn build_request(model: &str, messages: &[ChatMessage], stream: bool) -> Result<ChatCompletionRequest> {
+        let mut builder = ChatCompletionRequestBuilder::default();
+        builder.model(model).messages(Self::to_completion_messages(messages));
+        if stream {
+            builder.stream(true);
+        }
+        builder.build().map_err(|e| anyhow!("Failed to build chat request: {}", e))
+    }
+
+    /// Like `ask`, printing the answer to stdout as it streams in
+    pub async fn ask_stream(&self, question: &str, context: &[ChatMessage]) -> Result<String> {
+        self.chat_stream(&self.question_messages(question, context)).await
+    }
+
+    /// Continue a conversation, printing the reply to stdout as it streams in.
+    /// The conversation system prompt is added unless `messages` starts with one.
+    ///
+    /// Returns the assembled reply; if the user presses Ctrl-C the partial text is returned.
+    pub async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<String> {
+        let mut messages = messages.to_vec();
+        if messages.first().is_none_or(|m| m.role != Role::System) {
+            messages.insert(0, ChatMessage::system(self.chat_system_prompt()));
+        }
+
+        // Only opening the stream is retried; once tokens are printed a failure is final
+        let stream = self.with_fallback(|target| {
+            let messages = &messages;
+            async move {
+                let request = Self::build_request(&target.model, messages, true)?;
+                self.open_stream(target, &request).await
+            }
+        }).await?;
+        streaming::print_token_stream(stream, &mut std::io::stdout()).await
+    }
+
+    async fn open_stream(&self, target: &Target, request: &ChatCompletionRequest) -> Result<TokenStream> {
+        match &target.client {
+            AiClientType::Ollama(client) => client.chat_stream(request, self.num_ctx(target).await).await,
+            AiClientType::OpenAi(client) => client.stream_chat_completions(request).await,
+            AiClientType::Anthropic(client) => client.stream_messages(request).await,
+            AiClientType::Gemini(client) => client.stream_generate_content(request).await,
+        }
+    }
+
+    fn to_completion_messages(messages: &[ChatMessage]) -> Vec<ChatCompletionMessage> {
+        messages.iter()
             .map(|msg| {
-                match msg.role.as_str() {
-                    "user" => ChatCompletionMessage::User(msg.content.clone().into()),
-                    "assistant" => ChatCompletionMessage::Assistant(msg.content.clone().into()),
-                    _ => ChatCompletionMessage::User(msg.content.clone().into()), // Default to user
+                match msg.role {
+                    Role::System => ChatCompletionMessage::System(msg.content.clone().into()),
+                    Role::User => ChatCompletionMessage::User(msg.content.clone().into()),
+                    Role::Assistant => ChatCompletionMessage::Assistant(msg.content.clone().into()),
                 }
             })
-            .collect();
+            .collect()
+    }
 
-        let request = ChatCompletionRequestBuilder::default()
-            .model(&self.command_config.model)
-            .messages(ai_messages)
-            .build()
-            .map_err(|e| anyhow!("Failed to build chat request: {}", e))?;
+    /// How much diff fits in one commit request: the smallest context window among the
+    /// command's providers, minus the reserved reply tokens and the prompt around the diff
+    pub async fn diff_budget(&self, context: &CommitContext, instructions: &str) -> DiffBudget {
+        let prompt = context.fill_prompt(&self.git_config.commit_prompt, "");
+        let overhead = tokens::count(&self.commit_system_prompt(instructions))
+            + tokens::count(&prompt)
+            + 2 * tokens::MESSAGE_OVERHEAD_TOKENS;
 
-        let response = match &self.client {
-            AiClientType::Ollama(client) => {
-                client.chat_completions(&request).await
-                    .map_err(|e| self.handle_ollama_error(e))?
-            }
-            AiClientType::OpenAi(client) => {
-                client.chat_completions(&request).await
-                    .map_err(|e| anyhow!("OpenAI API error: {}", e))?
-            }
-        };
+        self.content_budget(overhead, self.git_config.reserved_output_tokens).await
+    }
 
-        if let Some(choice) = response.choices.first() {
-            Ok(choice.message.content.clone().unwrap_or_default())
-        } else {
-            Err(anyhow!("No response from AI"))
+    /// How much text fits in one request next to `overhead` tokens of prompt, keeping
+    /// `reserved_output_tokens` for the reply; `max_diff_length` caps it in characters
+    pub async fn content_budget(&self, overhead: usize, reserved_output_tokens: usize) -> DiffBudget {
+        let window = self.min_context_window().await;
+        let max_tokens = tokens::content_tokens(window, overhead, reserved_output_tokens).max(MIN_DIFF_TOKENS);
+
+        let max_chars = self.targets.iter()
+            .filter_map(|target| target.provider_config.max_diff_length.or(self.git_config.max_diff_length))
+            .min();
+
+        DiffBudget { max_tokens, max_chars }
+    }
+
+    /// The smallest context window among the command's providers, so a request that fits
+    /// can go to any fallback
+    pub async fn min_context_window(&self) -> usize {
+        let mut window = None;
+        for (index, target) in self.targets.iter().enumerate() {
+            // The primary is about to be called, so it gets the full pre-flight check (a
+            // failure is reported when the request is sent); fallbacks that may never be
+            // used are only looked up, so budgeting never prompts for or starts a pull
+            let reported = if index == 0 {
+                self.prepare(target).await.ok().flatten()
+            } else {
+                self.reported_window(target).await
+            };
+            let target_window = self.context_window(&target.model, reported);
+            window = Some(window.map_or(target_window, |w: usize| w.min(target_window)));
+        }
+        window.unwrap_or(tokens::DEFAULT_CONTEXT_WINDOW)
+    }
+
+    fn context_window(&self, model: &str, reported: Option<usize>) -> usize {
+        match &self.full_config {
+            Some(config) => config.context_window(model, reported),
+            None => tokens::context_window(model, &Default::default(), reported),
         }
     }
 
-    pub async fn generate_commit_message(&self, diff: &str) -> Result<String> {
-        let prompt = self.git_config.commit_prompt.replace("{diff}", diff);
-        self.ask(&prompt).await
+    /// Generate a commit message for a diff; `instructions` (style rules, hints) go into the system message
+    pub async fn generate_commit_message(&self, diff: &str, context: &CommitContext, instructions: &str) -> Result<String> {
+        let prompt = context.fill_prompt(&self.git_config.commit_prompt, diff);
+        let raw = self.ask_with_system(&self.commit_system_prompt(instructions), &prompt).await?;
+        Ok(CommitMessage::sanitize(&raw))
+    }
+
+    /// Ask the model to fix specific problems in a commit message it produced
+    pub async fn repair_commit_message(&self, message: &str, violations: &[String], instructions: &str) -> Result<String> {
+        let problems: Vec<String> = violations.iter().map(|v| format!("- {}", v)).collect();
+        let prompt = format!(
+            "This git commit message has problems:\n\n{}\n\nProblems:\n{}\n\n\
+             Rewrite the commit message so that every problem is fixed, keeping its meaning. \
+             Reply only with the corrected commit message, without quotes, markdown or explanations.",
+            message,
+            problems.join("\n")
+        );
+        let raw = self.ask_with_system(&self.commit_system_prompt(instructions), &prompt).await?;
+        Ok(CommitMessage::sanitize(&raw))
     }
 
-    /// Summarize diff segments in parallel with controlled concurrency
+    /// Summarize diff segments in parallel with controlled concurrency.
+    /// A segment that still fails after retries and fallbacks is listed without a summary
+    /// instead of failing the whole commit.
     pub async fn summarize_diff_segments(&self, segments: Vec<DiffSegment>) -> Result<Vec<FileSummary>> {
-        let max_concurrency = self.git_config.max_concurrency;
+        let max_concurrency = self.git_config.max_concurrency.max(1);
         let timeout_duration = Duration::from_secs(self.git_config.segment_timeout_seconds);
-        let semaphore = Arc::new(Semaphore::new(max_concurrency));
+        let semaphore = Semaphore::new(max_concurrency);
 
         let total_segments = segments.len();
-        println!("Analyzing large diff in {} segments...", total_segments);
-
-        let mut tasks = Vec::new();
-        for (index, segment) in segments.into_iter().enumerate() {
-            let sem = semaphore.clone();
-            let client_type = match &self.client {
-                AiClientType::Ollama(client) => AiClientType::Ollama(client.clone()),
-                AiClientType::OpenAi(client) => AiClientType::OpenAi(client.clone()),
-            };
-            let model = self.command_config.model.clone();
-            
-            let task = async move {
-                let _permit = sem.acquire().await.map_err(|e| anyhow!("Semaphore error: {}", e))?;
-                
-                println!("Processing segment {}/{}...", index + 1, total_segments);
+        eprintln!("Analyzing large diff in {} segments...", total_segments);
+
+        let tasks = segments.iter().enumerate().map(|(index, segment)| {
+            let semaphore = &semaphore;
+            async move {
+                let _permit = semaphore.acquire().await.map_err(|e| anyhow!("Semaphore error: {}", e))?;
                 
-                let result = timeout(timeout_duration, async {
-                    Self::summarize_segment(&client_type, &model, &segment).await
-                }).await;
+                eprintln!("Processing segment {}/{}...", index + 1, total_segments);
+                self.summarize_segment(segment, timeout_duration).await
+            }
+        });
+        let results = futures::future::join_all(tasks).await;
 
-                match result {
-                    Ok(summary_result) => summary_result,
-                    Err(_) => Err(anyhow!("Request timeout after {}s", timeout_duration.as_secs())),
-                }
-            };
-            
-            tasks.push(task);
+        // Ctrl-C while a segment waits to be retried stops the commit, not just that segment
+        if results.iter().any(|result| result.as_ref().is_err_and(|e| e.is::<Interrupted>())) {
+            return Err(Interrupted.into());
+        }
+        if results.iter().all(|result| result.is_err()) {
+            if let Some(Err(e)) = results.into_iter().next() {
+                return Err(e);
+            }
+            return Ok(Vec::new());
         }
 
-        // Wait for all tasks to complete
         let mut all_summaries = Vec::new();
-        for task in tasks {
-            let segment_summaries = task.await?;
-            all_summaries.extend(segment_summaries);
+        for (index, (segment, result)) in segments.iter().zip(results).enumerate() {
+            match result {
+                Ok(segment_summaries) => all_summaries.extend(segment_summaries),
+                Err(e) => {
+                    eprintln!(
+                        "⚠️  Segment {}/{} could not be analyzed ({}); listing its files without a summary",
+                        index + 1,
+                        total_segments,
+                        retry::first_line(&e)
+                    );
+                    all_summaries.extend(Self::parse_file_summaries("", &segment.files)?);
+                }
+            }
         }
 
-        println!("Analysis complete. Generating commit message...");
+        eprintln!("Analysis complete. Generating commit message...");
         Ok(all_summaries)
     }
 
-    /// Summarize a single diff segment
-    async fn summarize_segment(
-        client: &AiClientType, 
-        model: &str, 
-        segment: &DiffSegment
-    ) -> Result<Vec<FileSummary>> {
+    /// Summarize a single diff segment; `attempt_timeout` limits each request
+    async fn summarize_segment(&self, segment: &DiffSegment, attempt_timeout: Duration) -> Result<Vec<FileSummary>> {
         let prompt = format!(
             "请简洁总结以下每个文件的变更(每个文件一行)：\n\n{}\n\n输出格式：\nfilename: 变更描述 (10字以内)\n\n示例：\nsrc/main.rs: 添加错误处理逻辑\nconfig.toml: 更新依赖版本",
             segment.content
         );
 
-        let request = ChatCompletionRequestBuilder::default()
-            .model(model)
-            .messages(vec![ChatCompletionMessage::User(prompt.into())])
-            .build()
-            .map_err(|e| anyhow!("Failed to build chat request: {}", e))?;
-
-        let response = match client {
-            AiClientType::Ollama(ollama_client) => {
-                ollama_client.chat_completions(&request).await
-                    .map_err(|e| anyhow!("Ollama API error: {}", e))?
-            }
-            AiClientType::OpenAi(openai_client) => {
-                openai_client.chat_completions(&request).await
-                    .map_err(|e| anyhow!("OpenAI API error: {}", e))?
-            }
-        };
-
-        let content = response.choices.first()
-            .and_then(|choice| choice.message.content.as_ref())
-            .ok_or_else(|| anyhow!("No response content from AI"))?;
+        let content = self.complete(&[ChatMessage::user(prompt)], Some(attempt_timeout)).await?;
 
         // Parse the response into FileSummary objects
-        Self::parse_file_summaries(content, &segment.files)
+        Self::parse_file_summaries(&content, &segment.files)
     }
 
     /// Parse AI response into FileSummary objects
@@ -251,11 +670,8 @@ impl AiClient {
     }
 
     /// Generate final commit message based on stats and file summaries
-    pub async fn generate_final_commit_message(&self, stats: &DiffStats, file_summaries: &[FileSummary]) -> Result<String> {
-        let stats_text = format!(
-            "{} files changed, {} insertions(+), {} deletions(-)",
-            stats.files_changed, stats.lines_added, stats.lines_deleted
-        );
+    pub async fn generate_final_commit_message(&self, context: &CommitContext, file_summaries: &[FileSummary], instructions: &str) -> Result<String> {
+        let stats_text = context.stats.summary();
 
         let mut file_details = String::new();
         for summary in file_summaries.iter().take(10) { // Limit to prevent overflow
@@ -266,130 +682,116 @@ impl AiClient {
             file_details.push_str(&format!("- ... and {} more files\n", file_summaries.len() - 10));
         }
 
+        let recent_commits = if context.recent_commits.is_empty() {
+            "(no commits yet)".to_string()
+        } else {
+            context.recent_commits.join("\n")
+        };
+
+        // The format rules come from the commit style in the system message
         let prompt = format!(
-            "基于以下信息生成commit message：\n\n统计摘要：\n{}\n\n文件变更详情：\n{}\n\n生成符合conventional commits格式的一行commit message。\n描述必须以小写字母开头，不超过72字符。",
-            stats_text, file_details
+            "基于以下信息生成commit message：\n\n当前分支：{}\n\n最近的commit（请沿用其风格和ticket前缀）：\n{}\n\n涉及的模块：{}\n\n统计摘要：\n{}\n\n文件变更详情：\n{}",
+            context.branch, recent_commits, context.scopes.join(", "), stats_text, file_details
         );
 
-        self.ask(&prompt).await
+        let raw = self.ask_with_system(&self.commit_system_prompt(instructions), &prompt).await?;
+        Ok(CommitMessage::sanitize(&raw))
     }
 
     pub async fn analyze_and_fix_error(&self, history_context: &str, user_prompt: &str) -> Result<String> {
-        let base_prompt = r#"You are an expert system administrator and developer that helps fix command line errors.
-
-Analyze the provided context to identify the root cause of errors and provide solutions.
-
-IMPORTANT: Pay attention to the "Analysis Type" field:
-- If it's "Shell startup error", focus on configuration file issues (.zshrc, .bashrc, .gitconfig, etc.)
-- If it's command execution error, focus on the failed command in the history
-
-Please follow this format:
-## Analysis
-[Brief explanation of what went wrong]
-
-## Root Cause  
-[The specific reason for the failure]
-
-## Solution
-[Step by step explanation of how to fix it]
+        let prompt = if user_prompt.is_empty() {
+            format!("Terminal History and Context:\n{}", history_context)
+        } else {
+            format!("Additional Context from User: {}\n\nTerminal History and Context:\n{}", 
+                   user_prompt, history_context)
+        };
 
-## Commands
-```bash
-# Commands to fix the issue (one per line)
-command1
-command2
-command3
-```
+        self.ask_with_system(self.system_prompt(DEFAULT_FIX_SYSTEM_PROMPT), &prompt).await
+    }
 
-The commands section should only contain the actual shell commands that need to be executed, one per line, without explanations or comments inside the code block.
+    /// Fold `transcript` (the oldest turns of a chat) into `previous`, the summary of
+    /// the turns before them
+    pub async fn summarize_conversation(&self, previous: Option<&str>, transcript: &str) -> Result<String> {
+        let prompt = match previous {
+            Some(previous) => format!("Summary so far:\n{}\n\nConversation that followed:\n{}", previous, transcript),
+            None => format!("Conversation:\n{}", transcript),
+        };
+        let summary = self.ask_with_system(CONVERSATION_SUMMARY_PROMPT, &prompt).await?;
+        Ok(summary.trim().to_string())
+    }
 
-For shell startup errors, common causes include:
-- Corrupted config files (.zshrc, .bashrc, .gitconfig)
-- Lock files that weren't cleaned up properly
-- Permission issues with config files
-- Path issues or missing dependencies
-- Syntax errors in shell configuration
+    /// The system message `chat_stream` sends when the conversation has none
+    pub fn chat_system_prompt(&self) -> &str {
+        self.system_prompt(DEFAULT_CHAT_SYSTEM_PROMPT)
+    }
 
-"#;
+    /// Health check: send a minimal request to the command's provider (no retries or
+    /// fallbacks) and return how long the reply took
+    pub async fn ping(&self) -> Result<Duration> {
+        let primary = &self.targets[0];
+        let request = Self::build_request(&primary.model, &[ChatMessage::user("ping")], false)?;
+        let started = std::time::Instant::now();
+        self.send_request(primary, &request).await
+            .map_err(|e| self.describe_error(primary, e))?;
+        Ok(started.elapsed())
+    }
 
-        let full_prompt = if user_prompt.is_empty() {
-            format!("{}\n\nTerminal History and Context:\n{}", base_prompt, history_context)
-        } else {
-            format!("{}\n\nAdditional Context from User: {}\n\nTerminal History and Context:\n{}", 
-                   base_prompt, user_prompt, history_context)
+    /// Models a provider offers: the installed models for Ollama, its model listing otherwise
+    pub async fn available_models(provider_name: &str, provider_config: &ProviderConfig) -> Result<Vec<String>> {
+        let http = reqwest::Client::new();
+        let api_key = Self::api_key(provider_name, provider_config)?;
+        let mut models = match Self::create_client(provider_name, provider_config, &api_key, &http)? {
+            AiClientType::Ollama(client) => {
+                client.list_models().await?
+                    .into_iter()
+                    .map(|model| model.name)
+                    .collect()
+            }
+            AiClientType::OpenAi(client) => client.list_models().await?,
+            AiClientType::Anthropic(client) => client.list_models().await?,
+            AiClientType::Gemini(client) => client.list_models().await?,
         };
+        models.sort();
+        Ok(models)
+    }
 
-        self.ask(&full_prompt).await
+    fn model_not_installed_error(target: &Target) -> anyhow::Error {
+        anyhow!(
+            "❌ Model '{}' is not installed.\n\n\
+             📥 To install this model, run:\n\
+             {}\n\
+             📦 Or install every configured model with `ai models pull`.\n\n\
+             💡 After installation, run your ai command again.\n\
+             🔗 Available models: https://ollama.com/library",
+            target.model,
+            Self::command_box(&format!("ollama pull {}", target.model))
+        )
     }
 
-    #[allow(dead_code)]
-    pub async fn is_available(&self) -> bool {
-        // Simple health check by trying to make a minimal request
-        let request = ChatCompletionRequestBuilder::default()
-            .model(&self.command_config.model)
-            .messages(vec![ChatCompletionMessage::User("hello".into())])
-            .build();
-        
-        if let Ok(req) = request {
-            match &self.client {
-                AiClientType::Ollama(client) => {
-                    client.chat_completions(&req).await.is_ok()
-                }
-                AiClientType::OpenAi(client) => {
-                    client.chat_completions(&req).await.is_ok()
-                }
-            }
-        } else {
-            false
-        }
+    /// A shell command between two horizontal rules
+    fn command_box(command: &str) -> String {
+        let total_width = std::cmp::max(41, command.len() + 4);
+        let inner_width = total_width - 2; // 减去左右边框
+        let padded_command = format!(" {:<width$}", command, width = inner_width - 1); // -1 因为前面有一个空格
+        format!("{}\n{}\n{}", "─".repeat(total_width), padded_command, "─".repeat(total_width))
     }
 
-    fn handle_ollama_error(&self, error: impl std::fmt::Display) -> anyhow::Error {
+    fn handle_ollama_error(target: &Target, error: impl std::fmt::Display) -> anyhow::Error {
         let error_msg = error.to_string().to_lowercase();
         
-        // Check for common model not found patterns
-        if error_msg.contains("model") && (
-            error_msg.contains("not found") ||
-            error_msg.contains("not available") ||
-            error_msg.contains("pull") ||
-            error_msg.contains("does not exist") ||
-            error_msg.contains("404")
-        ) {
-            let model_name = &self.command_config.model;
-            {
-                let command = format!("ollama pull {}", model_name);
-                let total_width = std::cmp::max(41, command.len() + 4);
-                let inner_width = total_width - 2; // 减去左右边框
-                let padded_command = format!(" {:<width$}", command, width = inner_width - 1); // -1 因为前面有一个空格
-                
-                anyhow!(
-                    "❌ Model '{}' is not installed.\n\n\
-                     📥 To install this model, run:\n\
-                     {}\n\
-                     {}\n\
-                     {}\n\n\
-                     💡 After installation, run your ai command again.\n\
-                     🔗 Available models: https://ollama.com/library",
-                    model_name,
-                    "─".repeat(total_width),
-                    padded_command,
-                    "─".repeat(total_width)
-                )
-            }
-        } else if error_msg.contains("connection") || 
-                  error_msg.contains("refused") || 
-                  error_msg.contains("connect") ||
-                  error_msg.contains("no such host") ||
-                  error_msg.contains("network is unreachable") ||
-                  error_msg.contains("connection reset") ||
-                  (error_msg.trim() == "unknown error:" || error_msg.trim().is_empty()) {
+        if error_msg.contains("connection") || 
+           error_msg.contains("refused") || 
+           error_msg.contains("connect") ||
+           error_msg.contains("no such host") ||
+           error_msg.contains("network is unreachable") ||
+           error_msg.contains("connection reset") ||
+           (error_msg.trim() == "unknown error:" || error_msg.trim().is_empty()) {
             
             // Check if this might be because Ollama is not installed
             if error_msg.contains("connection refused") || 
                error_msg.contains("no such host") || 
                error_msg.trim() == "unknown error:" ||
                error_msg.trim().is_empty() {
-                let models_info = self.get_all_configured_models();
                 anyhow!(
                     "❌ Cannot connect to Ollama server.\n\n\
                      🔍 This might be because:\n\
@@ -405,10 +807,13 @@ For shell startup errors, common causes include:
                      │ ollama serve                            │\n\
                      └─────────────────────────────────────────┘\n\n\
                      📦 After Ollama is running, install your configured models:\n\
-                     {}\n\
+                     ┌─────────────────────────────────────────┐\n\
+                     │ ai models pull                          │\n\
+                     └─────────────────────────────────────────┘\n\
                      📍 Server URL: {}\n\
-                     💡 Follow terminal instructions after installation",
-                    models_info, self.provider_config.base_url
+                     💡 Follow terminal instructions after installation\n\
+                     🩺 Run `ai doctor` to check the rest of your setup",
+                    target.provider_config.base_url
                 )
             } else {
                 anyhow!(
@@ -418,83 +823,117 @@ For shell startup errors, common causes include:
                      │ ollama serve                            │\n\
                      └─────────────────────────────────────────┘\n\n\
                      📍 Server URL: {}\n\
-                     🔗 Install Ollama: https://ollama.com/download",
-                    self.provider_config.base_url
+                     🔗 Install Ollama: https://ollama.com/download\n\
+                     🩺 Run `ai doctor` to check the rest of your setup",
+                    target.provider_config.base_url
                 )
             }
         } else {
             anyhow!("Failed to get AI response: {}", error)
         }
     }
-
-    fn get_all_configured_models(&self) -> String {
-        if let Some(config) = &self.full_config {
-            let mut models = std::collections::HashSet::new();
-            
-            // Collect all unique models from commands
-            models.insert(&config.commands.git_operations.model);
-            models.insert(&config.commands.conversation.model);
-            models.insert(&config.commands.error_analysis.model);
-            
-            let mut models_vec: Vec<_> = models.into_iter().collect();
-            models_vec.sort();
-            
-            let mut result = String::new();
-            for (i, model) in models_vec.iter().enumerate() {
-                let command = format!("ollama pull {}", model);
-                let total_width = std::cmp::max(41, command.len() + 4);
-                let inner_width = total_width - 2; // 减去左右边框
-                let padded_command = format!(" {:<width$}", command, width = inner_width - 1); // -1 因为前面有一个空格
-                
-                result.push_str(&format!(
-                    "     {}\n\
-                     {}\n\
-                     {}",
-                    "─".repeat(total_width),
-                    padded_command,
-                    "─".repeat(total_width)
-                ));
-                if i < models_vec.len() - 1 {
-                    result.push('\n');
-                }
-            }
-            result
-        } else {
-            let command = format!("ollama pull {}", &self.command_config.model);
-            let total_width = std::cmp::max(41, command.len() + 4);
-            let inner_width = total_width - 2; // 减去左右边框
-            let padded_command = format!(" {:<width$}", command, width = inner_width - 1); // -1 因为前面有一个空格
-            
-            format!(
-                "     {}\n\
-                 {}\n\
-                 {}",
-                "─".repeat(total_width),
-                padded_command,
-                "─".repeat(total_width)
-            )
-        }
-    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
-    pub role: String, // "user" or "assistant"
+    pub role: Role,
     pub content: String,
+    /// Kept in the chat context even when older turns are summarized or dropped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+/// Who a chat message is from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
 }
 
 impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+            pinned: false,
+        }
+    }
+
     pub fn user(content: impl Into<String>) -> Self {
         Self {
-            role: "user".to_string(),
+            role: Role::User,
             content: content.into(),
+            pinned: false,
         }
     }
 
     pub fn assistant(content: impl Into<String>) -> Self {
         Self {
-            role: "assistant".to_string(),
+            role: Role::Assistant,
             content: content.into(),
+            pinned: false,
+        }
+    }
+
+    /// Estimated tokens the message takes in a request
+    pub fn tokens(&self) -> usize {
+        tokens::count(&self.content) + tokens::MESSAGE_OVERHEAD_TOKENS
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FallbackTarget;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(server: &MockServer) -> ProviderConfig {
+        ProviderConfig {
+            kind: Some(ProviderKind::OpenaiCompatible),
+            api_key: "test-key".to_string(),
+            base_url: server.uri(),
+            max_diff_length: None,
+            max_tokens: None,
+            api_key_env: None,
+            api_key_file: None,
+            api_key_cmd: None,
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn falls_back_when_retry_after_exceeds_the_max_backoff() {
+        let primary = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "3600"))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        let backup = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Hello" } }],
+            })))
+            .expect(1)
+            .mount(&backup)
+            .await;
+
+        let mut config = Config::default();
+        config.providers.insert("primary".to_string(), provider(&primary));
+        config.providers.insert("backup".to_string(), provider(&backup));
+        let command_config = CommandAiConfig {
+            provider: "primary".to_string(),
+            model: "test-model".to_string(),
+            system_prompt: None,
+            fallback: vec![FallbackTarget { provider: "backup".to_string(), model: None }],
+        };
+
+        let client = AiClient::new_with_full_config(provider(&primary), command_config, config.git.clone(), config).unwrap();
+        assert_eq!(client.chat(&[ChatMessage::user("Hi")]).await.unwrap(), "Hello");
+        assert_eq!(client.usage().provider, "backup");
+    }
+}
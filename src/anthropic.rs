@@ -0,0 +1,296 @@
+use crate::retry::HttpError;
+use crate::streaming::{self, StreamEvent, TokenStream};
+use ai::chat_completions::ChatCompletionRequest;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+
+/// Output limit sent when the provider sets no `max_tokens` (the API requires one)
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Client for the Anthropic Messages API (`POST /v1/messages`)
+#[derive(Clone)]
+pub struct AnthropicClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    max_tokens: u32,
+}
+
+/// Non-streaming Messages API response
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// `{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}`
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl AnthropicClient {
+    pub fn new(http: reqwest::Client, base_url: &str, api_key: &str, max_tokens: Option<u32>) -> Self {
+        let base_url = if base_url.is_empty() { DEFAULT_ANTHROPIC_BASE_URL } else { base_url };
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            max_tokens: max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        }
+    }
+
+    /// Send a request and return the text of the reply
+    pub async fn messages(&self, request: &ChatCompletionRequest) -> Result<String> {
+        let response = self.send(request, false).await?;
+        let body: MessagesResponse = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Anthropic: {}", e))?;
+
+        Ok(body.content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect())
+    }
+
+    /// Send a request and stream the reply's text deltas
+    pub async fn stream_messages(&self, request: &ChatCompletionRequest) -> Result<TokenStream> {
+        let response = self.send(request, true).await?;
+
+        // `event:` lines repeat the type that is also in the data payload
+        Ok(streaming::sse_tokens(response.bytes_stream(), parse_stream_event))
+    }
+
+    /// IDs of the models the API key can use (`GET /v1/models`)
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.http
+            .get(format!("{}/v1/models?limit=1000", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Anthropic API error: {:#}", anyhow::Error::from(e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpError::new(status.as_u16(), &headers, api_error(Some(status.as_u16()), &text)).into());
+        }
+
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Anthropic: {}", e))?;
+        Ok(body["data"]
+            .as_array()
+            .map(|models| models.iter().filter_map(|m| m["id"].as_str().map(String::from)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn send(&self, request: &ChatCompletionRequest, stream: bool) -> Result<reqwest::Response> {
+        let response = self.http
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&self.request_body(request, stream)?)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Anthropic API error: {:#}", anyhow::Error::from(e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpError::new(status.as_u16(), &headers, api_error(Some(status.as_u16()), &text)).into());
+        }
+
+        Ok(response)
+    }
+
+    /// Convert an OpenAI-style request: system messages move to the top-level `system` field
+    fn request_body(&self, request: &ChatCompletionRequest, stream: bool) -> Result<Value> {
+        let request = serde_json::to_value(request)?;
+
+        let mut system = Vec::new();
+        let mut messages = Vec::new();
+        for message in request["messages"].as_array().cloned().unwrap_or_default() {
+            let role = message["role"].as_str().unwrap_or("user");
+            let text = message_text(&message["content"]);
+            match role {
+                "system" | "developer" => system.push(text),
+                "assistant" => messages.push(json!({ "role": "assistant", "content": text })),
+                _ => messages.push(json!({ "role": "user", "content": text })),
+            }
+        }
+
+        let mut body = json!({
+            "model": request["model"],
+            "max_tokens": self.max_tokens,
+            "messages": messages,
+        });
+        if !system.is_empty() {
+            body["system"] = Value::String(system.join("\n\n"));
+        }
+        if let Some(temperature) = request.get("temperature").filter(|t| !t.is_null()) {
+            body["temperature"] = temperature.clone();
+        }
+        if stream {
+            body["stream"] = Value::Bool(true);
+        }
+        Ok(body)
+    }
+}
+
+/// Text of an OpenAI-style message content: a string or a list of text parts
+fn message_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Turn an Anthropic error response into a readable error
+fn api_error(status: Option<u16>, body: &str) -> anyhow::Error {
+    let status = status.map(|s| format!(" {}", s)).unwrap_or_default();
+    match serde_json::from_str::<ErrorBody>(body) {
+        Ok(body) => anyhow!("Anthropic API error ({}{}): {}", body.error.kind, status, body.error.message),
+        Err(_) => anyhow!("Anthropic API error{}: {}", status, body.trim()),
+    }
+}
+
+/// Parse the data payload of one server-sent event from a streaming response
+pub fn parse_stream_event(data: &str) -> Result<StreamEvent> {
+    let event: Value = serde_json::from_str(data)
+        .map_err(|e| anyhow!("Invalid stream event from Anthropic: {} ({})", e, data))?;
+
+    match event["type"].as_str().unwrap_or_default() {
+        "content_block_delta" if event["delta"]["type"] == "text_delta" => {
+            Ok(StreamEvent::Token(event["delta"]["text"].as_str().unwrap_or_default().to_string()))
+        }
+        "message_stop" => Ok(StreamEvent::Done),
+        "error" => Err(api_error(None, data)),
+        _ => Ok(StreamEvent::Skip),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use futures::StreamExt;
+    use ai::chat_completions::{ChatCompletionMessage, ChatCompletionRequestBuilder};
+    use std::time::Duration;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequestBuilder::default()
+            .model("claude-sonnet-4-5")
+            .messages(vec![
+                ChatCompletionMessage::System("Be brief.".to_string().into()),
+                ChatCompletionMessage::User("Hi".to_string().into()),
+            ])
+            .build()
+            .unwrap()
+    }
+
+    fn client(server: &MockServer) -> AnthropicClient {
+        AnthropicClient::new(reqwest::Client::new(), &server.uri(), "test-key", None)
+    }
+
+    #[test]
+    fn parses_stream_events() {
+        assert_eq!(
+            parse_stream_event(r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Bon"}}"#).unwrap(),
+            StreamEvent::Token("Bon".to_string())
+        );
+        assert_eq!(parse_stream_event(r#"{"type":"message_start","message":{}}"#).unwrap(), StreamEvent::Skip);
+        assert_eq!(parse_stream_event(r#"{"type":"message_stop"}"#).unwrap(), StreamEvent::Done);
+
+        let error = parse_stream_event(r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Anthropic API error (overloaded_error): Overloaded");
+    }
+
+    #[tokio::test]
+    async fn sends_key_and_version_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(header("anthropic-version", ANTHROPIC_VERSION))
+            .and(body_partial_json(json!({ "system": "Be brief.", "max_tokens": DEFAULT_MAX_TOKENS })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "Hello" }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(client(&server).messages(&request()).await.unwrap(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn maps_error_responses_to_http_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(429)
+                .insert_header("retry-after", "2")
+                .set_body_json(json!({ "type": "error", "error": { "type": "rate_limit_error", "message": "slow down" } })))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).messages(&request()).await.unwrap_err();
+        assert!(retry::is_transient(&error));
+        let http = error.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(http.status, 429);
+        assert_eq!(http.retry_after, Some(Duration::from_secs(2)));
+        assert_eq!(error.to_string(), "Anthropic API error (rate_limit_error 429): slow down");
+    }
+
+    #[tokio::test]
+    async fn streams_text_deltas_until_an_error_event() {
+        let server = MockServer::start().await;
+        let body = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Bon\"}}\n\n",
+            "event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let mut stream = client(&server).stream_messages(&request()).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), "Bon");
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("overloaded_error"));
+    }
+}
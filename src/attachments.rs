@@ -0,0 +1,322 @@
+use crate::ai_client::ChatMessage;
+use crate::git_ops::GitOperations;
+use crate::tokens::{self, DiffBudget};
+use anyhow::{anyhow, Context, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Bytes checked for a NUL to tell binary files apart
+const BINARY_SNIFF_LENGTH: usize = 8000;
+
+/// A file, command output or diff sent to the model along with the question
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    /// A path, `$ command` or `git diff`
+    pub label: String,
+    pub content: String,
+    /// Cut to fit the size limit
+    pub truncated: bool,
+    /// Keep the last lines rather than the first when cut, as errors come at the end of output
+    pub keep_end: bool,
+}
+
+impl Attachment {
+    pub fn new(label: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            content: content.into(),
+            truncated: false,
+            keep_end: false,
+        }
+    }
+
+    /// Output of a command or a pipe, which keeps its end when cut
+    pub fn output(label: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            keep_end: true,
+            ..Self::new(label, content)
+        }
+    }
+
+    pub fn tokens(&self) -> usize {
+        tokens::count(&self.content)
+    }
+
+    /// The attachment as it appears in the prompt
+    fn to_prompt(&self) -> String {
+        let note = if self.truncated { " (truncated)" } else { "" };
+        format!("### {}{}\n```\n{}\n```\n", self.label, note, self.content.trim_end())
+    }
+}
+
+/// All attachments as one system message
+pub fn message(attachments: &[Attachment]) -> Option<ChatMessage> {
+    if attachments.is_empty() {
+        return None;
+    }
+    let mut content = String::from("The user attached these files and command outputs:\n\n");
+    for attachment in attachments {
+        content.push_str(&attachment.to_prompt());
+        content.push('\n');
+    }
+    Some(ChatMessage::system(content.trim_end()))
+}
+
+/// Remove the attachment named `label`, or all of them when `label` is empty (`/drop`);
+/// returns how many were removed
+pub fn remove(attachments: &mut Vec<Attachment>, label: &str) -> usize {
+    let before = attachments.len();
+    attachments.retain(|attachment| !label.is_empty() && attachment.label != label);
+    before - attachments.len()
+}
+
+/// The attachments `fit` kept, and what it left out
+#[derive(Default)]
+pub struct Fitted {
+    pub attachments: Vec<Attachment>,
+    /// Labels of attachments that didn't fit at all
+    pub skipped: Vec<String>,
+}
+
+/// Keep attachments in order while they fit in `budget`; the first one that doesn't is cut
+/// to the room that is left and the rest are skipped
+pub fn fit(attachments: Vec<Attachment>, budget: DiffBudget) -> Fitted {
+    let mut fitted = Fitted::default();
+    let (mut chars, mut used) = (0, 0);
+    for mut attachment in attachments {
+        let tokens = attachment.tokens();
+        if budget.fits_counted(chars + attachment.content.len(), used + tokens) {
+            chars += attachment.content.len();
+            used += tokens;
+            fitted.attachments.push(attachment);
+            continue;
+        }
+
+        let room_tokens = budget.max_tokens.saturating_sub(used);
+        let room_chars = budget.max_chars.map_or(usize::MAX, |max| max.saturating_sub(chars));
+        let cut = if attachment.keep_end {
+            cut_last_lines(&attachment.content, room_tokens, room_chars)
+        } else {
+            cut_lines(&attachment.content, room_tokens, room_chars)
+        };
+        if cut.trim().is_empty() {
+            fitted.skipped.push(attachment.label);
+            continue;
+        }
+        chars += cut.len();
+        used += tokens::count(cut);
+        attachment.content = cut.to_string();
+        attachment.truncated = true;
+        fitted.attachments.push(attachment);
+    }
+    fitted
+}
+
+/// The longest prefix of whole lines of `text` within both limits
+fn cut_lines(text: &str, max_tokens: usize, max_chars: usize) -> &str {
+    let text = if text.len() > max_chars {
+        let end = text.as_bytes()[..max_chars].iter().rposition(|&byte| byte == b'\n').map_or(0, |i| i + 1);
+        &text[..end]
+    } else {
+        text
+    };
+    tokens::truncate_lines(text, max_tokens)
+}
+
+/// The longest suffix of whole lines of `text` within both limits
+fn cut_last_lines(text: &str, max_tokens: usize, max_chars: usize) -> &str {
+    let (mut start, mut used) = (text.len(), 0);
+    for line in text.split_inclusive('\n').rev() {
+        used += tokens::count(line);
+        if used > max_tokens || text.len() - start + line.len() > max_chars {
+            break;
+        }
+        start -= line.len();
+    }
+    &text[start..]
+}
+
+/// Files named by `pattern`: a file, every file under a directory, or a glob such as
+/// `src/**/*.rs`. Directories and globs skip hidden files and whatever .gitignore excludes.
+pub fn find_files(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut walk = if path.is_dir() {
+        WalkBuilder::new(path)
+    } else {
+        let overrides = OverrideBuilder::new(".")
+            .add(pattern)
+            .with_context(|| format!("Invalid glob: {}", pattern))?
+            .build()?;
+        let mut walk = WalkBuilder::new(".");
+        walk.overrides(overrides);
+        walk
+    };
+    // .gitignore counts even outside a git repository
+    walk.require_git(false);
+
+    let mut files: Vec<PathBuf> = walk.build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(|entry| entry.into_path())
+        .map(|path| path.strip_prefix("./").map(Path::to_path_buf).unwrap_or(path))
+        .collect();
+    if files.is_empty() {
+        return Err(anyhow!("No files match {}", pattern));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Read `paths` as attachments; binary and unreadable files are skipped with a warning
+pub fn read_files(paths: &[PathBuf]) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+    for path in paths {
+        match read_text(path) {
+            Ok(content) => attachments.push(Attachment::new(path.display().to_string(), content)),
+            Err(e) => eprintln!("⚠️  Skipping {}: {}", path.display(), e),
+        }
+    }
+    attachments
+}
+
+fn read_text(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path)?;
+    if bytes.iter().take(BINARY_SNIFF_LENGTH).any(|&byte| byte == 0) {
+        return Err(anyhow!("binary file"));
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("not UTF-8 text"))
+}
+
+/// Run `command` in the shell and attach what it printed, with its exit status
+pub fn command_output(command: &str) -> Result<Attachment> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .with_context(|| format!("Failed to run `{}`", command))?;
+
+    let mut content = String::from_utf8_lossy(&output.stdout).into_owned();
+    content.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        let status = output.status.code().map_or_else(|| "a signal".to_string(), |code| format!("status {}", code));
+        content.push_str(&format!("\n(exited with {})", status));
+    }
+    Ok(Attachment::output(format!("$ {}", command), content))
+}
+
+/// The staged and unstaged changes of the current repository, whichever exist
+pub fn git_diffs() -> Result<Vec<Attachment>> {
+    if !GitOperations::is_git_repo() {
+        return Err(anyhow!("Not in a git repository"));
+    }
+    let diffs = [
+        ("git diff --staged", GitOperations::get_staged_diff()?),
+        ("git diff", GitOperations::get_unstaged_diff()?),
+    ];
+    Ok(diffs.into_iter()
+        .filter(|(_, diff)| !diff.trim().is_empty())
+        .map(|(label, diff)| Attachment::new(label, diff))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_tokens: usize, max_chars: Option<usize>) -> DiffBudget {
+        DiffBudget { max_tokens, max_chars }
+    }
+
+    #[test]
+    fn fit_cuts_the_first_attachment_over_the_budget_and_skips_the_rest() {
+        let small = Attachment::new("a.txt", "alpha\n");
+        let large = Attachment::new("b.txt", "one two three\nfour five six\nseven eight nine\n");
+        let late = Attachment::new("c.txt", "gamma\n");
+        let max_tokens = small.tokens() + tokens::count("one two three\nfour five six\n");
+
+        let fitted = fit(vec![small, large, late], budget(max_tokens, None));
+        let kept: Vec<(&str, &str, bool)> = fitted.attachments.iter()
+            .map(|attachment| (attachment.label.as_str(), attachment.content.as_str(), attachment.truncated))
+            .collect();
+        assert_eq!(kept, vec![("a.txt", "alpha\n", false), ("b.txt", "one two three\nfour five six\n", true)]);
+        assert_eq!(fitted.skipped, vec!["c.txt"]);
+        assert!(fitted.attachments.iter().map(Attachment::tokens).sum::<usize>() <= max_tokens);
+    }
+
+    #[test]
+    fn fit_keeps_the_end_of_command_output() {
+        let output = Attachment::output("$ cargo build", "Compiling ai\nwarning: unused\nerror: linker failed\n");
+        let max_tokens = tokens::count("error: linker failed\n");
+
+        let fitted = fit(vec![output], budget(max_tokens, None));
+        assert_eq!(fitted.attachments[0].content, "error: linker failed\n");
+        assert!(fitted.attachments[0].truncated);
+        assert!(message(&fitted.attachments).unwrap().content.contains("### $ cargo build (truncated)"));
+    }
+
+    #[test]
+    fn fit_counts_characters_across_attachments() {
+        let first = Attachment::new("first.txt", "12345\n");
+        let second = Attachment::new("second.txt", "abc\ndef\n");
+
+        // Six characters are left after the first file: one line of the second
+        let fitted = fit(vec![first, second], budget(10_000, Some(12)));
+        assert_eq!(fitted.attachments[1].content, "abc\n");
+        assert!(fitted.skipped.is_empty());
+
+        // Not even a line: skipped rather than sent empty
+        let fitted = fit(vec![Attachment::new("second.txt", "abc\ndef\n")], budget(10_000, Some(3)));
+        assert!(fitted.attachments.is_empty());
+        assert_eq!(fitted.skipped, vec!["second.txt"]);
+    }
+
+    #[test]
+    fn cuts_non_ascii_text_at_a_line() {
+        let text = "第一行：你好\n第二行：世界\n第三行：再见\n";
+        // Inside the second "第", two bytes into the three-byte character
+        let max_chars = text.find("第二").unwrap() + 2;
+        assert!(!text.is_char_boundary(max_chars));
+        assert_eq!(cut_lines(text, 10_000, max_chars), "第一行：你好\n");
+
+        // Inside the last line, counting from the end
+        let max_chars = text.len() - text.find("第三").unwrap() + 1;
+        assert_eq!(cut_last_lines(text, 10_000, max_chars), "第三行：再见\n");
+        assert_eq!(cut_last_lines(text, 10_000, max_chars - 2), "");
+    }
+
+    #[test]
+    fn cut_last_lines_stays_within_the_token_budget() {
+        let text = "first\nsecond\nthird\nfourth\n";
+        let last_two = tokens::count("third\nfourth\n");
+        assert_eq!(cut_last_lines(text, last_two, usize::MAX), "third\nfourth\n");
+        assert_eq!(cut_last_lines(text, last_two - 1, usize::MAX), "fourth\n");
+        assert_eq!(cut_last_lines(text, 0, usize::MAX), "");
+        assert_eq!(cut_last_lines(text, 10_000, usize::MAX), text);
+    }
+
+    #[test]
+    fn remove_drops_one_attachment_by_name_or_all_of_them() {
+        let mut attached = vec![
+            Attachment::new("src/main.rs", "fn main() {}"),
+            Attachment::output("$ cargo test", "ok"),
+            Attachment::new("git diff", "+added"),
+        ];
+
+        assert_eq!(remove(&mut attached, "$ cargo test"), 1);
+        let labels: Vec<&str> = attached.iter().map(|attachment| attachment.label.as_str()).collect();
+        assert_eq!(labels, vec!["src/main.rs", "git diff"]);
+
+        assert_eq!(remove(&mut attached, "README.md"), 0);
+        assert_eq!(attached.len(), 2);
+
+        assert_eq!(remove(&mut attached, ""), 2);
+        assert!(attached.is_empty());
+        assert_eq!(message(&attached).map(|message| message.content), None);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::ai_client::{ChatMessage, Role};
+use crate::tokens;
+use serde::{Deserialize, Serialize};
+
+/// Share of the budget a conversation is compacted down to, so it isn't compacted
+/// again on the very next turn
+const COMPACT_TARGET: f64 = 0.6;
+
+/// Which turns of a chat are still sent to the model, and what stands in for the rest
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatContext {
+    /// Summary of the compacted turns (none when they were dropped)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Messages before this index are only sent when pinned
+    #[serde(default)]
+    pub compacted: usize,
+}
+
+/// Estimated tokens of each part of the next request
+pub struct ContextUsage {
+    /// The system prompt and attachments
+    pub prelude: usize,
+    pub summary: usize,
+    pub pinned: usize,
+    pub pinned_messages: usize,
+    pub recent: usize,
+    pub recent_messages: usize,
+}
+
+impl ContextUsage {
+    pub fn total(&self) -> usize {
+        self.prelude + self.summary + self.pinned + self.recent
+    }
+}
+
+impl ChatContext {
+    pub fn is_empty(&self) -> bool {
+        self.summary.is_none() && self.compacted == 0
+    }
+
+    /// Whether `messages[index]` is part of the next request
+    pub fn is_sent(&self, messages: &[ChatMessage], index: usize) -> bool {
+        index >= self.compacted || messages[index].pinned
+    }
+
+    /// The messages sent for the next request: the `prelude` (system prompt and attachments),
+    /// the summary of the compacted turns, then every message that is pinned or more recent
+    pub fn request(&self, prelude: &[ChatMessage], messages: &[ChatMessage]) -> Vec<ChatMessage> {
+        let mut request = prelude.to_vec();
+        if let Some(summary) = &self.summary {
+            request.push(ChatMessage::system(summary_message(summary)));
+        }
+        request.extend(
+            messages.iter()
+                .enumerate()
+                .filter(|(index, _)| self.is_sent(messages, *index))
+                .map(|(_, message)| message.clone()),
+        );
+        request
+    }
+
+    pub fn usage(&self, prelude: &[ChatMessage], messages: &[ChatMessage]) -> ContextUsage {
+        let mut usage = ContextUsage {
+            prelude: prelude.iter().map(ChatMessage::tokens).sum(),
+            summary: self.summary.as_deref()
+                .map_or(0, |summary| ChatMessage::system(summary_message(summary)).tokens()),
+            pinned: 0,
+            pinned_messages: 0,
+            recent: 0,
+            recent_messages: 0,
+        };
+        for (index, message) in messages.iter().enumerate() {
+            if index >= self.compacted {
+                usage.recent += message.tokens();
+                usage.recent_messages += 1;
+            } else if message.pinned {
+                usage.pinned += message.tokens();
+                usage.pinned_messages += 1;
+            }
+        }
+        usage
+    }
+
+    /// Where the compacted turns should end when the next request is over `budget` tokens:
+    /// far enough to bring it well under the budget, always at the start of a user turn and
+    /// never past the latest message. `None` when the request fits or nothing can be compacted.
+    pub fn compaction_point(&self, prelude: &[ChatMessage], messages: &[ChatMessage], budget: usize) -> Option<usize> {
+        let mut remaining = self.usage(prelude, messages).total();
+        if remaining <= budget {
+            return None;
+        }
+
+        let target = (budget as f64 * COMPACT_TARGET) as usize;
+        let last = messages.len().saturating_sub(1);
+        let mut end = self.compacted;
+        for (index, message) in messages.iter().enumerate().take(last).skip(self.compacted) {
+            if remaining <= target && message.role == Role::User {
+                break;
+            }
+            if !message.pinned {
+                remaining -= message.tokens();
+            }
+            end = index + 1;
+        }
+        (end > self.compacted).then_some(end)
+    }
+
+    /// The unpinned messages between the compacted turns and `end`, as text to summarize
+    pub fn transcript(&self, messages: &[ChatMessage], end: usize) -> String {
+        messages[self.compacted..end]
+            .iter()
+            .filter(|message| !message.pinned)
+            .map(|message| format!("{}: {}", speaker(message.role), message.content.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Record that the turns before `end` are now compacted, replacing the summary
+    /// (`None` keeps the previous one when the turns were dropped)
+    pub fn compact(&mut self, end: usize, summary: Option<String>) {
+        self.compacted = end;
+        if summary.is_some() {
+            self.summary = summary;
+        }
+    }
+}
+
+fn summary_message(summary: &str) -> String {
+    format!("Summary of the earlier conversation:\n{}", summary)
+}
+
+/// How a role is named in transcripts and listings
+pub fn speaker(role: Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::User => "You",
+        Role::Assistant => "AI",
+    }
+}
+
+/// Usable tokens for a chat request in a `window`-token context, minus the reply
+pub fn budget(window: usize, reserved_output_tokens: usize) -> usize {
+    tokens::usable(window).saturating_sub(reserved_output_tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Alternating user and assistant turns of the same size, starting with the user
+    fn conversation(turns: usize) -> Vec<ChatMessage> {
+        (0..turns)
+            .map(|index| {
+                let content = format!("turn {} {}", index, "word ".repeat(100));
+                if index % 2 == 0 { ChatMessage::user(content) } else { ChatMessage::assistant(content) }
+            })
+            .collect()
+    }
+
+    fn prelude() -> Vec<ChatMessage> {
+        vec![ChatMessage::system("You are a helpful assistant.")]
+    }
+
+    /// A budget of the prelude plus `messages` turns
+    fn budget_for(messages: usize) -> usize {
+        prelude()[0].tokens() + messages * conversation(1)[0].tokens()
+    }
+
+    #[test]
+    fn a_request_that_fits_is_left_alone() {
+        let messages = conversation(7);
+        let context = ChatContext::default();
+        assert_eq!(context.compaction_point(&prelude(), &messages, budget_for(7)), None);
+        assert!(context.compaction_point(&prelude(), &messages, budget_for(7) - 1).is_some());
+    }
+
+    #[test]
+    fn compacts_whole_turns_to_well_under_the_budget() {
+        let messages = conversation(7);
+        let context = ChatContext::default();
+
+        // One turn too many: three go to get under 60% of the budget, and the fourth
+        // (an answer) follows so the kept part starts with a question
+        let end = context.compaction_point(&prelude(), &messages, budget_for(6)).unwrap();
+        assert_eq!(end, 4);
+        assert_eq!(messages[end].role, Role::User);
+        assert!(context.usage(&prelude(), &messages[end..]).total() <= (budget_for(6) as f64 * COMPACT_TARGET) as usize);
+    }
+
+    #[test]
+    fn never_compacts_the_latest_message() {
+        let context = ChatContext::default();
+        // The question being asked can't be compacted, however large it is
+        assert_eq!(context.compaction_point(&prelude(), &conversation(1), 1), None);
+        assert_eq!(context.compaction_point(&prelude(), &conversation(3), 1), Some(2));
+
+        // Nothing left between the compacted turns and the latest message
+        let context = ChatContext { summary: Some("earlier".to_string()), compacted: 2 };
+        assert_eq!(context.compaction_point(&prelude(), &conversation(3), 1), None);
+    }
+
+    #[test]
+    fn pinned_messages_are_kept_and_do_not_count_as_freed() {
+        let mut messages = conversation(7);
+        messages[1].pinned = true;
+        let mut context = ChatContext::default();
+
+        // The pinned answer stays in the request, so two more turns go instead
+        let end = context.compaction_point(&prelude(), &messages, budget_for(6)).unwrap();
+        assert_eq!(end, 6);
+        assert!(!context.transcript(&messages, end).contains("turn 1 "));
+        assert!(context.transcript(&messages, end).contains("turn 5 "));
+
+        context.compact(end, Some("They talked about words.".to_string()));
+        let request = context.request(&prelude(), &messages);
+        let contents: Vec<&str> = request.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(request.len(), 4);
+        assert_eq!(contents[0], "You are a helpful assistant.");
+        assert_eq!(contents[1], "Summary of the earlier conversation:\nThey talked about words.");
+        assert!(contents[2].starts_with("turn 1 "));
+        assert!(contents[3].starts_with("turn 6 "));
+    }
+
+    #[test]
+    fn dropping_sends_no_summary_but_keeps_the_system_prompt() {
+        let messages = conversation(7);
+        let mut context = ChatContext::default();
+
+        let end = context.compaction_point(&prelude(), &messages, budget_for(6)).unwrap();
+        context.compact(end, None);
+
+        let request = context.request(&prelude(), &messages);
+        assert_eq!(request.len(), 1 + messages.len() - end);
+        assert_eq!(request[0].role, Role::System);
+        assert_eq!(request[0].content, "You are a helpful assistant.");
+        assert!(request[1].content.starts_with("turn 4 "));
+        assert_eq!(context.summary, None);
+    }
+
+    #[test]
+    fn dropping_later_keeps_the_earlier_summary() {
+        let mut context = ChatContext::default();
+        context.compact(2, Some("First summary".to_string()));
+        context.compact(4, None);
+        assert_eq!(context.compacted, 4);
+        assert_eq!(context.summary.as_deref(), Some("First summary"));
+
+        context.compact(6, Some("Second summary".to_string()));
+        assert_eq!(context.summary.as_deref(), Some("Second summary"));
+    }
+}
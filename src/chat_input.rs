@@ -0,0 +1,206 @@
+use crate::config::{self, Config};
+use anyhow::Result;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, CompletionType, Context, Editor, EventHandler, Helper, KeyCode, KeyEvent, Modifiers};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Lines kept in `~/.ai.chat_history`
+const HISTORY_SIZE: usize = 1000;
+
+/// Starts and ends a message that spans several lines
+pub const MULTILINE_QUOTE: &str = "\"\"\"";
+
+/// What the argument of a slash command completes to
+pub enum ArgumentCompletion {
+    None,
+    Options(&'static [&'static str]),
+    /// Names and ids of saved chat sessions
+    Session,
+    /// Names of the current attachments
+    Attachment,
+    Path,
+}
+
+/// A command available at the chat prompt
+pub struct SlashCommand {
+    pub name: &'static str,
+    /// Shown after the name in `/help`
+    pub arguments: &'static str,
+    pub description: &'static str,
+    pub completion: ArgumentCompletion,
+}
+
+/// The chat prompt: line editing, history across sessions, multi-line input and completion
+pub struct ChatInput {
+    editor: Editor<ChatHelper, FileHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl ChatInput {
+    pub fn new(commands: &'static [SlashCommand]) -> Result<Self> {
+        let config = rustyline::Config::builder()
+            .max_history_size(HISTORY_SIZE)?
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(ChatHelper {
+            commands,
+            sessions: Vec::new(),
+            attachments: Vec::new(),
+            files: FilenameCompleter::new(),
+        }));
+        // Alt-Enter starts a new line instead of sending
+        editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), EventHandler::Simple(Cmd::Newline));
+
+        let history_path = Config::chat_history_path().ok();
+        if let Some(path) = history_path.as_ref().filter(|path| path.exists()) {
+            if let Err(e) = editor.load_history(path) {
+                eprintln!("⚠️  Could not read the input history {}: {}", path.display(), e);
+            }
+        }
+        // Everything typed goes in here, so it is created private before the first line
+        let history_path = history_path.filter(|path| match create_private(path) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("⚠️  Could not save the input history {}: {}", path.display(), e);
+                false
+            }
+        });
+        Ok(Self { editor, history_path })
+    }
+
+    /// The next message, or `None` when the user leaves with Ctrl-C or Ctrl-D
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>> {
+        // The editor only draws the prompt on a terminal
+        let terminal = std::io::stdin().is_terminal();
+        if !terminal {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+        }
+
+        let line = match tokio::task::block_in_place(|| self.editor.readline(prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
+                if !terminal {
+                    println!();
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if !line.trim().is_empty() {
+            self.editor.add_history_entry(line.as_str())?;
+            // Appended right away so chats running side by side share their history
+            if let Some(path) = &self.history_path {
+                if let Err(e) = self.editor.append_history(path) {
+                    eprintln!("⚠️  Could not save the input history {}: {}", path.display(), e);
+                    self.history_path = None;
+                }
+            }
+        }
+        Ok(Some(unquote(line.trim()).to_string()))
+    }
+
+    /// Session names and ids offered after `/load`
+    pub fn set_sessions(&mut self, sessions: Vec<String>) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.sessions = sessions;
+        }
+    }
+
+    /// Attachment names offered after `/drop`
+    pub fn set_attachments(&mut self, attachments: Vec<String>) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.attachments = attachments;
+        }
+    }
+}
+
+/// The text between the quotes of a `"""` block
+fn unquote(input: &str) -> &str {
+    input.strip_prefix(MULTILINE_QUOTE)
+        .and_then(|rest| rest.strip_suffix(MULTILINE_QUOTE))
+        .map_or(input, str::trim)
+}
+
+/// Create `path` if it is missing and make it readable by its owner only
+fn create_private(path: &Path) -> std::io::Result<()> {
+    std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+    config::make_private(path)
+}
+
+struct ChatHelper {
+    commands: &'static [SlashCommand],
+    sessions: Vec<String>,
+    attachments: Vec<String>,
+    files: FilenameCompleter,
+}
+
+impl Completer for ChatHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let typed = &line[..pos];
+        if !typed.starts_with('/') || typed.contains('\n') {
+            return Ok((0, Vec::new()));
+        }
+
+        let Some((name, argument)) = typed.split_once(' ') else {
+            let names = self.commands.iter()
+                .map(|command| command.name)
+                .filter(|name| name.starts_with(typed));
+            return Ok((0, candidates(names)));
+        };
+
+        let Some(command) = self.commands.iter().find(|command| command.name == name) else {
+            return Ok((0, Vec::new()));
+        };
+        let options: Vec<&str> = match command.completion {
+            ArgumentCompletion::None => Vec::new(),
+            ArgumentCompletion::Options(options) => options.to_vec(),
+            ArgumentCompletion::Session => self.sessions.iter().map(String::as_str).collect(),
+            ArgumentCompletion::Attachment => self.attachments.iter().map(String::as_str).collect(),
+            ArgumentCompletion::Path => return self.files.complete(line, pos, ctx),
+        };
+        let prefix = argument.trim_start();
+        let matching = options.into_iter().filter(|option| option.starts_with(prefix));
+        Ok((pos - prefix.len(), candidates(matching)))
+    }
+}
+
+fn candidates<'a>(words: impl Iterator<Item = &'a str>) -> Vec<Pair> {
+    words
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: word.to_string(),
+        })
+        .collect()
+}
+
+/// Keep reading lines while a `"""` block is open
+impl Validator for ChatHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if ctx.input().matches(MULTILINE_QUOTE).count() % 2 == 1 {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Hinter for ChatHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ChatHelper {}
+
+impl Helper for ChatHelper {}
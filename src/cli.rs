@@ -1,57 +1,233 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crate::output::OutputFormat;
+use crate::sessions::ExportFormat;
+use clap_complete::Shell;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "ai")]
 #[command(about = "Personal AI CLI tool for chat, git operations, and project publishing")]
 #[command(version)]
 pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+/// Per-invocation configuration overrides (the config file is left untouched)
+#[derive(Args, Clone, Default)]
+pub struct GlobalArgs {
+    /// Provider to use instead of the configured one (e.g. ollama, deepseek)
+    #[arg(long, global = true, env = "AI_PROVIDER")]
+    pub provider: Option<String>,
+    /// Model to use instead of the configured one
+    #[arg(long, global = true, env = "AI_MODEL")]
+    pub model: Option<String>,
+    /// Base URL for the selected provider
+    #[arg(long, global = true)]
+    pub base_url: Option<String>,
+    /// Config file to use instead of ~/.ai.conf.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Print the result of ask, commit, push, fix, doctor, models list or config get as one JSON object
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Display help information
-    Help,
     /// Ask AI a single question
     Ask {
-        /// The question to ask
-        question: String,
+        /// Attach a file, or every file of a directory or glob (repeatable)
+        #[arg(short = 'f', long = "file", value_name = "PATH")]
+        files: Vec<String>,
+        /// Don't print status messages, only the answer and warnings
+        #[arg(short, long)]
+        quiet: bool,
+        /// Print only the answer, exactly as the model returned it, once it is complete (implies --quiet)
+        #[arg(long)]
+        raw: bool,
+        /// The question to ask; text piped to stdin is attached to it, or is the question when none is given.
+        /// Start with `-` to wait for slow piped input
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        question: Vec<String>,
     },
     /// Start interactive chat session
-    Chat,
+    Chat {
+        /// Continue a saved session (by id or name); without one, the latest in this directory
+        #[arg(long, value_name = "ID", num_args = 0..=1, conflicts_with_all = ["list", "export"])]
+        resume: Option<Option<String>>,
+        /// List saved chat sessions
+        #[arg(long, conflicts_with = "export")]
+        list: bool,
+        /// Print a saved session (by id or name) as Markdown or JSON
+        #[arg(long, value_name = "ID")]
+        export: Option<String>,
+        /// Write the export to a file; *.json files get JSON
+        #[arg(short, long, value_name = "PATH", requires = "export")]
+        output: Option<PathBuf>,
+        /// Export format (defaults to the output file's extension, else Markdown)
+        #[arg(long = "export-format", value_name = "FORMAT", requires = "export")]
+        export_format: Option<ExportFormat>,
+    },
     /// Commit changes with AI-generated message
     Commit {
         /// Stage all files before committing
+        #[arg(short, long)]
         all: bool,
+        /// Generate and print the message without staging or committing
+        #[arg(long)]
+        dry_run: bool,
+        /// Commit without reviewing the generated message
+        #[arg(short, long)]
+        yes: bool,
     },
     /// Push changes to remote repository
     Push {
         /// Force push changes
+        #[arg(short, long)]
         force: bool,
+        /// Show what would be pushed without pushing
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Publish project to appropriate registry
-    Publish,
+    Publish {
+        /// Run the registry's dry-run check instead of publishing
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Analyze terminal history and fix the last error
     Fix {
         /// Additional context or specific error description
-        context: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        context: Vec<String>,
+        /// Print the suggested commands instead of copying them to the clipboard
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// View, edit and validate the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// List, pull, ping and switch the configured models
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommands,
+    },
+    /// Check the config, providers, git and other tools ai relies on
+    Doctor {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Generate shell completion scripts
+    Completions {
+        /// Shell to generate completions for (bash, zsh, fish, ...)
+        shell: Shell,
     },
 }
 
 impl Commands {
-    #[allow(dead_code)]
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s {
-            "help" => Some(Commands::Help),
-            "ask" => None, // Requires argument
-            "chat" => Some(Commands::Chat),
-            "commit" => Some(Commands::Commit { all: false }),
-            "push" => Some(Commands::Push { force: false }),
-            "publish" => Some(Commands::Publish),
-            "fix" => Some(Commands::Fix { context: None }),
-            _ => None,
-        }
+    /// Whether the command can print its result with `--format json`
+    pub fn supports_json(&self) -> bool {
+        matches!(
+            self,
+            Commands::Ask { .. } | Commands::Commit { .. } | Commands::Push { .. } | Commands::Fix { .. } | Commands::Doctor { .. }
+                | Commands::Models { command: ModelsCommands::List }
+                | Commands::Config { command: ConfigCommands::Get { .. } }
+        )
     }
 }
 
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective configuration (user config merged with the project .ai.toml)
+    Show {
+        /// Show which file each value comes from
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Print the effective value of a dotted key (e.g. git.commit_style)
+    Get {
+        key: String,
+    },
+    /// Set a dotted key, keeping the file's comments and formatting
+    Set {
+        key: String,
+        /// TOML value (72, true, ["a", "b"]); anything else is stored as a string
+        value: String,
+        /// Write to the repository's .ai.toml instead of the user config
+        #[arg(long)]
+        project: bool,
+    },
+    /// Remove a dotted key from the config file
+    Unset {
+        key: String,
+        /// Edit the repository's .ai.toml instead of the user config
+        #[arg(long)]
+        project: bool,
+    },
+    /// Open the config in $EDITOR; it is only saved once it validates
+    Edit {
+        /// Edit the repository's .ai.toml instead of the user config
+        #[arg(long)]
+        project: bool,
+    },
+    /// Check the config files for errors
+    Validate,
+    /// Print the config file path
+    Path {
+        /// Print the repository's .ai.toml path instead
+        #[arg(long)]
+        project: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ModelsCommands {
+    /// Show each command's provider and model and whether it is available
+    List,
+    /// Download missing Ollama models (all configured ones, or MODEL)
+    Pull {
+        model: Option<String>,
+    },
+    /// Measure the response time of each configured provider and model
+    Ping,
+    /// Choose the model a command type uses
+    Set {
+        command_type: CommandType,
+        /// Model to use with the command's current provider (or --provider); picked
+        /// interactively from the providers' available models when omitted
+        model: Option<String>,
+        /// Write to the repository's .ai.toml instead of the user config
+        #[arg(long)]
+        project: bool,
+    },
+}
+
+/// A `[commands.*]` table
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CommandType {
+    /// commit, push, publish
+    #[value(name = "git_operations", alias = "git")]
+    GitOperations,
+    /// ask, chat
+    #[value(name = "conversation", alias = "chat")]
+    Conversation,
+    /// fix
+    #[value(name = "error_analysis", alias = "fix")]
+    ErrorAnalysis,
+}
+
+impl CommandType {
+    pub fn key(self) -> &'static str {
+        match self {
+            CommandType::GitOperations => "git_operations",
+            CommandType::Conversation => "conversation",
+            CommandType::ErrorAnalysis => "error_analysis",
+        }
+    }
+}
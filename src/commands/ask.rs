@@ -1,16 +1,203 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use crate::config::Config;
-use crate::ai_client::AiClient;
+use crate::ai_client::{AiClient, ChatMessage, ModelUsage};
+use crate::attachments::{self, Attachment};
+use crate::output::{self, OutputFormat};
+use crate::sessions;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{IsTerminal, Read, Write};
+use std::time::{Duration, Instant};
 
-pub async fn handle_ask(question: &str) -> Result<()> {
-    println!("Loading configuration...");
+/// Characters of the question echoed in the "Asking AI" status line
+const QUESTION_PREVIEW_LENGTH: usize = 80;
+
+/// How long a question waits for piped input that hasn't arrived yet, e.g. from a `cat`
+/// that is still starting; `ai ask - "question"` waits as long as it takes
+const STDIN_GRACE_PERIOD: Duration = Duration::from_millis(200);
+
+/// Options for `ai ask`
+#[derive(Debug, Clone, Default)]
+pub struct AskOptions {
+    /// Files, directories or globs to attach
+    pub files: Vec<String>,
+    /// Hide status messages
+    pub quiet: bool,
+    /// Print only the finished answer, without status messages or warnings
+    pub raw: bool,
+    /// JSON waits for the whole answer, like `raw`, and wraps it in a result object
+    pub format: OutputFormat,
+}
+
+/// What `ai ask --format json` prints
+#[derive(Serialize)]
+struct AskReport {
+    answer: String,
+    /// Labels of what was sent along with the question, `(truncated)` when cut
+    attachments: Vec<String>,
+    model: ModelUsage,
+    elapsed_ms: u64,
+}
+
+impl AskOptions {
+    /// Status messages go to stderr so stdout only carries the answer
+    fn status(&self, message: &str) {
+        if !self.quiet && !self.raw {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+pub async fn handle_ask(question: &str, options: AskOptions) -> Result<()> {
+    let started = Instant::now();
+    // `cat error.log | ai ask "what broke?"` asks about the log; piped text alone is the question.
+    // With a question, stdin is only read if it is ready, so an idle pipe (cron, CI) can't
+    // hang the command; `-` waits for it, for input that takes a while to arrive
+    let (question, wait_for_stdin) = strip_stdin_marker(question);
+    let piped = if question.is_empty() || wait_for_stdin || stdin_ready(STDIN_GRACE_PERIOD) {
+        read_piped_stdin()?
+    } else {
+        None
+    };
+    let (question, piped) = match (question, piped) {
+        ("", None) => return Err(anyhow!("No question given. Pass it as arguments or pipe it in: echo \"question\" | ai ask")),
+        ("", Some(piped)) => (piped.trim().to_string(), None),
+        (question, piped) => (question.to_string(), piped),
+    };
+
+    options.status("Loading configuration...");
     let config = Config::load()?;
     let (provider_config, command_config) = config.get_conversation_ai_config()?;
     let client = AiClient::new_with_full_config(provider_config.clone(), command_config.clone(), config.git.clone(), config.clone())?;
-    
-    println!("Asking AI: {}", question);
-    let response = client.ask(question).await?;
-    println!("{}", response);
-    
+
+    let mut attached: Vec<Attachment> = piped.map(|piped| Attachment::output("stdin", piped)).into_iter().collect();
+    if !options.files.is_empty() {
+        let mut paths = Vec::new();
+        for pattern in &options.files {
+            paths.extend(attachments::find_files(pattern)?);
+        }
+        let mut seen = HashSet::new();
+        paths.retain(|path| seen.insert(path.clone()));
+        attached.extend(attachments::read_files(&paths));
+    }
+
+    let mut context = Vec::new();
+    let mut attached_labels = Vec::new();
+    if !attached.is_empty() {
+        // Attachments get whatever the window has left after the prompt and the question
+        let overhead = ChatMessage::system(client.chat_system_prompt()).tokens() + ChatMessage::user(&question).tokens();
+        let budget = client.content_budget(overhead, config.chat.reserved_output_tokens).await;
+        let fitted = attachments::fit(attached, budget);
+        if !options.raw {
+            for attachment in fitted.attachments.iter().filter(|attachment| attachment.truncated) {
+                eprintln!("⚠️  {} was truncated to fit the context window", attachment.label);
+            }
+            if !fitted.skipped.is_empty() {
+                eprintln!("⚠️  Not attached, the context window is full: {}", fitted.skipped.join(", "));
+            }
+        }
+        attached_labels = fitted.attachments.iter()
+            .map(|attachment| if attachment.truncated { format!("{} (truncated)", attachment.label) } else { attachment.label.clone() })
+            .collect();
+        options.status(&format!("📎 Attached {}", attached_labels.join(", ")));
+        context.extend(attachments::message(&fitted.attachments));
+    }
+
+    options.status(&format!("Asking AI: {}", sessions::first_line(&question, QUESTION_PREVIEW_LENGTH)));
+    if options.format.is_json() {
+        let answer = client.ask(&question, &context).await?;
+        output::print_json(&AskReport {
+            answer,
+            attachments: attached_labels,
+            model: client.usage(),
+            elapsed_ms: output::elapsed_ms(started),
+        })?;
+    } else if options.raw {
+        let answer = client.ask(&question, &context).await?;
+        let mut stdout = std::io::stdout();
+        stdout.write_all(answer.as_bytes())?;
+        stdout.flush()?;
+    } else {
+        client.ask_stream(&question, &context).await?;
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The question without a leading `-` word, and whether there was one
+fn strip_stdin_marker(question: &str) -> (&str, bool) {
+    let question = question.trim();
+    match question.strip_prefix('-') {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => (rest.trim_start(), true),
+        _ => (question, false),
+    }
+}
+
+/// Whether reading stdin would not block for longer than `wait`: it has data, has been
+/// closed, or is a file
+#[cfg(unix)]
+fn stdin_ready(wait: Duration) -> bool {
+    is_readable(libc::STDIN_FILENO, wait)
+}
+
+#[cfg(not(unix))]
+fn stdin_ready(_wait: Duration) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn is_readable(fd: std::os::fd::RawFd, wait: Duration) -> bool {
+    let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let timeout = wait.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    // SAFETY: `poll` points at one valid pollfd for the duration of the call
+    unsafe { libc::poll(&mut poll, 1, timeout) > 0 }
+}
+
+/// Everything piped to stdin, or `None` when it is a terminal or nothing was sent
+fn read_piped_stdin() -> Result<Option<String>> {
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    stdin.read_to_end(&mut bytes).context("Failed to read stdin")?;
+    let text = String::from_utf8_lossy(&bytes).into_owned();
+    Ok(Some(text).filter(|text| !text.trim().is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_leading_dash_asks_to_wait_for_stdin() {
+        assert_eq!(strip_stdin_marker("  what broke?  "), ("what broke?", false));
+        assert_eq!(strip_stdin_marker("- what broke?"), ("what broke?", true));
+        assert_eq!(strip_stdin_marker("-"), ("", true));
+        assert_eq!(strip_stdin_marker(""), ("", false));
+        // A question that merely starts with a dash
+        assert_eq!(strip_stdin_marker("-v prints what?"), ("-v prints what?", false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_idle_pipe_is_not_read_but_piped_input_is() {
+        use std::os::fd::AsRawFd;
+        let (reader, mut writer) = std::io::pipe().unwrap();
+
+        // `ai ask "question"` under cron or CI: the pipe stays open and nothing arrives
+        assert!(!is_readable(reader.as_raw_fd(), Duration::from_millis(10)));
+
+        // `cat error.log | ai ask "what broke?"`
+        writer.write_all(b"error: linker failed").unwrap();
+        assert!(is_readable(reader.as_raw_fd(), Duration::ZERO));
+
+        // A closed pipe reads as its end straight away
+        drop(writer);
+        let mut reader = reader;
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "error: linker failed");
+        assert!(is_readable(reader.as_raw_fd(), Duration::ZERO));
+    }
+}
@@ -1,106 +1,590 @@
-use anyhow::Result;
-use crate::config::Config;
-use crate::ai_client::{AiClient, ChatMessage};
-use super::{handle_commit, handle_push, handle_publish};
+use anyhow::{anyhow, Result};
+use crate::config::{Config, ContextStrategy};
+use crate::ai_client::{AiClient, ChatMessage, Role};
+use crate::attachments::{self, Attachment};
+use crate::chat_context::{self, ChatContext};
+use crate::chat_input::{ArgumentCompletion, ChatInput, SlashCommand};
+use crate::output::OutputFormat;
+use crate::retry;
+use crate::sessions::{self, ChatSession, ExportFormat, SessionStore};
+use crate::tokens;
+use crate::utils::Utils;
+use super::{handle_commit, handle_push, handle_publish, CommitOptions};
+use std::path::{Path, PathBuf};
 
-pub async fn handle_chat() -> Result<()> {
-    println!("Starting chat session... (type /exit or /quit to leave)");
-    println!("Available commands: /help, /commit, /push, /publish, /exit, /quit");
-    println!();
-    
+/// Sessions shown by `/load` without a name
+const RECENT_SESSIONS: usize = 10;
+
+/// Characters of each message shown by `/context` and `/pin`
+const PREVIEW_LENGTH: usize = 60;
+
+/// Commands listed by `/help` and completed with Tab
+const SLASH_COMMANDS: &[SlashCommand] = &[
+    SlashCommand { name: "/help", arguments: "", description: "Show this help message", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/context", arguments: "", description: "Show how much of the model's context window the conversation uses", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/pin", arguments: "[n]", description: "Always keep message n (default: the latest) in the context", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/unpin", arguments: "[n]", description: "Unpin message n, or every message", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/add", arguments: "[path|glob]", description: "Attach files (a directory or glob skips .gitignore'd files); without a path, list attachments", completion: ArgumentCompletion::Path },
+    SlashCommand { name: "/run", arguments: "<command>", description: "Run a shell command and attach its output", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/diff", arguments: "", description: "Attach the staged and unstaged git changes", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/drop", arguments: "[name]", description: "Remove an attachment, or all of them", completion: ArgumentCompletion::Attachment },
+    SlashCommand { name: "/save", arguments: "<name>", description: "Name this session so it can be loaded by name", completion: ArgumentCompletion::Session },
+    SlashCommand { name: "/load", arguments: "[name|id]", description: "Switch to a saved session (without a name, list this directory's sessions)", completion: ArgumentCompletion::Session },
+    SlashCommand { name: "/export", arguments: "[path]", description: "Write this session to a Markdown file (or JSON for *.json)", completion: ArgumentCompletion::Path },
+    SlashCommand { name: "/commit", arguments: "[--all]", description: "Commit changes with AI-generated message (--all stages all changes first)", completion: ArgumentCompletion::Options(&["--all"]) },
+    SlashCommand { name: "/push", arguments: "[--force]", description: "Push changes to remote repository", completion: ArgumentCompletion::Options(&["--force"]) },
+    SlashCommand { name: "/publish", arguments: "", description: "Publish project to appropriate registry", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/exit", arguments: "", description: "Exit the chat session", completion: ArgumentCompletion::None },
+    SlashCommand { name: "/quit", arguments: "", description: "Exit the chat session", completion: ArgumentCompletion::None },
+];
+
+/// Which conversation `ai chat` starts with
+pub enum Resume {
+    New,
+    /// The most recent session started in the current directory
+    Latest,
+    /// A session by id or name
+    Session(String),
+}
+
+pub async fn handle_chat(resume: Resume) -> Result<()> {
     let config = Config::load()?;
     let (provider_config, command_config) = config.get_conversation_ai_config()?;
     let client = AiClient::new_with_full_config(provider_config.clone(), command_config.clone(), config.git.clone(), config.clone())?;
-    
-    let mut conversation: Vec<ChatMessage> = Vec::new();
-    
+    let model = format!("{}/{}", command_config.provider, command_config.model);
+    let working_dir = Utils::current_dir()?;
+
+    // Chatting still works when sessions can't be stored
+    let store = match SessionStore::open() {
+        Ok(store) => Some(store),
+        Err(e) => {
+            eprintln!("⚠️  Chat sessions won't be saved: {}", e);
+            None
+        }
+    };
+
+    let mut session = match (&resume, &store) {
+        (Resume::New, _) => ChatSession::new(&working_dir, &model),
+        (_, None) => return Err(anyhow!("Cannot resume: chat sessions are unavailable")),
+        (Resume::Latest, Some(store)) => match store.latest_in(&working_dir)? {
+            Some(session) => session,
+            None => {
+                println!("No previous chat session in {}; starting a new one.", working_dir);
+                ChatSession::new(&working_dir, &model)
+            }
+        },
+        (Resume::Session(key), Some(store)) => store.find(key)?,
+    };
+
+    let mut editor = ChatInput::new(SLASH_COMMANDS)?;
+    if let Some(store) = &store {
+        editor.set_sessions(session_keys(store));
+    }
+
+    println!("Starting chat session... (type /exit or /quit to leave)");
+    let names: Vec<&str> = SLASH_COMMANDS.iter().map(|command| command.name).collect();
+    println!("Available commands: {}", names.join(", "));
+    println!("Start and end a message with {} to write several lines (or press Alt-Enter for a new line).", crate::chat_input::MULTILINE_QUOTE);
+    if !session.messages.is_empty() {
+        print_resumed(&session);
+    }
+    println!();
+
+    let window = client.min_context_window().await;
+    let budget = chat_context::budget(window, config.chat.reserved_output_tokens);
+
+    let mut conversation: Vec<ChatMessage> = session.messages.clone();
+    // Sent with every request until dropped; not saved with the session
+    let mut attached: Vec<Attachment> = Vec::new();
+
     loop {
-        print!("You: ");
         use std::io::{self, Write};
-        io::stdout().flush()?;
-        
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim();
-        
+        let Some(line) = editor.read("You: ")? else {
+            // Ctrl-C or Ctrl-D
+            println!("Goodbye!");
+            break;
+        };
+        let input = line.as_str();
+
         if input.is_empty() {
             continue;
         }
-        
+
         // Handle slash commands
         if input.starts_with('/') {
-            match input {
-                "/exit" | "/quit" => {
+            let (command, argument) = input
+                .split_once(char::is_whitespace)
+                .map_or((input, ""), |(command, argument)| (command, argument.trim()));
+
+            match (command, argument) {
+                ("/exit" | "/quit", _) => {
                     println!("Goodbye!");
                     break;
                 }
-                "/help" => {
+                ("/help", _) => {
                     show_chat_help();
-                    continue;
                 }
-                "/commit" => {
-                    if let Err(e) = handle_commit(false).await {
+                ("/context", _) => {
+                    print_context(&client, &config, &session.context, &attached, &conversation, window);
+                }
+                ("/pin", argument) => {
+                    match message_index(&conversation, argument) {
+                        Ok(index) => {
+                            conversation[index].pinned = true;
+                            println!("📌 Pinned message {}: {}", index + 1, sessions::first_line(&conversation[index].content, PREVIEW_LENGTH));
+                            save_session(&store, &mut session, &conversation);
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/unpin", "") => {
+                    let pinned = conversation.iter().filter(|message| message.pinned).count();
+                    conversation.iter_mut().for_each(|message| message.pinned = false);
+                    println!("✓ Unpinned {} message(s)", pinned);
+                    save_session(&store, &mut session, &conversation);
+                }
+                ("/unpin", argument) => {
+                    match message_index(&conversation, argument) {
+                        Ok(index) => {
+                            conversation[index].pinned = false;
+                            println!("✓ Unpinned message {}", index + 1);
+                            save_session(&store, &mut session, &conversation);
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/add", "") => {
+                    print_attachments(&attached);
+                }
+                ("/add", pattern) => {
+                    match attachments::find_files(pattern) {
+                        Ok(paths) => {
+                            let files = attachments::read_files(&paths);
+                            attach(&client, &config, &mut attached, files, &session.context, &conversation).await;
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/run", "") => {
+                    println!("Usage: /run <command>");
+                }
+                ("/run", command) => {
+                    match attachments::command_output(command) {
+                        Ok(output) => {
+                            println!("{}", output.content.trim_end());
+                            attach(&client, &config, &mut attached, vec![output], &session.context, &conversation).await;
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/diff", _) => {
+                    match attachments::git_diffs() {
+                        Ok(diffs) if diffs.is_empty() => println!("No changes to attach."),
+                        Ok(diffs) => attach(&client, &config, &mut attached, diffs, &session.context, &conversation).await,
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/drop", "") => {
+                    println!("✓ Dropped {} attachment(s)", attachments::remove(&mut attached, ""));
+                }
+                ("/drop", label) => {
+                    if attachments::remove(&mut attached, label) > 0 {
+                        println!("✓ Dropped {}", label);
+                    } else {
+                        println!("Error: nothing named {} is attached; /add lists the attachments", label);
+                    }
+                }
+                ("/save", "") => {
+                    println!("Usage: /save <name>");
+                }
+                ("/save", name) => {
+                    match &store {
+                        Some(store) => {
+                            session.messages = conversation.clone();
+                            session.touch();
+                            match store.rename(&mut session, name) {
+                                Ok(()) => {
+                                    println!("✓ Saved session as '{}'", name);
+                                    editor.set_sessions(session_keys(store));
+                                }
+                                Err(e) => println!("Error: {}", e),
+                            }
+                        }
+                        None => println!("Error: chat sessions are unavailable"),
+                    }
+                }
+                ("/load", key) => {
+                    match &store {
+                        Some(store) if key.is_empty() => {
+                            if let Err(e) = print_sessions(store, Some(&working_dir), RECENT_SESSIONS) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        Some(store) => match store.find(key) {
+                            Ok(loaded) => {
+                                session = loaded;
+                                conversation = session.messages.clone();
+                                print_resumed(&session);
+                            }
+                            Err(e) => println!("Error: {}", e),
+                        },
+                        None => println!("Error: chat sessions are unavailable"),
+                    }
+                }
+                ("/export", path) => {
+                    session.messages = conversation.clone();
+                    let path = if path.is_empty() {
+                        PathBuf::from(format!("chat-{}.md", session.id))
+                    } else {
+                        PathBuf::from(path)
+                    };
+                    match export_to_file(&session, &path, ExportFormat::from_path(&path)) {
+                        Ok(()) => println!("✓ Exported to {}", path.display()),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ("/commit", "") => {
+                    if let Err(e) = handle_commit(CommitOptions::default()).await {
                         println!("Error: {}", e);
                     }
-                    continue;
                 }
-                "/commit all" => {
-                    if let Err(e) = handle_commit(true).await {
+                ("/commit", "all" | "--all") => {
+                    if let Err(e) = handle_commit(CommitOptions::all()).await {
                         println!("Error: {}", e);
                     }
-                    continue;
                 }
-                "/push" => {
-                    if let Err(e) = handle_push(false).await {
+                ("/push", "") => {
+                    if let Err(e) = handle_push(false, false, OutputFormat::Text).await {
                         println!("Error: {}", e);
                     }
-                    continue;
                 }
-                "/push force" => {
-                    if let Err(e) = handle_push(true).await {
+                ("/push", "force" | "--force") => {
+                    if let Err(e) = handle_push(true, false, OutputFormat::Text).await {
                         println!("Error: {}", e);
                     }
-                    continue;
                 }
-                "/publish" => {
-                    if let Err(e) = handle_publish().await {
+                ("/publish", "") => {
+                    if let Err(e) = handle_publish(false).await {
                         println!("Error: {}", e);
                     }
-                    continue;
                 }
                 _ => {
                     println!("Unknown command: {}. Type /help for available commands.", input);
-                    continue;
                 }
             }
+            editor.set_attachments(attached.iter().map(|attachment| attachment.label.clone()).collect());
+            continue;
         }
-        
+
         // Add user message to conversation
         conversation.push(ChatMessage::user(input));
-        
-        // Get AI response
-        match client.chat(&conversation).await {
-            Ok(response) => {
-                println!("AI: {}", response);
-                // Add AI response to conversation
-                conversation.push(ChatMessage::assistant(response));
-            }
+        let prelude = prelude(&client, &attached);
+        fit_context(&client, &config, &mut session.context, &prelude, &conversation, budget).await;
+        let request = session.context.request(&prelude, &conversation);
+
+        // Stream AI response
+        print!("AI: ");
+        io::stdout().flush()?;
+        let reply = match client.chat_stream(&request).await {
+            Ok(response) => Some(response),
             Err(e) => {
                 println!("Error getting AI response: {}", e);
+                None
+            }
+        };
+        record_reply(&mut conversation, reply);
+
+        // Saved after every turn so a crash loses at most the reply in flight
+        save_session(&store, &mut session, &conversation);
+    }
+
+    if !conversation.is_empty() && store.is_some() {
+        println!("Resume this chat with: ai chat --resume {}", session.name.as_deref().unwrap_or(&session.id));
+    }
+
+    Ok(())
+}
+
+/// Add the reply to the conversation. Without one (an error, or Ctrl-C before the first
+/// token) the unanswered question is removed as well: providers reject empty assistant
+/// turns, and a saved session holding one would fail on every later request.
+fn record_reply(conversation: &mut Vec<ChatMessage>, reply: Option<String>) {
+    match reply {
+        Some(reply) if !reply.trim().is_empty() => conversation.push(ChatMessage::assistant(reply)),
+        _ => {
+            if conversation.last().is_some_and(|message| message.role == Role::User) {
+                conversation.pop();
+            }
+        }
+    }
+}
+
+fn save_session(store: &Option<SessionStore>, session: &mut ChatSession, conversation: &[ChatMessage]) {
+    if let Some(store) = store {
+        session.messages = conversation.to_vec();
+        session.touch();
+        if let Err(e) = store.save(session) {
+            eprintln!("⚠️  Could not save the chat session: {}", e);
+        }
+    }
+}
+
+/// What every request starts with: the system prompt and the attachments
+fn prelude(client: &AiClient, attached: &[Attachment]) -> Vec<ChatMessage> {
+    let mut prelude = vec![ChatMessage::system(client.chat_system_prompt())];
+    prelude.extend(attachments::message(attached));
+    prelude
+}
+
+/// Add `added` to the attachments, replacing any with the same name, cut to the room
+/// the conversation leaves in the context window
+async fn attach(
+    client: &AiClient,
+    config: &Config,
+    attached: &mut Vec<Attachment>,
+    added: Vec<Attachment>,
+    context: &ChatContext,
+    conversation: &[ChatMessage],
+) {
+    attached.retain(|attachment| added.iter().all(|new| new.label != attachment.label));
+    let used = context.usage(&prelude(client, attached), conversation).total();
+    let mut budget = client.content_budget(used, config.chat.reserved_output_tokens).await;
+    let attached_chars: usize = attached.iter().map(|attachment| attachment.content.len()).sum();
+    budget.max_chars = budget.max_chars.map(|max| max.saturating_sub(attached_chars));
+
+    let fitted = attachments::fit(added, budget);
+    for attachment in &fitted.attachments {
+        let note = if attachment.truncated { ", truncated to fit the context window" } else { "" };
+        println!("📎 Attached {} ({} tokens{})", attachment.label, attachment.tokens(), note);
+    }
+    if !fitted.skipped.is_empty() {
+        println!("⚠️  Not attached, the context window is full: {}", fitted.skipped.join(", "));
+    }
+    attached.extend(fitted.attachments);
+}
+
+fn print_attachments(attached: &[Attachment]) {
+    if attached.is_empty() {
+        println!("Nothing is attached. Use /add <path|glob>, /run <command> or /diff.");
+        return;
+    }
+    for attachment in attached {
+        let note = if attachment.truncated { "  (truncated)" } else { "" };
+        println!("📎 {:<40} {:>6} tokens{}", attachment.label, attachment.tokens(), note);
+    }
+}
+
+/// Summarize or drop the oldest turns when the next request would outgrow `budget` tokens
+async fn fit_context(client: &AiClient, config: &Config, context: &mut ChatContext, prelude: &[ChatMessage], conversation: &[ChatMessage], budget: usize) {
+    let Some(end) = context.compaction_point(prelude, conversation, budget) else {
+        return;
+    };
+    let count = conversation[context.compacted..end].iter().filter(|message| !message.pinned).count();
+
+    let summary = match config.chat.context_strategy {
+        ContextStrategy::Summarize => {
+            eprintln!("🗜️  Summarizing {} earlier message(s) to stay within the context window...", count);
+            let transcript = context.transcript(conversation, end);
+            let room = budget.saturating_sub(context.usage(prelude, conversation).summary);
+            match client.summarize_conversation(context.summary.as_deref(), tokens::truncate_lines(&transcript, room)).await {
+                Ok(summary) => Some(summary),
+                Err(e) => {
+                    eprintln!("⚠️  Could not summarize them ({}); dropping them instead", retry::first_line(&e));
+                    None
+                }
             }
         }
+        ContextStrategy::Drop => None,
+    };
+    if summary.is_none() {
+        eprintln!("✂️  Dropped {} earlier message(s) from the context (see /context)", count);
+    }
+    context.compact(end, summary);
+
+    let used = context.usage(prelude, conversation).total();
+    if used > budget {
+        eprintln!(
+            "⚠️  The conversation still needs about {} tokens of the {} available; remove attachments with /drop or unpin messages with /unpin",
+            used, budget
+        );
+    }
+}
+
+/// `/context`: what the next request contains and how much of the window it takes
+fn print_context(client: &AiClient, config: &Config, context: &ChatContext, attached: &[Attachment], conversation: &[ChatMessage], window: usize) {
+    let reserved = config.chat.reserved_output_tokens;
+    let budget = chat_context::budget(window, reserved);
+    let system = ChatMessage::system(client.chat_system_prompt()).tokens();
+    let usage = context.usage(&prelude(client, attached), conversation);
+    let total = usage.total();
+    let percent = total * 100 / budget.max(1);
+
+    println!("Context window: {} tokens ({} usable, {} kept for the reply)", window, budget, reserved);
+    let filled = percent.min(100) / 5;
+    println!("[{}{}] {}% used, about {} tokens", "█".repeat(filled), "░".repeat(20 - filled), percent, total);
+    println!("  System prompt  {:>7}", system);
+    if !attached.is_empty() {
+        println!("  Attachments    {:>7}  ({} attached, see /add)", usage.prelude - system, attached.len());
+    }
+    let left_out = conversation[..context.compacted].iter().filter(|message| !message.pinned).count();
+    if context.summary.is_some() {
+        println!("  Summary        {:>7}  ({} earlier message(s))", usage.summary, left_out);
+    } else if context.compacted > 0 {
+        println!("  Dropped        {:>7}  ({} earlier message(s))", "-", left_out);
+    }
+    println!("  Pinned         {:>7}  ({} message(s))", usage.pinned, usage.pinned_messages);
+    println!("  Recent         {:>7}  ({} message(s))", usage.recent, usage.recent_messages);
+    let strategy = match config.chat.context_strategy {
+        ContextStrategy::Summarize => "summarized",
+        ContextStrategy::Drop => "dropped",
+    };
+    println!("The oldest turns are {} when the conversation outgrows the window; pinned messages are always kept.", strategy);
+
+    if conversation.is_empty() {
+        return;
+    }
+    println!();
+    for (index, message) in conversation.iter().enumerate() {
+        let state = if message.pinned {
+            "📌"
+        } else if !context.is_sent(conversation, index) {
+            "--"
+        } else {
+            "  "
+        };
+        println!(
+            "{:>4} {} {:<3} {:>6}  {}",
+            index + 1,
+            state,
+            chat_context::speaker(message.role),
+            message.tokens(),
+            sessions::first_line(&message.content, PREVIEW_LENGTH)
+        );
+    }
+    println!("(📌 pinned, -- left out of the request)");
+}
+
+/// The message `argument` refers to: its number in `/context`, or the latest one
+fn message_index(conversation: &[ChatMessage], argument: &str) -> Result<usize> {
+    if argument.is_empty() {
+        return conversation.len().checked_sub(1).ok_or_else(|| anyhow!("There are no messages yet"));
+    }
+    argument.parse::<usize>()
+        .ok()
+        .filter(|number| (1..=conversation.len()).contains(number))
+        .map(|number| number - 1)
+        .ok_or_else(|| anyhow!("No message {}; /context lists them with their numbers", argument))
+}
+
+/// Names and ids of the saved sessions, for completing `/load`
+fn session_keys(store: &SessionStore) -> Vec<String> {
+    store.list()
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|session| session.name.into_iter().chain(std::iter::once(session.id)))
+        .collect()
+}
+
+/// `ai chat --list`: saved sessions, most recent first
+pub async fn handle_chat_list() -> Result<()> {
+    let store = SessionStore::open()?;
+    print_sessions(&store, None, usize::MAX)
+}
+
+/// `ai chat --export <id>`: write a session to `output` or stdout
+pub async fn handle_chat_export(key: &str, output: Option<&Path>, format: Option<ExportFormat>) -> Result<()> {
+    let session = SessionStore::open()?.find(key)?;
+    match output {
+        Some(path) => {
+            export_to_file(&session, path, format.unwrap_or_else(|| ExportFormat::from_path(path)))?;
+            eprintln!("✓ Exported {} to {}", session.label(), path.display());
+        }
+        None => print!("{}", session.export(format.unwrap_or(ExportFormat::Markdown))?),
+    }
+    Ok(())
+}
+
+fn export_to_file(session: &ChatSession, path: &Path, format: ExportFormat) -> Result<()> {
+    std::fs::write(path, session.export(format)?)
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+/// List sessions, only those started in `working_dir` when given
+fn print_sessions(store: &SessionStore, working_dir: Option<&str>, limit: usize) -> Result<()> {
+    let sessions: Vec<ChatSession> = store.list()?
+        .into_iter()
+        .filter(|session| working_dir.is_none_or(|dir| session.working_dir == dir))
+        .take(limit)
+        .collect();
+
+    if sessions.is_empty() {
+        println!("No saved chat sessions.");
+        return Ok(());
+    }
+
+    for session in &sessions {
+        println!(
+            "{:<24} {:>9}  {:>3} msgs  {}",
+            session.label(),
+            sessions::format_age(session.updated_at),
+            session.messages.len(),
+            session.title()
+        );
+        if working_dir.is_none() {
+            println!("{:<24} {}", "", session.working_dir);
+        }
     }
-    
     Ok(())
 }
 
+fn print_resumed(session: &ChatSession) {
+    println!(
+        "Resumed session {} ({} messages, last active {}): {}",
+        session.label(),
+        session.messages.len(),
+        sessions::format_age(session.updated_at),
+        session.title()
+    );
+}
+
 pub fn show_chat_help() {
     println!("Chat Commands:");
-    println!("  /help          Show this help message");
-    println!("  /commit        Commit changes with AI-generated message");
-    println!("  /commit all    Stage all changes and commit with AI-generated message");
-    println!("  /push          Push changes to remote repository");
-    println!("  /push force    Force push changes to remote repository");
-    println!("  /publish       Publish project to appropriate registry");
-    println!("  /exit, /quit   Exit the chat session");
-}
\ No newline at end of file
+    for command in SLASH_COMMANDS {
+        let usage = format!("{} {}", command.name, command.arguments);
+        println!("  {:<18} {}", usage.trim_end(), command.description);
+    }
+    println!();
+    println!("Start and end a message with {} (or press Alt-Enter) to write several lines.", crate::chat_input::MULTILINE_QUOTE);
+    println!("Up/Down recall earlier messages, Ctrl-R searches them and Tab completes commands.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_non_empty_replies_are_recorded() {
+        let mut conversation = vec![ChatMessage::user("Hi"), ChatMessage::assistant("Hello")];
+
+        conversation.push(ChatMessage::user("Interrupted question"));
+        record_reply(&mut conversation, Some(String::new()));
+        assert_eq!(conversation.len(), 2);
+
+        conversation.push(ChatMessage::user("Failed question"));
+        record_reply(&mut conversation, None);
+        assert_eq!(conversation.len(), 2);
+
+        conversation.push(ChatMessage::user("Next question"));
+        record_reply(&mut conversation, Some("Answer".to_string()));
+        let turns: Vec<(Role, &str)> = conversation.iter().map(|m| (m.role, m.content.as_str())).collect();
+        assert_eq!(
+            turns,
+            vec![
+                (Role::User, "Hi"),
+                (Role::Assistant, "Hello"),
+                (Role::User, "Next question"),
+                (Role::Assistant, "Answer"),
+            ]
+        );
+    }
+}
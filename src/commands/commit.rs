@@ -1,12 +1,82 @@
 use anyhow::Result;
-use crate::config::Config;
-use crate::ai_client::AiClient;
-use crate::git_ops::GitOperations;
+use crate::commit_message::{CommitMessage, CommitStyle, Violation};
+use crate::config::{Config, GitConfig};
+use crate::ai_client::{AiClient, ModelUsage};
+use crate::git_ops::{CommitContext, DiffStats, FileSummary, GitOperations};
+use crate::output::{self, OutputFormat};
+use crate::tokens;
 use crate::utils::Utils;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::time::Instant;
+
+/// Options for `ai commit`
+#[derive(Debug, Clone, Default)]
+pub struct CommitOptions {
+    /// Stage all changes before committing
+    pub all: bool,
+    /// Generate and print the message without staging or committing
+    pub dry_run: bool,
+    /// Commit the generated message without the interactive review
+    pub yes: bool,
+    /// JSON commits without the review, like `yes`
+    pub format: OutputFormat,
+}
+
+impl CommitOptions {
+    pub fn all() -> Self {
+        Self { all: true, ..Self::default() }
+    }
+}
+
+/// What `ai commit --format json` prints
+#[derive(Serialize)]
+struct CommitReport {
+    status: CommitStatus,
+    message: Option<String>,
+    sha: Option<String>,
+    stats: Option<DiffStats>,
+    /// Per-file summaries, when the diff was too large to send as-is
+    files: Vec<FileSummary>,
+    /// Style rules the message still breaks
+    violations: Vec<String>,
+    model: ModelUsage,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CommitStatus {
+    Committed,
+    DryRun,
+    /// Nothing to commit
+    Clean,
+}
+
+impl CommitReport {
+    fn new(status: CommitStatus, client: &AiClient, started: Instant) -> Self {
+        Self {
+            status,
+            message: None,
+            sha: None,
+            stats: None,
+            files: Vec::new(),
+            violations: Vec::new(),
+            model: client.usage(),
+            elapsed_ms: output::elapsed_ms(started),
+        }
+    }
+}
+
+pub async fn handle_commit(options: CommitOptions) -> Result<()> {
+    let started = Instant::now();
+    let format = options.format;
 
-pub async fn handle_commit(all: bool) -> Result<()> {
     // Check if we're in a git repository
     if !GitOperations::is_git_repo() {
+        if format.is_json() {
+            return Err(anyhow::anyhow!("Not in a git repository"));
+        }
         println!("Error: Not in a git repository");
         return Ok(());
     }
@@ -16,50 +86,70 @@ pub async fn handle_commit(all: bool) -> Result<()> {
     let client = AiClient::new_with_full_config(provider_config.clone(), command_config.clone(), config.git.clone(), config.clone())?;
 
     // Handle 'all' flag
-    if all {
-        println!("Staging all changes...");
+    if options.all && !options.dry_run {
+        format.status("Staging all changes...");
         GitOperations::add_all()?;
     }
 
     // Check for staged changes
     let has_staged = GitOperations::has_staged_changes()?;
-    if !has_staged {
+    let mut use_head_diff = options.dry_run && options.all;
+    if !has_staged && !use_head_diff {
         // No staged changes, check if there are unstaged changes
         let has_unstaged = GitOperations::has_unstaged_changes()?;
         if !has_unstaged {
-            println!("No changes to commit. Working directory is clean.");
-            return Ok(());
+            format.status("No changes to commit. Working directory is clean.");
+            return report_clean(format, &client, started);
         }
         
         // There are unstaged changes but no staged changes
-        println!("No staged changes found, but there are unstaged changes.");
-        println!("Would you like to stage all changes and commit them?");
+        format.status("No staged changes found, but there are unstaged changes.");
         
-        if Utils::confirm("Stage all changes and commit?")? {
-            println!("Staging all changes...");
-            GitOperations::add_all()?;
+        if options.dry_run {
+            // Nothing gets staged in a dry run; describe the tracked changes instead
+            use_head_diff = true;
+        } else if format.is_json() {
+            return Err(anyhow::anyhow!("Nothing is staged; stage the changes to commit or pass --all"));
         } else {
-            println!("Commit cancelled.");
-            return Ok(());
+            println!("Would you like to stage all changes and commit them?");
+            
+            if Utils::confirm("Stage all changes and commit?")? {
+                println!("Staging all changes...");
+                GitOperations::add_all()?;
+            } else {
+                println!("Commit cancelled.");
+                return Ok(());
+            }
         }
     }
 
     // Get staged diff (either from originally staged files or newly staged files)
-    let diff = GitOperations::get_staged_diff()?;
+    let diff = if use_head_diff {
+        // Repositories without commits have no HEAD to diff against
+        GitOperations::get_head_diff().or_else(|_| GitOperations::get_unstaged_diff())?
+    } else {
+        GitOperations::get_staged_diff()?
+    };
+    if diff.trim().is_empty() {
+        format.status("No changes to commit. Working directory is clean.");
+        return report_clean(format, &client, started);
+    }
 
-    // Get provider-specific max diff length
-    let (_provider_config, command_config) = config.get_git_operations_ai_config()?;
-    let max_diff_length = config.get_max_diff_length_for_provider(&command_config.provider, &command_config.model);
+    // Branch, recent history and touched scopes help the model follow repo conventions
+    let context = GitOperations::gather_commit_context(&diff, config.git.recent_commits_count)?;
+    let mut guidance = MessageGuidance::new(&config.git);
 
-    // Check diff length and decide processing strategy
-    let commit_message = if diff.len() > max_diff_length {
-        println!("Large diff detected ({} chars). Using intelligent processing...", diff.len());
-        
-        // Generate overall statistics
-        let stats = GitOperations::generate_diff_stats(&diff);
+    // The diff is sent as-is if it fits in the model's context window next to the prompt
+    let budget = client.diff_budget(&context, &guidance.instructions()).await;
+    let source = if !budget.fits(&diff) {
+        format.status(format!(
+            "Large diff detected (~{} tokens, {} fit in one request). Using intelligent processing...",
+            tokens::count(&diff),
+            budget.max_tokens
+        ));
         
         // Segment the diff by files for parallel processing
-        let segments = GitOperations::segment_diff_by_files(&diff, max_diff_length);
+        let segments = GitOperations::segment_diff_by_files(&diff, budget);
         
         if segments.is_empty() {
             return Err(anyhow::anyhow!("Failed to segment diff for processing"));
@@ -78,17 +168,230 @@ pub async fn handle_commit(all: bool) -> Result<()> {
                 )
             })?;
 
-        // Generate final commit message based on stats and summaries
-        client.generate_final_commit_message(&stats, &file_summaries).await?
+        // Summaries are kept so regenerating doesn't re-analyze every segment
+        MessageSource::Summaries(file_summaries)
     } else {
-        // Use original logic for smaller diffs
-        println!("Generating commit message...");
-        client.generate_commit_message(&diff).await?
+        MessageSource::Diff(diff)
     };
-    
-    println!("Commit message: {}", commit_message);
+
+    let mut commit_message = generate_message(&client, &source, &context, &guidance, format).await?;
+
+    if options.dry_run {
+        format.status(format!("Commit message: {}", commit_message));
+        let violations = report_violations(&commit_message, &guidance, format);
+        format.status("Dry run: nothing was staged or committed.");
+        if format.is_json() {
+            output::print_json(&CommitReport {
+                message: Some(commit_message),
+                stats: Some(context.stats),
+                files: source.summaries().to_vec(),
+                violations: violations.iter().map(Violation::to_string).collect(),
+                ..CommitReport::new(CommitStatus::DryRun, &client, started)
+            })?;
+        }
+        return Ok(());
+    }
+
+    // Let the user review the message unless running non-interactively
+    let interactive = !options.yes && !format.is_json() && std::io::stdin().is_terminal();
+    let mut violations;
+    loop {
+        format.status(format!("Commit message: {}", commit_message));
+        violations = report_violations(&commit_message, &guidance, format);
+        if !interactive {
+            // Style warnings are only printed; a message without a valid subject is not committed
+            let blocking: Vec<String> = violations.iter()
+                .filter(|violation| violation.blocking)
+                .map(Violation::to_string)
+                .collect();
+            if !blocking.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Generated commit message does not match the '{}' commit style ({}); nothing was committed",
+                    guidance.style,
+                    blocking.join("; ")
+                ));
+            }
+            break;
+        }
+
+        let choices = [
+            REVIEW_ACCEPT,
+            REVIEW_EDIT,
+            REVIEW_REGENERATE,
+            REVIEW_REGENERATE_HINT,
+            if guidance.style.is_multi_line() { REVIEW_SUBJECT_ONLY } else { REVIEW_WITH_BODY },
+            REVIEW_CANCEL,
+        ];
+        match Utils::select_option(&choices, "Commit message: ")?.as_deref() {
+            Some(REVIEW_ACCEPT) => break,
+            Some(REVIEW_EDIT) => {
+                let edited = Utils::edit_text(&commit_message)?;
+                if edited.is_empty() {
+                    println!("Empty commit message, keeping the previous one.");
+                } else {
+                    commit_message = edited;
+                }
+            }
+            Some(REVIEW_REGENERATE) => {
+                commit_message = generate_message(&client, &source, &context, &guidance, format).await?;
+            }
+            Some(REVIEW_REGENERATE_HINT) => {
+                let hint = Utils::prompt_input("Hint for the new message (e.g. \"mention the migration\"):")?;
+                guidance.hint = if hint.is_empty() { None } else { Some(hint) };
+                commit_message = generate_message(&client, &source, &context, &guidance, format).await?;
+            }
+            Some(REVIEW_WITH_BODY) | Some(REVIEW_SUBJECT_ONLY) => {
+                guidance.toggle_body();
+                commit_message = generate_message(&client, &source, &context, &guidance, format).await?;
+            }
+            _ => {
+                println!("Commit cancelled.");
+                return Ok(());
+            }
+        }
+    }
+
     GitOperations::commit(&commit_message)?;
-    println!("✓ Committed successfully!");
+    format.status("✓ Committed successfully!");
+
+    if format.is_json() {
+        output::print_json(&CommitReport {
+            message: Some(commit_message),
+            sha: Some(GitOperations::get_head_sha()?),
+            stats: Some(context.stats),
+            files: source.summaries().to_vec(),
+            violations: violations.iter().map(Violation::to_string).collect(),
+            ..CommitReport::new(CommitStatus::Committed, &client, started)
+        })?;
+    }
+    Ok(())
+}
 
+fn report_clean(format: OutputFormat, client: &AiClient, started: Instant) -> Result<()> {
+    if format.is_json() {
+        output::print_json(&CommitReport::new(CommitStatus::Clean, client, started))?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// How many times the model is re-prompted with violations it must fix
+const MAX_REPAIR_ATTEMPTS: usize = 2;
+
+const REVIEW_ACCEPT: &str = "Accept and commit";
+const REVIEW_EDIT: &str = "Edit in $EDITOR";
+const REVIEW_REGENERATE: &str = "Regenerate";
+const REVIEW_REGENERATE_HINT: &str = "Regenerate with a hint";
+const REVIEW_WITH_BODY: &str = "Regenerate with a multi-line body";
+const REVIEW_SUBJECT_ONLY: &str = "Regenerate as a single line";
+const REVIEW_CANCEL: &str = "Cancel";
+
+/// What the commit message is generated from
+enum MessageSource {
+    /// The full diff, small enough to send as-is
+    Diff(String),
+    /// Per-file summaries of a large diff
+    Summaries(Vec<FileSummary>),
+}
+
+impl MessageSource {
+    fn summaries(&self) -> &[FileSummary] {
+        match self {
+            MessageSource::Diff(_) => &[],
+            MessageSource::Summaries(file_summaries) => file_summaries,
+        }
+    }
+}
+
+/// Style settings plus user guidance collected while reviewing the message
+#[derive(Debug)]
+struct MessageGuidance {
+    style: CommitStyle,
+    /// Multi-line style to switch to when the user asks for a body
+    body_style: CommitStyle,
+    commit_types: Vec<String>,
+    max_subject_length: usize,
+    language: Option<String>,
+    hint: Option<String>,
+}
+
+impl MessageGuidance {
+    fn new(git_config: &GitConfig) -> Self {
+        let body_style = if git_config.commit_style.is_multi_line() {
+            git_config.commit_style
+        } else {
+            CommitStyle::SubjectBody
+        };
+        Self {
+            style: git_config.commit_style,
+            body_style,
+            commit_types: git_config.commit_types.clone(),
+            max_subject_length: git_config.max_subject_length,
+            language: git_config.language.clone(),
+            hint: None,
+        }
+    }
+
+    fn toggle_body(&mut self) {
+        self.style = if self.style.is_multi_line() { CommitStyle::OneLine } else { self.body_style };
+    }
+
+    /// Extra instructions appended to the commit prompt
+    fn instructions(&self) -> String {
+        let mut instructions = self.style.instructions(&self.commit_types, self.max_subject_length);
+        instructions.push('\n');
+        if let Some(language) = &self.language {
+            instructions.push_str(&format!("Write the commit message in {}.\n", language));
+        }
+        if let Some(hint) = &self.hint {
+            instructions.push_str(&format!("Additional guidance from the author: {}\n", hint));
+        }
+        instructions
+    }
+}
+
+async fn generate_message(client: &AiClient, source: &MessageSource, context: &CommitContext, guidance: &MessageGuidance, format: OutputFormat) -> Result<String> {
+    let instructions = guidance.instructions();
+    let raw = match source {
+        MessageSource::Diff(diff) => {
+            format.status("Generating commit message...");
+            client.generate_commit_message(diff, context, &instructions).await?
+        }
+        MessageSource::Summaries(file_summaries) => {
+            client.generate_final_commit_message(context, file_summaries, &instructions).await?
+        }
+    };
+
+    let mut message = repaired(&raw, guidance);
+    for _ in 0..MAX_REPAIR_ATTEMPTS {
+        let violations: Vec<String> = message.validate(guidance.style, &guidance.commit_types, guidance.max_subject_length)
+            .iter()
+            .map(Violation::to_string)
+            .collect();
+        if violations.is_empty() {
+            break;
+        }
+        format.status(format!("Asking the model to fix: {}", violations.join("; ")));
+        let raw = client.repair_commit_message(&message.to_string(), &violations, &instructions).await?;
+        message = repaired(&raw, guidance);
+    }
+
+    // Re-render so bodies are wrapped consistently
+    Ok(message.to_string())
+}
+
+/// Parse sanitized model output and apply the fixes that need no model
+fn repaired(raw: &str, guidance: &MessageGuidance) -> CommitMessage {
+    let mut message = CommitMessage::parse(raw);
+    message.repair(guidance.style, &guidance.commit_types);
+    message
+}
+
+/// Print style violations for the message and return them
+fn report_violations(message: &str, guidance: &MessageGuidance, format: OutputFormat) -> Vec<Violation> {
+    let violations = CommitMessage::parse(message)
+        .validate(guidance.style, &guidance.commit_types, guidance.max_subject_length);
+    for violation in &violations {
+        format.status(format!("⚠️  {}", violation));
+    }
+    violations
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use crate::config::{Config, ConfigEntry, MASKED_API_KEY};
+use crate::output::{self, OutputFormat};
+use crate::utils::Utils;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Longest value preview printed by `ai config show --origin`
+const MAX_PREVIEW_LENGTH: usize = 60;
+
+pub async fn handle_config_show(origin: bool) -> Result<()> {
+    if !origin {
+        let mut value = toml::Value::try_from(Config::load()?)?;
+        mask_api_keys(&mut value);
+        print!("{}", toml::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    for entry in Config::load_with_origins()? {
+        let preview = if is_api_key(&entry.key, &entry.value) {
+            format!("\"{}\"", MASKED_API_KEY)
+        } else {
+            preview(&entry.value)
+        };
+        println!("{} = {}  # {}", entry.key, preview, entry.origin);
+    }
+
+    Ok(())
+}
+
+/// Print the effective value of a key; a table prefix prints every key below it
+pub async fn handle_config_get(key: &str, format: OutputFormat) -> Result<()> {
+    let entries = Config::load_with_origins()?;
+    let prefix = format!("{}.", key);
+
+    if format.is_json() {
+        return output::print_json(&ConfigValue { key, value: json_value(key, &entries)? });
+    }
+
+    if let Some(entry) = entries.iter().find(|entry| entry.key == key) {
+        // Strings are printed raw so the output can be used in scripts
+        match &entry.value {
+            _ if is_api_key(&entry.key, &entry.value) => println!("{}", MASKED_API_KEY),
+            toml::Value::String(s) => println!("{}", s),
+            other => println!("{}", other),
+        }
+        return Ok(());
+    }
+
+    let nested: Vec<_> = entries.iter().filter(|entry| entry.key.starts_with(&prefix)).collect();
+    if nested.is_empty() {
+        return Err(anyhow::anyhow!("Unknown config key '{}'", key));
+    }
+    for entry in nested {
+        let value = if is_api_key(&entry.key, &entry.value) {
+            format!("\"{}\"", MASKED_API_KEY)
+        } else {
+            preview(&entry.value)
+        };
+        println!("{} = {}", entry.key, value);
+    }
+
+    Ok(())
+}
+
+/// What `ai config get --format json` prints
+#[derive(Serialize)]
+struct ConfigValue<'a> {
+    key: &'a str,
+    value: serde_json::Value,
+}
+
+/// The value of `key`, or an object of every key below it, with API keys masked
+fn json_value(key: &str, entries: &[ConfigEntry]) -> Result<serde_json::Value> {
+    let masked = |entry: &ConfigEntry| -> Result<serde_json::Value> {
+        if is_api_key(&entry.key, &entry.value) {
+            Ok(serde_json::Value::String(MASKED_API_KEY.to_string()))
+        } else {
+            Ok(serde_json::to_value(&entry.value)?)
+        }
+    };
+    if let Some(entry) = entries.iter().find(|entry| entry.key == key) {
+        return masked(entry);
+    }
+
+    let prefix = format!("{}.", key);
+    let mut object = serde_json::Map::new();
+    for entry in entries.iter().filter(|entry| entry.key.starts_with(&prefix)) {
+        let mut path = entry.key[prefix.len()..].split('.').peekable();
+        let mut table = &mut object;
+        while let Some(part) = path.next() {
+            if path.peek().is_none() {
+                table.insert(part.to_string(), masked(entry)?);
+                break;
+            }
+            table = table.entry(part)
+                .or_insert_with(|| serde_json::Value::Object(Default::default()))
+                .as_object_mut()
+                .context("Config keys overlap")?;
+        }
+    }
+    if object.is_empty() {
+        return Err(anyhow::anyhow!("Unknown config key '{}'", key));
+    }
+    Ok(serde_json::Value::Object(object))
+}
+
+pub async fn handle_config_set(key: &str, value: &str, project: bool) -> Result<()> {
+    let path = target_path(project)?;
+    Config::set_value(&path, key, value)?;
+    println!("✓ Set {} in {}", key, path.display());
+    report_issues(&Config::validate()?);
+    Ok(())
+}
+
+pub async fn handle_config_unset(key: &str, project: bool) -> Result<()> {
+    let path = target_path(project)?;
+    Config::unset_value(&path, key)?;
+    println!("✓ Removed {} from {}", key, path.display());
+    Ok(())
+}
+
+/// Edit a copy of the config and only replace the file once it validates
+pub async fn handle_config_edit(project: bool) -> Result<()> {
+    let path = target_path(project)?;
+    if !path.exists() && !project {
+        // Loading creates the default user config
+        Config::load()?;
+    }
+    let original = std::fs::read_to_string(&path).unwrap_or_default();
+
+    // A private file (mode 0600, random name) next to the config, so API keys in it
+    // are never readable by other users and the name can't be guessed in advance
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let draft = tempfile::Builder::new()
+        .prefix(".ai-config-")
+        .suffix(".toml")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a draft in {}", dir.display()))?;
+    std::fs::write(draft.path(), &original)?;
+
+    loop {
+        Utils::open_in_editor(draft.path())?;
+        let content = std::fs::read_to_string(draft.path())?;
+        if content == original {
+            println!("No changes made.");
+            return Ok(());
+        }
+
+        let issues = Config::check_file(&path, &content)?;
+        if issues.is_empty() {
+            std::fs::write(&path, content)?;
+            println!("✓ Saved {}", path.display());
+            return Ok(());
+        }
+
+        report_issues(&issues);
+        if !Utils::confirm("Edit again?")? {
+            println!("Changes discarded.");
+            return Ok(());
+        }
+    }
+}
+
+pub async fn handle_config_validate() -> Result<()> {
+    let issues = Config::validate()?;
+    if issues.is_empty() {
+        println!("✓ Config is valid");
+        return Ok(());
+    }
+
+    report_issues(&issues);
+    Err(anyhow::anyhow!("Found {} problem(s) in the config", issues.len()))
+}
+
+pub async fn handle_config_path(project: bool) -> Result<()> {
+    println!("{}", target_path(project)?.display());
+    Ok(())
+}
+
+pub(super) fn target_path(project: bool) -> Result<PathBuf> {
+    if project {
+        Config::project_config_target()
+    } else {
+        Config::config_path()
+    }
+}
+
+pub(super) fn report_issues(issues: &[crate::config::ConfigIssue]) {
+    for issue in issues {
+        eprintln!("❌ {}", issue);
+    }
+}
+
+fn is_api_key(key: &str, value: &toml::Value) -> bool {
+    key.ends_with(".api_key") && value.as_str().is_some_and(|k| !k.is_empty())
+}
+
+fn mask_api_keys(value: &mut toml::Value) {
+    let Some(providers) = value.get_mut("providers").and_then(|p| p.as_table_mut()) else {
+        return;
+    };
+    for (_, provider) in providers.iter_mut() {
+        if let Some(key) = provider.get_mut("api_key") {
+            if key.as_str().is_some_and(|k| !k.is_empty()) {
+                *key = toml::Value::String(MASKED_API_KEY.to_string());
+            }
+        }
+    }
+}
+
+/// Single-line, shortened rendering of a value (prompts span many lines)
+fn preview(value: &toml::Value) -> String {
+    let text = match value {
+        toml::Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    };
+    if text.chars().count() > MAX_PREVIEW_LENGTH {
+        let short: String = text.chars().take(MAX_PREVIEW_LENGTH).collect();
+        format!("{}…", short)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigOrigin;
+    use serde_json::json;
+
+    #[test]
+    fn json_values_nest_keys_and_mask_api_keys() {
+        let entry = |key: &str, value: toml::Value| ConfigEntry {
+            key: key.to_string(),
+            value,
+            origin: ConfigOrigin::Default,
+        };
+        let entries = vec![
+            entry("git.commit_style", toml::Value::String("one-line".to_string())),
+            entry("providers.deepseek.api_key", toml::Value::String("sk-secret".to_string())),
+            entry("providers.deepseek.base_url", toml::Value::String("https://api.deepseek.com".to_string())),
+            entry("providers.ollama.api_key", toml::Value::String(String::new())),
+        ];
+
+        assert_eq!(json_value("git.commit_style", &entries).unwrap(), json!("one-line"));
+        assert_eq!(json_value("providers.deepseek.api_key", &entries).unwrap(), json!(MASKED_API_KEY));
+        assert_eq!(
+            json_value("providers", &entries).unwrap(),
+            json!({
+                "deepseek": { "api_key": MASKED_API_KEY, "base_url": "https://api.deepseek.com" },
+                "ollama": { "api_key": "" },
+            })
+        );
+        assert!(json_value("git.commit", &entries).is_err());
+    }
+}
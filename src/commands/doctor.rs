@@ -0,0 +1,379 @@
+use anyhow::{anyhow, Result};
+use crate::ai_client::AiClient;
+use crate::config::{is_readable_by_others, Config};
+use crate::git_ops::GitOperations;
+use crate::output;
+use crate::utils::{Utils, CLIPBOARD_INSTALL_HINT};
+use super::models::{configured_models, ModelCatalog, ModelStatus};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::process::Command;
+
+/// Shell history entries read to check that history is readable
+const HISTORY_SAMPLE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Info,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn icon(self) -> &'static str {
+        match self {
+            Status::Pass => "✓",
+            Status::Info => "ℹ️ ",
+            Status::Warn => "⚠️ ",
+            Status::Fail => "❌",
+        }
+    }
+}
+
+/// One diagnostic and, when it didn't pass, how to fix it
+#[derive(Debug, Serialize)]
+struct Check {
+    section: &'static str,
+    name: String,
+    status: Status,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    checks: Vec<Check>,
+    passed: usize,
+    warnings: usize,
+    failed: usize,
+}
+
+/// Collects checks for one section at a time
+struct Checks {
+    section: &'static str,
+    checks: Vec<Check>,
+}
+
+impl Checks {
+    fn add(&mut self, name: impl Into<String>, status: Status, detail: impl Into<String>, fix: Option<String>) {
+        self.checks.push(Check {
+            section: self.section,
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            fix,
+        });
+    }
+
+    fn pass(&mut self, name: impl Into<String>, detail: impl Into<String>) {
+        self.add(name, Status::Pass, detail, None);
+    }
+
+    fn info(&mut self, name: impl Into<String>, detail: impl Into<String>) {
+        self.add(name, Status::Info, detail, None);
+    }
+
+    fn warn(&mut self, name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) {
+        self.add(name, Status::Warn, detail, Some(fix.into()));
+    }
+
+    fn fail(&mut self, name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) {
+        self.add(name, Status::Fail, detail, Some(fix.into()));
+    }
+}
+
+/// Check the config, providers, git, forge CLIs, clipboard and shell history
+pub async fn handle_doctor(json: bool) -> Result<()> {
+    let mut checks = Checks { section: "Config", checks: Vec::new() };
+
+    let config = check_config(&mut checks);
+    checks.section = "Providers";
+    if let Some(config) = &config {
+        check_providers(&mut checks, config).await;
+    } else {
+        checks.info("providers", "skipped until the config loads");
+    }
+    checks.section = "Git";
+    check_git(&mut checks);
+    checks.section = "Forges";
+    check_forge_cli(&mut checks, "gh", "GitHub", "brew install gh  (https://cli.github.com)");
+    check_forge_cli(&mut checks, "glab", "GitLab", "brew install glab  (https://gitlab.com/gitlab-org/cli)");
+    checks.section = "Clipboard";
+    check_clipboard(&mut checks);
+    checks.section = "Shell";
+    check_shell_history(&mut checks);
+    checks.section = "Features";
+    check_features(&mut checks, config.as_ref());
+
+    let checks = checks.checks;
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    let report = Report {
+        passed: count(Status::Pass),
+        warnings: count(Status::Warn),
+        failed: count(Status::Fail),
+        checks,
+    };
+
+    if json {
+        output::print_json(&report)?;
+    } else {
+        print_report(&report);
+    }
+
+    if report.failed > 0 {
+        let message = format!("ai doctor found {} problem(s)", report.failed);
+        return Err(if json { output::Reported(message).into() } else { anyhow!(message) });
+    }
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    let mut section = "";
+    for check in &report.checks {
+        if check.section != section {
+            if !section.is_empty() {
+                println!();
+            }
+            section = check.section;
+            println!("{}", section);
+        }
+
+        println!("  {} {}: {}", check.status.icon(), check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("     💡 {}", fix);
+        }
+    }
+
+    println!();
+    println!(
+        "{} passed, {} warning(s), {} failed",
+        report.passed, report.warnings, report.failed
+    );
+}
+
+/// Config file presence and validity, and API keys of the providers in use.
+/// Returns the loaded config when it can be used for the provider checks.
+fn check_config(checks: &mut Checks) -> Option<Config> {
+    let path = match Config::config_path() {
+        Ok(path) => path,
+        Err(e) => {
+            checks.fail("config file", e.to_string(), "Set HOME or pass --config <PATH>");
+            return None;
+        }
+    };
+    let existed = path.exists();
+    if existed {
+        checks.pass("config file", path.display().to_string());
+        match Config::validate() {
+            Ok(issues) if issues.is_empty() => checks.pass("validity", "config is valid"),
+            Ok(issues) => {
+                for issue in issues {
+                    checks.fail("validity", issue.to_string(), "ai config edit");
+                }
+            }
+            Err(e) => checks.fail("validity", e.to_string(), "ai config edit"),
+        }
+    }
+    if let Some(project_path) = Config::project_config_path() {
+        checks.info("project config", project_path.display().to_string());
+    }
+
+    // Loading creates the default user config when there is none
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            checks.fail("config file", format!("{:#}", e), "ai config edit");
+            return None;
+        }
+    };
+    if !existed {
+        checks.info("config file", format!("created {} with the defaults", path.display()));
+    }
+
+    let mut seen = HashSet::new();
+    for configured in configured_models(&config) {
+        if !seen.insert(configured.provider.clone()) {
+            continue;
+        }
+        let Some(provider_config) = config.providers.get(&configured.provider) else {
+            continue; // Reported by validate
+        };
+
+        let name = format!("{} API key", configured.provider);
+        let source = provider_config.api_key_source(&configured.provider);
+        match provider_config.resolve_api_key(&configured.provider) {
+            Ok(key) if !key.is_empty() => checks.pass(name, format!("set (from {})", source)),
+            Ok(_) if AiClient::requires_api_key(&configured.provider, provider_config) => checks.fail(
+                name,
+                format!("missing (looked in {})", source),
+                format!(
+                    "export {}=<key>, or set providers.{}.api_key_env / api_key_file / api_key_cmd",
+                    source.trim_start_matches('$'), configured.provider
+                ),
+            ),
+            Ok(_) => checks.pass(name, "not needed"),
+            Err(e) => checks.fail(name, e.to_string(), format!("ai config edit  (check providers.{})", configured.provider)),
+        }
+    }
+
+    if existed {
+        let literal_key = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| toml::from_str::<toml::Value>(&content).ok())
+            .is_some_and(|value| Config::has_literal_api_key(&value));
+        if literal_key && is_readable_by_others(&path) {
+            checks.warn(
+                "permissions",
+                "the config contains an API key and is readable by other users",
+                format!("chmod 600 {}  (or move the key to api_key_env / api_key_file / api_key_cmd)", path.display()),
+            );
+        }
+    }
+
+    Some(config)
+}
+
+/// Every provider the commands use is reachable and has the configured models
+async fn check_providers(checks: &mut Checks, config: &Config) {
+    let mut catalog = ModelCatalog::default();
+    let mut seen = HashSet::new();
+    for configured in configured_models(config) {
+        if !seen.insert(configured.label()) {
+            continue;
+        }
+
+        let name = if configured.fallback {
+            format!("{} (fallback for {})", configured.label(), configured.command)
+        } else {
+            format!("{} ({})", configured.label(), configured.command)
+        };
+        let status = catalog.status(config, &configured).await;
+        match &status {
+            ModelStatus::Ready(_) => checks.pass(name, status.describe()),
+            ModelStatus::Missing(_) if configured.is_ollama(config) => {
+                checks.warn(name, status.describe(), format!("ai models pull {}", configured.model));
+            }
+            ModelStatus::Missing(_) => checks.warn(
+                name,
+                status.describe(),
+                format!("Check the model name, or pick one with `ai models set {}`", configured.command),
+            ),
+            ModelStatus::Unavailable(_) => checks.fail(
+                name,
+                status.describe(),
+                "Check the provider's base_url and api_key, and that the server is running (`ai models ping`)",
+            ),
+        }
+    }
+}
+
+/// git itself, and the repository, remote and upstream of the current directory
+fn check_git(checks: &mut Checks) {
+    match Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            checks.pass("git", String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+        _ => {
+            checks.fail("git", "not installed", "Install git (https://git-scm.com/downloads)");
+            return;
+        }
+    }
+
+    if !GitOperations::is_git_repo() {
+        checks.info("repository", "the current directory is not a git repository");
+        return;
+    }
+    checks.pass("repository", Utils::current_dir().unwrap_or_default());
+
+    match GitOperations::get_remotes() {
+        Ok(remotes) if !remotes.is_empty() => checks.pass("remote", remotes.join(", ")),
+        Ok(_) => {
+            checks.warn("remote", "no remote configured", "git remote add origin <url>, or `ai publish` to create one");
+            return;
+        }
+        Err(e) => checks.fail("remote", e.to_string(), "git remote -v"),
+    }
+
+    let branch = GitOperations::get_current_branch().unwrap_or_default();
+    match GitOperations::get_upstream_status() {
+        Ok((upstream, 0, 0)) => checks.pass("upstream", format!("{} (up to date)", upstream)),
+        Ok((upstream, ahead, 0)) => checks.pass("upstream", format!("{} ({} ahead)", upstream, ahead)),
+        Ok((upstream, ahead, behind)) => checks.warn(
+            "upstream",
+            format!("{} ({} ahead, {} behind)", upstream, ahead, behind),
+            "git pull --rebase",
+        ),
+        Err(_) if branch.is_empty() => checks.info("upstream", "HEAD is detached"),
+        Err(_) => checks.warn(
+            "upstream",
+            format!("branch '{}' has no upstream", branch),
+            format!("git push -u origin {}  (`ai push` sets it up too)", branch),
+        ),
+    }
+}
+
+/// A forge CLI used by `ai publish` to create repositories
+fn check_forge_cli(checks: &mut Checks, tool: &str, forge: &str, install: &str) {
+    if !Utils::is_command_available(tool) {
+        checks.warn(tool, format!("not installed (needed to create {} repositories)", forge), install);
+        return;
+    }
+
+    let authenticated = Command::new(tool)
+        .args(["auth", "status"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    if authenticated {
+        checks.pass(tool, "installed and authenticated");
+    } else {
+        checks.warn(tool, "not authenticated", format!("{} auth login", tool));
+    }
+}
+
+fn check_clipboard(checks: &mut Checks) {
+    match Utils::clipboard_tool() {
+        Some(tool) => checks.pass("clipboard", tool),
+        None => checks.warn("clipboard", "no clipboard tool found (ai fix copies commands)", CLIPBOARD_INSTALL_HINT),
+    }
+}
+
+/// `ai fix` reads the shell history; zsh needs EXTENDED_HISTORY for exit codes
+fn check_shell_history(checks: &mut Checks) {
+    let shell = Utils::get_current_shell().unwrap_or_else(|_| "unknown".to_string());
+    checks.info("shell", shell.clone());
+
+    match Utils::get_shell_history(HISTORY_SAMPLE) {
+        Ok(history) if !history.is_empty() => checks.pass("history", "readable"),
+        Ok(_) => checks.warn("history", "empty", "Make sure your shell saves history to a file (HISTFILE)"),
+        Err(e) => checks.fail("history", e.to_string(), "Make sure your shell saves history to a file (HISTFILE)"),
+    }
+
+    if shell == "zsh" {
+        if Utils::is_zsh_extended_history_enabled() {
+            checks.pass("EXTENDED_HISTORY", "enabled");
+        } else {
+            checks.warn(
+                "EXTENDED_HISTORY",
+                "not enabled, so ai fix can't see timestamps",
+                "Add `setopt EXTENDED_HISTORY` to ~/.zshrc, then `source ~/.zshrc`",
+            );
+        }
+    }
+}
+
+fn check_features(checks: &mut Checks, config: Option<&Config>) {
+    let enabled = config.is_some_and(|config| config.history.enabled);
+    match (cfg!(feature = "history"), enabled) {
+        (true, _) => checks.pass("history feature", "compiled in"),
+        (false, true) => checks.warn(
+            "history feature",
+            "history.enabled is set but this build doesn't include the history feature",
+            "cargo install --path . --features history",
+        ),
+        (false, false) => checks.info("history feature", "not compiled in (optional)"),
+    }
+}
@@ -1,20 +1,45 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use crate::config::Config;
-use crate::ai_client::AiClient;
+use crate::ai_client::{AiClient, ModelUsage};
+use crate::output::{self, OutputFormat};
 use crate::utils::{Utils, HistoryEntry};
+use serde::Serialize;
+use std::time::Instant;
 
-pub async fn handle_fix(user_context: &str) -> Result<()> {
-    println!("🔍 Analyzing terminal history for errors...");
+/// What `ai fix --format json` prints
+#[derive(Serialize)]
+struct FixReport {
+    /// The model's whole answer
+    analysis: String,
+    /// Its `## ` sections (Analysis, Root Cause, Solution, Commands)
+    sections: Vec<AnalysisSection>,
+    /// Commands from its code blocks
+    commands: Vec<String>,
+    model: ModelUsage,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize)]
+struct AnalysisSection {
+    title: String,
+    content: String,
+}
+
+pub async fn handle_fix(user_context: &str, dry_run: bool, format: OutputFormat) -> Result<()> {
+    let started = Instant::now();
+    format.status("🔍 Analyzing terminal history for errors...");
     
     // Check zsh configuration for better error tracking
-    check_zsh_configuration();
+    if !format.is_json() {
+        check_zsh_configuration();
+    }
     
     // Get shell history (last 25 commands to give more context)
     let history = match Utils::get_extended_shell_history(25) {
         Ok(hist) => hist,
         Err(e) => {
-            println!("Warning: Could not get extended history ({})", e);
-            println!("Trying basic history...");
+            format.status(format!("Warning: Could not get extended history ({})", e));
+            format.status("Trying basic history...");
             
             match Utils::get_shell_history(25) {
                 Ok(commands) => commands.into_iter().map(|cmd| HistoryEntry {
@@ -22,6 +47,7 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
                     exit_code: None,
                     timestamp: None,
                 }).collect(),
+                Err(e) if format.is_json() => return Err(anyhow!("Could not get command history: {}", e)),
                 Err(e) => {
                     eprintln!("Error: Could not get command history: {}", e);
                     return Ok(());
@@ -31,15 +57,13 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
     };
 
     if history.is_empty() {
-        println!("⚠️  No command history found.");
-        println!();
+        format.status("⚠️  No command history found.\n");
         
         // Try to read startup errors from log file
         match Utils::get_recent_startup_errors() {
             Ok(startup_errors) => {
-                println!("✅ Found recent startup errors in log file!");
-                println!("🔍 Analyzing startup errors automatically...");
-                println!();
+                format.status("✅ Found recent startup errors in log file!");
+                format.status("🔍 Analyzing startup errors automatically...\n");
                 
                 let startup_context = startup_errors.join("\n");
                 let config = Config::load()?;
@@ -54,35 +78,12 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
                 context.push_str(&startup_context);
                 context.push_str("\nNote: These errors were automatically captured during shell startup.\n");
                 
-                println!("🧠 Analyzing startup errors and generating solution...");
+                format.status("🧠 Analyzing startup errors and generating solution...");
                 let ai_response = client.analyze_and_fix_error(&context, user_context).await?;
-                
-                println!("\n{}", ai_response);
-                
-                if let Some(commands) = extract_commands_from_response(&ai_response) {
-                    if !commands.is_empty() {
-                        println!("\n📋 Copying commands to clipboard...");
-                        let commands_text = commands.join("\n");
-                        
-                        match Utils::copy_to_clipboard(&commands_text) {
-                            Ok(()) => {
-                                println!("✅ Commands copied to clipboard!");
-                                println!("💡 You can now paste and execute them in your terminal.");
-                            }
-                            Err(e) => {
-                                println!("❌ Failed to copy to clipboard: {}", e);
-                                println!("💡 Here are the commands to run manually:");
-                                println!("```bash");
-                                for cmd in &commands {
-                                    println!("{}", cmd);
-                                }
-                                println!("```");
-                            }
-                        }
-                    }
-                }
-                
-                return Ok(());
+                return report_analysis(&client, ai_response, dry_run, format, started);
+            }
+            Err(_) if format.is_json() => {
+                return Err(anyhow!("No command history or startup errors found; describe the error: ai fix \"error message here\""));
             }
             Err(_) => {
                 println!("This might be because:");
@@ -105,15 +106,15 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
     
     if shell == "zsh" {
         if !Utils::is_zsh_extended_history_enabled() {
-            show_zsh_tip = true;
-            println!("ℹ️  Note: zsh EXTENDED_HISTORY is not enabled.");
-            println!("   This limits error detection accuracy. Run 'ai setup zsh' for help.");
+            show_zsh_tip = !format.is_json();
+            format.status("ℹ️  Note: zsh EXTENDED_HISTORY is not enabled.");
+            format.status("   This limits error detection accuracy. Run 'ai setup zsh' for help.");
         } else {
-            println!("✅ zsh EXTENDED_HISTORY is enabled - excellent for error tracking!");
+            format.status("✅ zsh EXTENDED_HISTORY is enabled - excellent for error tracking!");
         }
     } else if !Utils::shell_supports_exit_codes() {
-        println!("ℹ️  Note: Your shell ({}) may not provide exit code information.", shell);
-        println!("   For better error detection, consider switching to zsh: 'ai setup zsh'");
+        format.status(format!("ℹ️  Note: Your shell ({}) may not provide exit code information.", shell));
+        format.status("   For better error detection, consider switching to zsh: 'ai setup zsh'");
     }
 
     // Check if this might be a fresh session with startup errors
@@ -168,40 +169,14 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
     }
 
     // Load AI configuration and analyze
-    println!("🤖 Loading AI configuration...");
+    format.status("🤖 Loading AI configuration...");
     let config = Config::load()?;
     let (provider_config, command_config) = config.get_error_analysis_ai_config()?;
     let client = AiClient::new_with_full_config(provider_config.clone(), command_config.clone(), config.git.clone(), config.clone())?;
 
-    println!("🧠 Analyzing error and generating solution...");
+    format.status("🧠 Analyzing error and generating solution...");
     let ai_response = client.analyze_and_fix_error(&context, user_context).await?;
-
-    // Display the analysis
-    println!("\n{}", ai_response);
-
-    // Extract commands from the response
-    if let Some(commands) = extract_commands_from_response(&ai_response) {
-        if !commands.is_empty() {
-            println!("\n📋 Copying commands to clipboard...");
-            let commands_text = commands.join("\n");
-            
-            match Utils::copy_to_clipboard(&commands_text) {
-                Ok(()) => {
-                    println!("✅ Commands copied to clipboard!");
-                    println!("💡 You can now paste and execute them in your terminal.");
-                }
-                Err(e) => {
-                    println!("❌ Failed to copy to clipboard: {}", e);
-                    println!("💡 Here are the commands to run manually:");
-                    println!("```bash");
-                    for cmd in &commands {
-                        println!("{}", cmd);
-                    }
-                    println!("```");
-                }
-            }
-        }
-    }
+    report_analysis(&client, ai_response, dry_run, format, started)?;
 
     // Show zsh setup tip if applicable
     if show_zsh_tip {
@@ -212,6 +187,78 @@ pub async fn handle_fix(user_context: &str) -> Result<()> {
     Ok(())
 }
 
+/// Print the analysis and hand over its commands, or print the JSON result
+fn report_analysis(client: &AiClient, ai_response: String, dry_run: bool, format: OutputFormat, started: Instant) -> Result<()> {
+    let commands = extract_commands_from_response(&ai_response).unwrap_or_default();
+    if format.is_json() {
+        // Scripts get the commands in the result rather than on the clipboard
+        return output::print_json(&FixReport {
+            sections: analysis_sections(&ai_response),
+            analysis: ai_response,
+            commands,
+            model: client.usage(),
+            elapsed_ms: output::elapsed_ms(started),
+        });
+    }
+
+    println!("\n{}", ai_response);
+    deliver_commands(&commands, dry_run);
+    Ok(())
+}
+
+/// The `## Heading` sections of an analysis, in order
+fn analysis_sections(response: &str) -> Vec<AnalysisSection> {
+    let mut sections: Vec<AnalysisSection> = Vec::new();
+    for line in response.lines() {
+        if let Some(title) = line.trim_start().strip_prefix("## ") {
+            sections.push(AnalysisSection { title: title.trim().to_string(), content: String::new() });
+        } else if let Some(section) = sections.last_mut() {
+            section.content.push_str(line);
+            section.content.push('\n');
+        }
+    }
+    for section in &mut sections {
+        section.content = section.content.trim().to_string();
+    }
+    sections
+}
+
+/// Copy suggested commands to the clipboard, or print them in a dry run
+fn deliver_commands(commands: &[String], dry_run: bool) {
+    if commands.is_empty() {
+        return;
+    }
+
+    if dry_run {
+        println!("\n💡 Dry run: commands were not copied to the clipboard:");
+        print_commands(commands);
+        return;
+    }
+
+    println!("\n📋 Copying commands to clipboard...");
+    let commands_text = commands.join("\n");
+    
+    match Utils::copy_to_clipboard(&commands_text) {
+        Ok(()) => {
+            println!("✅ Commands copied to clipboard!");
+            println!("💡 You can now paste and execute them in your terminal.");
+        }
+        Err(e) => {
+            println!("❌ Failed to copy to clipboard: {}", e);
+            println!("💡 Here are the commands to run manually:");
+            print_commands(commands);
+        }
+    }
+}
+
+fn print_commands(commands: &[String]) {
+    println!("```bash");
+    for cmd in commands {
+        println!("{}", cmd);
+    }
+    println!("```");
+}
+
 pub fn extract_commands_from_response(response: &str) -> Option<Vec<String>> {
     let lines: Vec<&str> = response.lines().collect();
     let mut in_code_block = false;
@@ -4,10 +4,19 @@ pub mod commit;
 pub mod push;
 pub mod publish;
 pub mod fix;
+pub mod config;
+pub mod models;
+pub mod doctor;
 
-pub use ask::handle_ask;
-pub use chat::handle_chat;
-pub use commit::handle_commit;
+pub use ask::{handle_ask, AskOptions};
+pub use chat::{handle_chat, handle_chat_export, handle_chat_list, Resume};
+pub use commit::{handle_commit, CommitOptions};
 pub use push::handle_push;
 pub use publish::handle_publish;
-pub use fix::handle_fix;
\ No newline at end of file
+pub use fix::handle_fix;
+pub use config::{
+    handle_config_edit, handle_config_get, handle_config_path, handle_config_set, handle_config_show,
+    handle_config_unset, handle_config_validate,
+};
+pub use models::{handle_models_list, handle_models_ping, handle_models_pull, handle_models_set};
+pub use doctor::handle_doctor;
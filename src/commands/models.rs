@@ -0,0 +1,377 @@
+use anyhow::{anyhow, Result};
+use crate::ai_client::AiClient;
+use crate::config::{CommandAiConfig, Config, ProviderConfig, ProviderKind};
+use crate::ollama_api::{self, OllamaApi};
+use crate::output::{self, OutputFormat};
+use crate::retry;
+use crate::utils::Utils;
+use super::config::{report_issues, target_path};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// What `ai models list --format json` prints
+#[derive(Serialize)]
+struct ModelsReport {
+    models: Vec<ModelReport>,
+}
+
+#[derive(Serialize)]
+struct ModelReport {
+    command: &'static str,
+    provider: String,
+    model: String,
+    fallback: bool,
+    /// `ready`, `missing` or `unavailable`
+    status: &'static str,
+    /// `installed`, `not listed by the provider`, why the provider is unreachable...
+    detail: String,
+}
+
+/// A provider and model one of the commands sends requests to
+pub(super) struct ConfiguredModel {
+    pub command: &'static str,
+    pub provider: String,
+    pub model: String,
+    pub fallback: bool,
+}
+
+impl ConfiguredModel {
+    pub fn label(&self) -> String {
+        format!("{}/{}", self.provider, self.model)
+    }
+
+    pub fn is_ollama(&self, config: &Config) -> bool {
+        config.providers.get(&self.provider)
+            .is_some_and(|provider_config| provider_config.kind(&self.provider) == ProviderKind::Ollama)
+    }
+}
+
+/// Every command's provider and model followed by its fallbacks
+pub(super) fn configured_models(config: &Config) -> Vec<ConfiguredModel> {
+    let mut models = Vec::new();
+    for (command, command_config) in config.command_configs() {
+        models.push(ConfiguredModel {
+            command,
+            provider: command_config.provider.clone(),
+            model: command_config.model.clone(),
+            fallback: false,
+        });
+        for fallback in &command_config.fallback {
+            models.push(ConfiguredModel {
+                command,
+                provider: fallback.provider.clone(),
+                model: fallback.model.clone().unwrap_or_else(|| command_config.model.clone()),
+                fallback: true,
+            });
+        }
+    }
+    models
+}
+
+/// The first line of an error without the icon the Ollama messages start with
+pub(super) fn short_error(error: &anyhow::Error) -> String {
+    retry::first_line(error).trim_start_matches("❌ ").to_string()
+}
+
+/// Whether a configured model can be used
+pub(super) enum ModelStatus {
+    /// Installed on the Ollama server or listed by the provider
+    Ready(&'static str),
+    /// The provider answered but doesn't have the model
+    Missing(&'static str),
+    /// The provider is undefined, misconfigured or unreachable
+    Unavailable(String),
+}
+
+impl ModelStatus {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            ModelStatus::Ready(_) => "✓",
+            ModelStatus::Missing(_) => "⚠️ ",
+            ModelStatus::Unavailable(_) => "❌",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ModelStatus::Ready(status) | ModelStatus::Missing(status) => status.to_string(),
+            ModelStatus::Unavailable(reason) => reason.clone(),
+        }
+    }
+}
+
+/// Model listings of the configured providers; each provider is only asked once
+#[derive(Default)]
+pub(super) struct ModelCatalog {
+    listings: HashMap<String, Result<Vec<String>, String>>,
+}
+
+impl ModelCatalog {
+    /// The provider's models, or why they couldn't be listed
+    pub async fn models(&mut self, config: &Config, provider: &str) -> &Result<Vec<String>, String> {
+        if !self.listings.contains_key(provider) {
+            let listing = match config.providers.get(provider) {
+                Some(provider_config) => AiClient::available_models(provider, provider_config).await
+                    .map_err(|e| short_error(&e)),
+                None => Err("provider is not defined".to_string()),
+            };
+            self.listings.insert(provider.to_string(), listing);
+        }
+        &self.listings[provider]
+    }
+
+    pub async fn status(&mut self, config: &Config, configured: &ConfiguredModel) -> ModelStatus {
+        let models = match self.models(config, &configured.provider).await {
+            Ok(models) => models,
+            Err(e) if config.providers.contains_key(&configured.provider) => {
+                return ModelStatus::Unavailable(format!("unreachable: {}", e));
+            }
+            Err(e) => return ModelStatus::Unavailable(e.clone()),
+        };
+
+        if configured.is_ollama(config) {
+            let wanted = ollama_api::normalize_name(&configured.model);
+            if models.iter().any(|model| ollama_api::normalize_name(model) == wanted) {
+                ModelStatus::Ready("installed")
+            } else {
+                ModelStatus::Missing("not installed")
+            }
+        } else {
+            let wanted = configured.model.trim_start_matches("models/");
+            if models.iter().any(|model| model == wanted) {
+                ModelStatus::Ready("available")
+            } else {
+                ModelStatus::Missing("not listed by the provider")
+            }
+        }
+    }
+}
+
+pub async fn handle_models_list(format: OutputFormat) -> Result<()> {
+    let config = Config::load()?;
+
+    let mut catalog = ModelCatalog::default();
+    if format.is_json() {
+        let mut models = Vec::new();
+        for configured in configured_models(&config) {
+            let status = catalog.status(&config, &configured).await;
+            models.push(ModelReport {
+                command: configured.command,
+                status: match status {
+                    ModelStatus::Ready(_) => "ready",
+                    ModelStatus::Missing(_) => "missing",
+                    ModelStatus::Unavailable(_) => "unavailable",
+                },
+                detail: status.describe(),
+                provider: configured.provider,
+                model: configured.model,
+                fallback: configured.fallback,
+            });
+        }
+        return output::print_json(&ModelsReport { models });
+    }
+
+    let mut current_command = "";
+    for configured in configured_models(&config) {
+        if configured.command != current_command {
+            current_command = configured.command;
+            println!("{}", current_command);
+        }
+
+        let status = catalog.status(&config, &configured).await;
+        let role = if configured.fallback { " (fallback)" } else { "" };
+        let hint = match status {
+            ModelStatus::Missing(_) if configured.is_ollama(&config) => " (run `ai models pull`)",
+            _ => "",
+        };
+        println!("  {} {}{}  {}{}", status.icon(), configured.label(), role, status.describe(), hint);
+    }
+
+    Ok(())
+}
+
+/// Pull `model`, or every configured Ollama model that is missing
+pub async fn handle_models_pull(model: Option<&str>) -> Result<()> {
+    let config = Config::load()?;
+
+    let wanted: Vec<(String, &ProviderConfig, String)> = match model {
+        Some(model) => {
+            let (name, provider_config) = default_ollama_provider(&config)?;
+            vec![(name, provider_config, model.to_string())]
+        }
+        None => {
+            let mut seen = HashSet::new();
+            configured_models(&config)
+                .into_iter()
+                .filter_map(|configured| {
+                    let provider_config = config.providers.get(&configured.provider)?;
+                    (provider_config.kind(&configured.provider) == ProviderKind::Ollama)
+                        .then_some((configured.provider, provider_config, configured.model))
+                })
+                .filter(|(_, provider_config, model)| {
+                    seen.insert((provider_config.base_url.clone(), ollama_api::normalize_name(model)))
+                })
+                .collect()
+        }
+    };
+
+    if wanted.is_empty() {
+        println!("No Ollama models are configured.");
+        return Ok(());
+    }
+
+    let http = reqwest::Client::new();
+    let mut failed = 0;
+    for (provider, provider_config, model) in &wanted {
+        let api = OllamaApi::new(http.clone(), &provider_config.base_url);
+        let result = match api.is_installed(model).await {
+            Ok(true) => {
+                println!("✓ {}/{} is already installed", provider, model);
+                continue;
+            }
+            Ok(false) => {
+                println!("📥 Pulling {} on {}...", model, provider_config.base_url);
+                api.pull_with_progress(model).await
+            }
+            Err(e) => Err(anyhow!("Cannot reach Ollama at {}: {}", provider_config.base_url, e)),
+        };
+
+        match result {
+            Ok(()) => println!("✓ Pulled {}", model),
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} model(s) could not be pulled", failed, wanted.len()));
+    }
+    Ok(())
+}
+
+/// The Ollama provider a command uses, else the first one defined
+fn default_ollama_provider(config: &Config) -> Result<(String, &ProviderConfig)> {
+    let is_ollama = |name: &str| {
+        config.providers.get(name)
+            .filter(|provider_config| provider_config.kind(name) == ProviderKind::Ollama)
+    };
+
+    let mut names: Vec<&String> = config.providers.keys().collect();
+    names.sort();
+    config.command_configs()
+        .iter()
+        .map(|(_, command_config)| &command_config.provider)
+        .chain(names)
+        .find_map(|name| is_ollama(name).map(|provider_config| (name.clone(), provider_config)))
+        .ok_or_else(|| anyhow!("No Ollama provider is configured"))
+}
+
+/// Send a tiny request to every configured provider/model and report how long it took
+pub async fn handle_models_ping() -> Result<()> {
+    let config = Config::load()?;
+
+    let mut seen = HashSet::new();
+    let targets: Vec<ConfiguredModel> = configured_models(&config)
+        .into_iter()
+        .filter(|configured| seen.insert(configured.label()))
+        .collect();
+    let width = targets.iter().map(|configured| configured.label().len()).max().unwrap_or(0);
+
+    println!("Pinging {} model(s)...", targets.len());
+    let mut failed = 0;
+    for configured in &targets {
+        let result = match config.providers.get(&configured.provider) {
+            Some(provider_config) => ping(&config, &configured.provider, provider_config, &configured.model).await,
+            None => Err(anyhow!("provider is not defined")),
+        };
+
+        match result {
+            Ok(latency) => println!("✓ {:<width$}  {} ms", configured.label(), latency.as_millis(), width = width),
+            Err(e) => {
+                println!("❌ {:<width$}  {}", configured.label(), short_error(&e), width = width);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} model(s) did not respond", failed, targets.len()));
+    }
+    Ok(())
+}
+
+async fn ping(config: &Config, provider: &str, provider_config: &ProviderConfig, model: &str) -> Result<std::time::Duration> {
+    let command_config = CommandAiConfig {
+        provider: provider.to_string(),
+        model: model.to_string(),
+        system_prompt: None,
+        fallback: Vec::new(),
+    };
+    AiClient::new(provider_config.clone(), command_config, config.git.clone())?
+        .ping()
+        .await
+}
+
+/// Point a command type at `model` on its current provider, or at a provider and model
+/// picked from everything the configured providers offer
+pub async fn handle_models_set(command_type: &str, model: Option<&str>, project: bool) -> Result<()> {
+    let config = Config::load()?;
+    let (_, command_config) = config.command_configs()
+        .into_iter()
+        .find(|(name, _)| *name == command_type)
+        .ok_or_else(|| anyhow!("Unknown command type: {}", command_type))?;
+
+    let (provider, model) = match model {
+        Some(model) => (command_config.provider.clone(), model.to_string()),
+        None => match pick_model(&config, command_type).await? {
+            Some(choice) => choice,
+            None => {
+                println!("No model selected.");
+                return Ok(());
+            }
+        },
+    };
+    if !config.providers.contains_key(&provider) {
+        return Err(anyhow!("Provider '{}' not found in config", provider));
+    }
+
+    let path = target_path(project)?;
+    // Quoted so names like `7b` or `true` stay strings
+    let quoted = |value: &str| toml::Value::String(value.to_string()).to_string();
+    Config::set_value(&path, &format!("commands.{}.provider", command_type), &quoted(&provider))?;
+    Config::set_value(&path, &format!("commands.{}.model", command_type), &quoted(&model))?;
+    println!("✓ {} now uses {}/{} ({})", command_type, provider, model, path.display());
+    report_issues(&Config::validate()?);
+    Ok(())
+}
+
+/// Let the user choose among the models of every provider that can list them
+async fn pick_model(config: &Config, command_type: &str) -> Result<Option<(String, String)>> {
+    let mut names: Vec<&String> = config.providers.keys().collect();
+    names.sort();
+
+    println!("Fetching available models...");
+    let listings = futures::future::join_all(names.iter().map(|name| {
+        AiClient::available_models(name, &config.providers[*name])
+    }))
+    .await;
+
+    let mut options = Vec::new();
+    for (name, listing) in names.iter().zip(listings) {
+        match listing {
+            Ok(models) => options.extend(models.into_iter().map(|model| format!("{}/{}", name, model))),
+            Err(e) => eprintln!("⚠️  Skipping {}: {}", name, short_error(&e)),
+        }
+    }
+    if options.is_empty() {
+        return Err(anyhow!("None of the configured providers returned any models"));
+    }
+
+    let options: Vec<&str> = options.iter().map(String::as_str).collect();
+    let selected = Utils::select_option(&options, &format!("Model for {}> ", command_type))?;
+    Ok(selected.and_then(|choice| {
+        choice.split_once('/').map(|(provider, model)| (provider.to_string(), model.to_string()))
+    }))
+}
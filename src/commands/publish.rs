@@ -1,9 +1,9 @@
 use anyhow::Result;
 use crate::git_ops::GitOperations;
 use crate::utils::Utils;
-use super::handle_commit;
+use super::{handle_commit, CommitOptions};
 
-pub async fn handle_publish() -> Result<()> {
+pub async fn handle_publish(dry_run: bool) -> Result<()> {
     // Detect project type
     let project_type = Utils::detect_project_type();
     
@@ -27,26 +27,39 @@ pub async fn handle_publish() -> Result<()> {
             println!("Publishing Rust crate to crates.io...");
             
             // Check for uncommitted changes
+            let mut dirty = false;
             if GitOperations::is_git_repo() {
                 let status = GitOperations::get_status()?;
-                if !status.trim().is_empty() {
-                    if Utils::confirm("You have uncommitted changes. Commit them first?")? {
-                        handle_commit(true).await?;
-                    }
+                dirty = !status.trim().is_empty();
+                if dirty && !dry_run && Utils::confirm("You have uncommitted changes. Commit them first?")? {
+                    handle_commit(CommitOptions::all()).await?;
                 }
             }
 
-            // Check cargo login
-            println!("Make sure you're logged into crates.io:");
-            println!("  cargo login");
+            if dry_run {
+                println!("Running cargo publish --dry-run...");
+            } else {
+                // Check cargo login
+                println!("Make sure you're logged into crates.io:");
+                println!("  cargo login");
+            }
             
-            if Utils::confirm("Proceed with cargo publish?")? {
+            if dry_run || Utils::confirm("Proceed with cargo publish?")? {
                 use std::process::Command;
+                let mut args = vec!["publish"];
+                if dry_run {
+                    args.push("--dry-run");
+                    if dirty {
+                        args.push("--allow-dirty");
+                    }
+                }
                 let output = Command::new("cargo")
-                    .args(["publish"])
+                    .args(&args)
                     .output()?;
                     
-                if output.status.success() {
+                if output.status.success() && dry_run {
+                    println!("✓ Dry run passed; the crate is ready to publish.");
+                } else if output.status.success() {
                     println!("✓ Published successfully to crates.io!");
                 } else {
                     let error = String::from_utf8_lossy(&output.stderr);
@@ -1,18 +1,63 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use crate::git_ops::GitOperations;
+use crate::output::{self, OutputFormat};
 use crate::utils::Utils;
-use super::handle_commit;
+use serde::Serialize;
+use std::time::Instant;
+use super::{handle_commit, CommitOptions};
+
+/// What `ai push --format json` prints
+#[derive(Serialize)]
+struct PushReport {
+    status: PushStatus,
+    force: bool,
+    /// What git printed
+    output: String,
+    /// The branch's upstream after the push
+    upstream: Option<Upstream>,
+    /// Changes left out because they weren't committed
+    uncommitted_changes: bool,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PushStatus {
+    Pushed,
+    UpToDate,
+    DryRun,
+}
+
+#[derive(Serialize)]
+struct Upstream {
+    /// e.g. `origin/main`
+    name: String,
+    ahead: usize,
+    behind: usize,
+}
+
+pub async fn handle_push(force: bool, dry_run: bool, format: OutputFormat) -> Result<()> {
+    let started = Instant::now();
 
-pub async fn handle_push(force: bool) -> Result<()> {
     // Check if we're in a git repository
     if !GitOperations::is_git_repo() {
+        if format.is_json() {
+            return Err(anyhow!("Not in a git repository"));
+        }
         println!("Error: Not in a git repository");
         return Ok(());
     }
 
-    // Check for uncommitted changes
+    // Check for uncommitted changes; JSON output never prompts, so they stay out of the push
     let status = GitOperations::get_status()?;
-    if !status.trim().is_empty() {
+    let uncommitted_changes = !status.trim().is_empty();
+    if uncommitted_changes && format.is_json() {
+        format.status("You have uncommitted changes (not pushed):");
+        format.status(&status);
+    } else if uncommitted_changes && dry_run {
+        println!("You have uncommitted changes (not included in a dry run):");
+        println!("{}", status);
+    } else if uncommitted_changes {
         println!("You have uncommitted changes:");
         println!("{}", status);
         
@@ -35,10 +80,10 @@ pub async fn handle_push(force: bool) -> Result<()> {
             Some(choice) => {
                 match choice.as_str() {
                     choice if choice.contains("Commit staged") => {
-                        handle_commit(false).await?;
+                        handle_commit(CommitOptions::default()).await?;
                     }
                     choice if choice.contains("Commit all") => {
-                        handle_commit(true).await?;
+                        handle_commit(CommitOptions::all()).await?;
                     }
                     choice if choice.contains("Push anyway") => {
                         // Continue with push
@@ -58,7 +103,13 @@ pub async fn handle_push(force: bool) -> Result<()> {
 
     // Check if remote exists
     if !GitOperations::has_remote() {
+        if format.is_json() {
+            return Err(anyhow!("No remote repository configured"));
+        }
         println!("No remote repository configured.");
+        if dry_run {
+            return Ok(());
+        }
         let mut available_tools = Vec::new();
         
         if Utils::is_command_available("gh") {
@@ -183,13 +234,34 @@ pub async fn handle_push(force: bool) -> Result<()> {
     }
 
     // Perform the push
-    let push_result = if force {
+    let push_result = if dry_run {
+        GitOperations::push_dry_run(force)
+    } else if force {
         GitOperations::push_force()
     } else {
         GitOperations::push()
     };
 
     match push_result {
+        Ok(git_output) if format.is_json() => {
+            let status = if dry_run {
+                PushStatus::DryRun
+            } else if git_output.contains("Everything up-to-date") {
+                PushStatus::UpToDate
+            } else {
+                PushStatus::Pushed
+            };
+            let upstream = GitOperations::get_upstream_status().ok()
+                .map(|(name, ahead, behind)| Upstream { name, ahead, behind });
+            output::print_json(&PushReport {
+                status,
+                force,
+                output: git_output,
+                upstream,
+                uncommitted_changes,
+                elapsed_ms: output::elapsed_ms(started),
+            })?;
+        }
         Ok(output) => {
             if output.contains("Everything up-to-date") {
                 println!("Everything up-to-date");
@@ -197,16 +269,15 @@ pub async fn handle_push(force: bool) -> Result<()> {
                 println!("{}", output);
             }
         }
+        Err(e) if format.is_json() => return Err(e.context("Push failed")),
         Err(e) => {
             println!("Push failed: {}", e);
             
             // Try setting upstream if no upstream is configured
-            if !GitOperations::has_upstream() {
-                if Utils::confirm("Set upstream branch and push?")? {
-                    let branch = GitOperations::get_current_branch()?;
-                    GitOperations::set_upstream("origin", &branch)?;
-                    println!("✓ Upstream branch '{}' set and pushed successfully!", branch);
-                }
+            if !dry_run && !GitOperations::has_upstream() && Utils::confirm("Set upstream branch and push?")? {
+                let branch = GitOperations::get_current_branch()?;
+                GitOperations::set_upstream("origin", &branch)?;
+                println!("✓ Upstream branch '{}' set and pushed successfully!", branch);
             }
         }
     }
@@ -0,0 +1,560 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Width that commit message bodies are wrapped to
+const BODY_WRAP_WIDTH: usize = 72;
+
+/// Common commit verbs; a description starting with one of their -s, -ed or -ing forms
+/// ("adds", "fixed", "updating") is not in the imperative mood
+const COMMON_VERBS: &[&str] = &[
+    "add", "adjust", "allow", "avoid", "bump", "change", "clean", "create", "delete", "document",
+    "drop", "enable", "disable", "ensure", "extract", "fix", "handle", "implement", "improve",
+    "introduce", "make", "merge", "move", "optimize", "prevent", "reduce", "refactor", "remove",
+    "rename", "replace", "revert", "simplify", "stop", "support", "test", "update", "upgrade", "use",
+];
+
+/// Shape of the commit messages `ai commit` produces
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommitStyle {
+    /// `type: description` on a single line
+    #[default]
+    OneLine,
+    /// `type: description`, a blank line, and a body explaining why
+    SubjectBody,
+    /// `type(scope): description`, body, and optional `Refs:`/`BREAKING CHANGE:` footers
+    Conventional,
+}
+
+impl CommitStyle {
+    pub fn is_multi_line(self) -> bool {
+        !matches!(self, CommitStyle::OneLine)
+    }
+
+    /// Instructions appended to the commit prompt for this style
+    pub fn instructions(self, allowed_types: &[String], max_subject_length: usize) -> String {
+        let types = allowed_types.join(", ");
+        match self {
+            CommitStyle::OneLine => format!(
+                "Use one of these types: {}. Keep the whole message on one line of at most {} characters.",
+                types, max_subject_length
+            ),
+            CommitStyle::SubjectBody => format!(
+                "Instead of a single line, reply with a subject line of the form <type>: <description> \
+                 (types: {}; at most {} characters), then a blank line, then a body wrapped at {} characters \
+                 that explains why the change was made. Optionally end with footers such as \
+                 \"Refs: #123\" or \"BREAKING CHANGE: <what breaks>\".",
+                types, max_subject_length, BODY_WRAP_WIDTH
+            ),
+            CommitStyle::Conventional => format!(
+                "Instead of a single line, reply with a full Conventional Commits message: a subject line \
+                 of the form <type>(<scope>): <description> (types: {}; at most {} characters; the scope \
+                 is the main module or directory touched), then a blank line, then a body wrapped at {} \
+                 characters that explains why the change was made. Add a \"BREAKING CHANGE: <what breaks>\" \
+                 footer for incompatible changes and \"Refs: <ticket>\" footers when a ticket applies.",
+                types, max_subject_length, BODY_WRAP_WIDTH
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CommitStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CommitStyle::OneLine => "one-line",
+            CommitStyle::SubjectBody => "subject-body",
+            CommitStyle::Conventional => "conventional",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A commit message split into subject, body and footers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitMessage {
+    pub subject: String,
+    pub body: Option<String>,
+    pub footers: Vec<String>,
+}
+
+/// A way a message breaks its commit style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub message: String,
+    /// The subject is missing or malformed, or its type isn't allowed: the message can't be
+    /// committed as-is. Other violations (length, mood, missing body) are only warnings.
+    pub blocking: bool,
+}
+
+impl Violation {
+    fn blocking(message: impl Into<String>) -> Self {
+        Self { message: message.into(), blocking: true }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self { message: message.into(), blocking: false }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The `type(scope)!: description` parts of a subject line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectParts<'a> {
+    pub commit_type: &'a str,
+    pub scope: Option<&'a str>,
+    pub breaking: bool,
+    pub description: &'a str,
+}
+
+impl CommitMessage {
+    /// Clean up raw model output: markdown fences, "Here is your commit message:"
+    /// preambles and surrounding quotes are removed
+    pub fn sanitize(raw: &str) -> String {
+        let mut text = raw.trim().replace("\r\n", "\n");
+
+        // Keep only the contents of the first fenced block, if any
+        if let Some(start) = text.find("```") {
+            let after_fence = &text[start + 3..];
+            let content_start = after_fence.find('\n').map(|i| i + 1).unwrap_or(0);
+            let content = &after_fence[content_start..];
+            let content = match content.find("```") {
+                Some(end) => &content[..end],
+                None => content,
+            };
+            text = content.trim().to_string();
+        }
+
+        // Drop preamble lines until something that looks like a subject line
+        let mut lines: Vec<&str> = text.lines().collect();
+        while lines.len() > 1 {
+            let first = lines[0].trim();
+            let lower = first.to_lowercase();
+            let is_preamble = first.is_empty()
+                || (Self::parse(first).subject_parts().is_none()
+                    && (first.ends_with(':')
+                        || lower.contains("commit message")
+                        || lower.starts_with("here")
+                        || lower.starts_with("sure")));
+            if !is_preamble {
+                break;
+            }
+            lines.remove(0);
+        }
+        let mut text = lines.join("\n").trim().to_string();
+
+        // "Commit message: feat: ..." on a single line
+        for prefix in ["commit message:", "commit:"] {
+            if text.to_lowercase().starts_with(prefix) {
+                text = text[prefix.len()..].trim_start().to_string();
+            }
+        }
+
+        // Surrounding quotes or backticks
+        for quote in ['"', '\'', '`'] {
+            if text.len() > 1 && text.starts_with(quote) && text.ends_with(quote) {
+                text = text[1..text.len() - 1].trim().to_string();
+            }
+        }
+
+        text
+    }
+
+    /// Fix problems that don't need the model: type case, a capitalised
+    /// description, a trailing period, and extra lines in one-line style
+    pub fn repair(&mut self, style: CommitStyle, allowed_types: &[String]) {
+        let repaired = self.subject_parts().map(|parts| {
+            let lower_type = parts.commit_type.to_lowercase();
+            let commit_type = if allowed_types.contains(&lower_type) {
+                lower_type
+            } else {
+                parts.commit_type.to_string()
+            };
+
+            let mut description = parts.description.trim_end_matches('.').to_string();
+            if starts_capitalised(&description) {
+                let mut chars = description.chars();
+                let first = chars.next().map(|c| c.to_lowercase().to_string()).unwrap_or_default();
+                description = first + chars.as_str();
+            }
+
+            format!(
+                "{}{}{}: {}",
+                commit_type,
+                parts.scope.map(|s| format!("({})", s)).unwrap_or_default(),
+                if parts.breaking { "!" } else { "" },
+                description
+            )
+        });
+        self.subject = match repaired {
+            Some(subject) => subject,
+            None => self.subject.trim_end_matches('.').to_string(),
+        };
+
+        if !style.is_multi_line() {
+            self.body = None;
+            self.footers.clear();
+        }
+    }
+
+    /// Split raw message text into subject, body paragraphs and trailing footers
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let (subject, rest) = match text.split_once('\n') {
+            Some((subject, rest)) => (subject.trim(), rest.trim()),
+            None => (text, ""),
+        };
+
+        let mut paragraphs: Vec<String> = rest
+            .split("\n\n")
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        // The last paragraph holds footers if every line looks like one
+        let mut footers = Vec::new();
+        if let Some(last) = paragraphs.last() {
+            if last.lines().all(Self::is_footer_line) {
+                footers = last.lines().map(|l| l.trim().to_string()).collect();
+                paragraphs.pop();
+            }
+        }
+
+        let body = if paragraphs.is_empty() {
+            None
+        } else {
+            Some(paragraphs.join("\n\n"))
+        };
+
+        Self {
+            subject: subject.to_string(),
+            body,
+            footers,
+        }
+    }
+
+    /// Whether a line is a git trailer / conventional-commit footer
+    fn is_footer_line(line: &str) -> bool {
+        let line = line.trim();
+        if line.starts_with("BREAKING CHANGE: ") || line.starts_with("BREAKING-CHANGE: ") {
+            return true;
+        }
+        let token_end = line.find(": ").or_else(|| line.find(" #"));
+        match token_end {
+            Some(end) => {
+                let token = &line[..end];
+                !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }
+            None => false,
+        }
+    }
+
+    /// Parse the subject as `type(scope)!: description`
+    pub fn subject_parts(&self) -> Option<SubjectParts<'_>> {
+        let (head, description) = self.subject.split_once(": ")?;
+        let (head, breaking) = match head.strip_suffix('!') {
+            Some(head) => (head, true),
+            None => (head, false),
+        };
+        let (commit_type, scope) = match head.split_once('(') {
+            Some((commit_type, scope)) => (commit_type, Some(scope.strip_suffix(')')?)),
+            None => (head, None),
+        };
+        if commit_type.is_empty() || !commit_type.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        Some(SubjectParts {
+            commit_type,
+            scope,
+            breaking,
+            description: description.trim(),
+        })
+    }
+
+    /// Check the message against a commit style; returns one entry per violation
+    pub fn validate(&self, style: CommitStyle, allowed_types: &[String], max_subject_length: usize) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if self.subject.is_empty() {
+            violations.push(Violation::blocking("the subject line is empty"));
+            return violations;
+        }
+
+        let subject_length = self.subject.chars().count();
+        if subject_length > max_subject_length {
+            violations.push(Violation::warning(format!(
+                "the subject line is {} characters long (limit {})",
+                subject_length, max_subject_length
+            )));
+        }
+
+        match self.subject_parts() {
+            None => violations.push(Violation::blocking(format!(
+                "the subject line must look like \"<type>{}: <description>\"",
+                if style == CommitStyle::Conventional { "(<scope>)" } else { "" }
+            ))),
+            Some(parts) => {
+                if !allowed_types.iter().any(|t| t == parts.commit_type) {
+                    violations.push(Violation::blocking(format!(
+                        "type \"{}\" is not one of: {}",
+                        parts.commit_type,
+                        allowed_types.join(", ")
+                    )));
+                }
+                if parts.description.is_empty() {
+                    violations.push(Violation::blocking("the description is empty"));
+                } else if starts_capitalised(parts.description) {
+                    violations.push(Violation::warning("the description must start with a lowercase letter"));
+                }
+                if parts.description.ends_with('.') {
+                    violations.push(Violation::warning("the subject line must not end with a period"));
+                }
+                if let Some(word) = non_imperative_word(parts.description) {
+                    violations.push(Violation::warning(format!(
+                        "use the imperative mood: \"{}\" should be a command like \"add\" (not \"added\", \"adds\" or \"adding\")",
+                        word
+                    )));
+                }
+                if style == CommitStyle::Conventional && parts.scope.is_none_or(|s| s.is_empty()) {
+                    violations.push(Violation::warning("the subject line needs a (scope) after the type"));
+                }
+            }
+        }
+
+        if style.is_multi_line() {
+            if self.body.is_none() {
+                violations.push(Violation::warning("the message needs a body explaining why the change was made"));
+            }
+        } else if self.body.is_some() || !self.footers.is_empty() {
+            violations.push(Violation::warning("the message must be a single line"));
+        }
+
+        violations
+    }
+}
+
+impl fmt::Display for CommitMessage {
+    /// Render the message with the body re-wrapped to 72 columns
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.subject)?;
+        if let Some(body) = &self.body {
+            write!(f, "\n\n{}", wrap_body(body, BODY_WRAP_WIDTH))?;
+        }
+        if !self.footers.is_empty() {
+            write!(f, "\n\n{}", self.footers.join("\n"))?;
+        }
+        Ok(())
+    }
+}
+
+/// "Add x" is capitalised; acronyms such as "README" or "API" are not
+fn starts_capitalised(description: &str) -> bool {
+    let mut chars = description.chars();
+    match (chars.next(), chars.next()) {
+        (Some(first), Some(second)) => first.is_uppercase() && !second.is_uppercase(),
+        (Some(first), None) => first.is_uppercase(),
+        _ => false,
+    }
+}
+
+/// Return the first word of the description if it is a common verb that is not in the
+/// imperative mood ("added", "adding", "adds"). Other words are left alone, so
+/// "missing header" or "streaming support" pass.
+fn non_imperative_word(description: &str) -> Option<&str> {
+    let word = description.split_whitespace().next()?;
+    let lower = word.to_lowercase();
+    COMMON_VERBS.iter()
+        .any(|verb| inflections(verb).contains(&lower))
+        .then_some(word)
+}
+
+/// The -s, -ed and -ing forms of `verb` ("fixes", "fixed", "fixing"). Verbs that may
+/// double their last consonant ("dropped") get both spellings.
+fn inflections(verb: &str) -> Vec<String> {
+    let mut forms = Vec::new();
+    if verb.ends_with(['s', 'x', 'z']) || verb.ends_with("ch") || verb.ends_with("sh") {
+        forms.push(format!("{}es", verb));
+    } else if let Some(stem) = verb.strip_suffix('y') {
+        forms.push(format!("{}ies", stem));
+    } else {
+        forms.push(format!("{}s", verb));
+    }
+
+    if let Some(stem) = verb.strip_suffix('e') {
+        forms.push(format!("{}d", verb));
+        forms.push(format!("{}ing", stem));
+    } else if let Some(stem) = verb.strip_suffix('y') {
+        forms.push(format!("{}ied", stem));
+        forms.push(format!("{}ing", verb));
+    } else {
+        forms.push(format!("{}ed", verb));
+        forms.push(format!("{}ing", verb));
+    }
+
+    // Consonant-vowel-consonant endings: "drop" -> "dropped", "stop" -> "stopping"
+    let chars: Vec<char> = verb.chars().collect();
+    let is_vowel = |c: char| "aeiou".contains(c);
+    if let [.., a, b, c] = chars[..] {
+        if !is_vowel(a) && is_vowel(b) && !is_vowel(c) && !"wxy".contains(c) {
+            forms.push(format!("{}{}ed", verb, c));
+            forms.push(format!("{}{}ing", verb, c));
+        }
+    }
+    forms
+}
+
+/// Re-wrap body paragraphs; list items keep their marker and get a hanging indent
+fn wrap_body(body: &str, width: usize) -> String {
+    let mut out = Vec::new();
+    for paragraph in body.split("\n\n") {
+        let lines: Vec<&str> = paragraph.lines().map(str::trim).collect();
+        let is_list = lines.iter().any(|l| l.starts_with("- ") || l.starts_with("* "));
+
+        if is_list {
+            // Group continuation lines with their list item
+            let mut items: Vec<String> = Vec::new();
+            for line in lines {
+                if line.starts_with("- ") || line.starts_with("* ") || items.is_empty() {
+                    items.push(line.to_string());
+                } else if let Some(last) = items.last_mut() {
+                    last.push(' ');
+                    last.push_str(line);
+                }
+            }
+            let wrapped: Vec<String> = items
+                .iter()
+                .map(|item| wrap_words(item, width, "  "))
+                .collect();
+            out.push(wrapped.join("\n"));
+        } else {
+            out.push(wrap_words(&lines.join(" "), width, ""));
+        }
+    }
+    out.join("\n\n")
+}
+
+fn wrap_words(text: &str, width: usize, continuation_indent: &str) -> String {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut current));
+            current.push_str(continuation_indent);
+        } else if !current.is_empty() && !current.ends_with(' ') {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.trim().is_empty() {
+        lines.push(current);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types() -> Vec<String> {
+        ["feat", "fix", "docs", "refactor", "chore"].iter().map(|t| t.to_string()).collect()
+    }
+
+    fn violations(message: &str, style: CommitStyle) -> Vec<String> {
+        CommitMessage::parse(message).validate(style, &types(), 72)
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    fn blocking(message: &str) -> Vec<String> {
+        CommitMessage::parse(message).validate(CommitStyle::Conventional, &types(), 20)
+            .into_iter()
+            .filter(|violation| violation.blocking)
+            .map(|violation| violation.message)
+            .collect()
+    }
+
+    #[test]
+    fn flags_non_imperative_commit_verbs() {
+        for subject in [
+            "feat: added streaming support",
+            "feat: adds streaming support",
+            "fix: fixing the header",
+            "fix: fixes the header",
+            "refactor: removed unused import",
+            "refactor: dropped the old parser",
+            "chore: updating dependencies",
+            "chore: simplified the config",
+        ] {
+            let found = violations(subject, CommitStyle::OneLine);
+            assert!(found.iter().any(|v| v.contains("imperative mood")), "{}: {:?}", subject, found);
+        }
+    }
+
+    #[test]
+    fn accepts_descriptions_that_start_with_other_words() {
+        for subject in [
+            "fix: missing header",
+            "feat: streaming support",
+            "chore: unused import",
+            "refactor: nested config",
+            "fix: shared state",
+            "feat: embedded assets",
+            "fix: string escaping",
+            "feat: add streaming support",
+            "fix: remove unused import",
+            "refactor: handle nested config",
+            "fix: update shared state",
+            "feat: support embedded assets",
+        ] {
+            assert_eq!(violations(subject, CommitStyle::OneLine), Vec::<String>::new(), "{}", subject);
+        }
+    }
+
+    #[test]
+    fn validate_reports_style_problems() {
+        assert_eq!(violations("", CommitStyle::OneLine), ["the subject line is empty"]);
+        assert_eq!(
+            violations("wip: Stuff.", CommitStyle::OneLine),
+            [
+                "type \"wip\" is not one of: feat, fix, docs, refactor, chore",
+                "the description must start with a lowercase letter",
+                "the subject line must not end with a period",
+            ]
+        );
+        assert_eq!(
+            violations("add a thing", CommitStyle::OneLine),
+            ["the subject line must look like \"<type>: <description>\""]
+        );
+        assert_eq!(
+            violations("feat: add a thing", CommitStyle::Conventional),
+            [
+                "the subject line needs a (scope) after the type",
+                "the message needs a body explaining why the change was made",
+            ]
+        );
+        assert_eq!(
+            violations("feat: add a thing\n\nBecause.", CommitStyle::OneLine),
+            ["the message must be a single line"]
+        );
+        let long = format!("feat: {}", "x".repeat(80));
+        assert_eq!(
+            violations(&long, CommitStyle::OneLine),
+            ["the subject line is 86 characters long (limit 72)"]
+        );
+        assert!(violations("feat(cli): add a thing\n\nBecause.", CommitStyle::Conventional).is_empty());
+    }
+
+    #[test]
+    fn only_structural_violations_block() {
+        assert_eq!(blocking(""), ["the subject line is empty"]);
+        assert_eq!(blocking("add a thing"), ["the subject line must look like \"<type>(<scope>): <description>\""]);
+        assert_eq!(blocking("wip: add a thing"), ["type \"wip\" is not one of: feat, fix, docs, refactor, chore"]);
+        // Too long, capitalised, past tense, with a period and no scope or body: all warnings
+        assert!(blocking("feat: Added a much longer description.").is_empty());
+    }
+}
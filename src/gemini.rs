@@ -0,0 +1,427 @@
+use crate::retry::HttpError;
+use crate::streaming::{self, StreamEvent, TokenStream};
+use ai::chat_completions::ChatCompletionRequest;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const DEFAULT_GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+
+/// Finish reasons that mean the reply was withheld rather than completed
+const BLOCKED_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+
+/// Client for the Gemini `generateContent` API
+#[derive(Clone)]
+pub struct GeminiClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    content: Option<Content>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Content {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Part {
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SafetyRating {
+    category: String,
+    #[serde(default)]
+    probability: String,
+    #[serde(default)]
+    blocked: bool,
+}
+
+/// `{"error": {"code": 400, "message": "...", "status": "INVALID_ARGUMENT"}}`
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    status: String,
+    message: String,
+}
+
+impl GeminiClient {
+    pub fn new(http: reqwest::Client, base_url: &str, api_key: &str, max_tokens: Option<u32>) -> Self {
+        let base_url = if base_url.is_empty() { DEFAULT_GEMINI_BASE_URL } else { base_url };
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            max_tokens,
+        }
+    }
+
+    /// Send a request and return the text of the reply
+    pub async fn generate_content(&self, request: &ChatCompletionRequest) -> Result<String> {
+        let response = self.send(request, "generateContent").await?;
+        let body: GenerateContentResponse = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Gemini: {}", e))?;
+
+        let text = response_text(body)?;
+        if text.is_empty() {
+            return Err(anyhow!("Gemini returned an empty reply"));
+        }
+        Ok(text)
+    }
+
+    /// Send a request and stream the reply's text
+    pub async fn stream_generate_content(&self, request: &ChatCompletionRequest) -> Result<TokenStream> {
+        let response = self.send(request, "streamGenerateContent?alt=sse").await?;
+
+        Ok(streaming::sse_tokens(response.bytes_stream(), parse_stream_event))
+    }
+
+    /// Models that support `generateContent` (`GET /v1beta/models`)
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.http
+            .get(format!("{}/v1beta/models?pageSize=1000", self.base_url))
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Gemini API error: {:#}", anyhow::Error::from(e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpError::new(status.as_u16(), &headers, api_error(status.as_u16(), &text)).into());
+        }
+
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Gemini: {}", e))?;
+        Ok(body["models"]
+            .as_array()
+            .map(|models| {
+                models.iter()
+                    .filter(|m| m["supportedGenerationMethods"]
+                        .as_array()
+                        .is_none_or(|methods| methods.iter().any(|method| method == "generateContent")))
+                    .filter_map(|m| m["name"].as_str())
+                    .map(|name| name.trim_start_matches("models/").to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn send(&self, request: &ChatCompletionRequest, method: &str) -> Result<reqwest::Response> {
+        let body = self.request_body(request)?;
+        let model = request_model(request)?;
+
+        let response = self.http
+            .post(format!("{}/v1beta/models/{}:{}", self.base_url, model, method))
+            .header("x-goog-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Gemini API error: {:#}", anyhow::Error::from(e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpError::new(status.as_u16(), &headers, api_error(status.as_u16(), &text)).into());
+        }
+
+        Ok(response)
+    }
+
+    /// Convert an OpenAI-style request: assistant turns become `model` turns and
+    /// system messages become the `systemInstruction`
+    fn request_body(&self, request: &ChatCompletionRequest) -> Result<Value> {
+        let request = serde_json::to_value(request)?;
+
+        let mut system = Vec::new();
+        let mut contents: Vec<Value> = Vec::new();
+        for message in request["messages"].as_array().cloned().unwrap_or_default() {
+            let text = message_text(&message["content"]);
+            let role = match message["role"].as_str().unwrap_or("user") {
+                "system" | "developer" => {
+                    system.push(json!({ "text": text }));
+                    continue;
+                }
+                "assistant" => "model",
+                _ => "user",
+            };
+
+            // Consecutive turns from the same side are sent as one turn with several parts
+            match contents.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(parts) = last["parts"].as_array_mut() {
+                        parts.push(json!({ "text": text }));
+                    }
+                }
+                _ => contents.push(json!({ "role": role, "parts": [{ "text": text }] })),
+            }
+        }
+
+        let mut body = json!({ "contents": contents });
+        if !system.is_empty() {
+            body["systemInstruction"] = json!({ "parts": system });
+        }
+
+        let mut generation_config = serde_json::Map::new();
+        if let Some(max_tokens) = self.max_tokens {
+            generation_config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+        }
+        if let Some(temperature) = request.get("temperature").filter(|t| !t.is_null()) {
+            generation_config.insert("temperature".to_string(), temperature.clone());
+        }
+        if !generation_config.is_empty() {
+            body["generationConfig"] = Value::Object(generation_config);
+        }
+        Ok(body)
+    }
+}
+
+fn request_model(request: &ChatCompletionRequest) -> Result<String> {
+    let request = serde_json::to_value(request)?;
+    let model = request["model"].as_str().unwrap_or_default();
+    // Accept both "gemini-1.5-pro" and the API's "models/gemini-1.5-pro"
+    Ok(model.trim_start_matches("models/").to_string())
+}
+
+/// Text of an OpenAI-style message content: a string or a list of text parts
+fn message_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Extract the reply text, turning safety blocks into errors that say what was blocked
+fn response_text(response: GenerateContentResponse) -> Result<String> {
+    if let Some(feedback) = &response.prompt_feedback {
+        if let Some(reason) = &feedback.block_reason {
+            return Err(anyhow!(
+                "Gemini blocked the prompt ({}){}",
+                reason,
+                describe_ratings(&feedback.safety_ratings)
+            ));
+        }
+    }
+
+    let Some(candidate) = response.candidates.into_iter().next() else {
+        return Ok(String::new());
+    };
+
+    if let Some(reason) = candidate.finish_reason.as_deref() {
+        if BLOCKED_FINISH_REASONS.contains(&reason) {
+            return Err(anyhow!(
+                "Gemini withheld the reply ({}){}",
+                reason,
+                describe_ratings(&candidate.safety_ratings)
+            ));
+        }
+    }
+
+    Ok(candidate.content
+        .map(|content| content.parts.into_iter().filter_map(|part| part.text).collect())
+        .unwrap_or_default())
+}
+
+/// ": flagged HARM_CATEGORY_X (HIGH)" for the ratings that caused a block
+fn describe_ratings(ratings: &[SafetyRating]) -> String {
+    let flagged: Vec<String> = ratings
+        .iter()
+        .filter(|rating| rating.blocked || matches!(rating.probability.as_str(), "MEDIUM" | "HIGH"))
+        .map(|rating| format!("{} ({})", rating.category, rating.probability))
+        .collect();
+
+    if flagged.is_empty() {
+        String::new()
+    } else {
+        format!(": flagged {}", flagged.join(", "))
+    }
+}
+
+/// Turn a Gemini error response into a readable error
+fn api_error(status: u16, body: &str) -> anyhow::Error {
+    match serde_json::from_str::<ErrorBody>(body) {
+        Ok(body) => anyhow!("Gemini API error ({} {}): {}", body.error.status, status, body.error.message),
+        Err(_) => anyhow!("Gemini API error {}: {}", status, body.trim()),
+    }
+}
+
+/// Parse the data payload of one server-sent event from a streaming response
+pub fn parse_stream_event(data: &str) -> Result<StreamEvent> {
+    if let Ok(error) = serde_json::from_str::<ErrorBody>(data) {
+        return Err(anyhow!("Gemini API error ({}): {}", error.error.status, error.error.message));
+    }
+
+    let chunk: GenerateContentResponse = serde_json::from_str(data)
+        .map_err(|e| anyhow!("Invalid stream chunk from Gemini: {} ({})", e, data))?;
+    // The stream simply ends after the last chunk; there is no end marker
+    let text = response_text(chunk)?;
+    if text.is_empty() {
+        Ok(StreamEvent::Skip)
+    } else {
+        Ok(StreamEvent::Token(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use ai::chat_completions::{ChatCompletionMessage, ChatCompletionRequestBuilder};
+    use futures::StreamExt;
+    use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequestBuilder::default()
+            .model("models/gemini-2.5-flash")
+            .messages(vec![
+                ChatCompletionMessage::System("Be brief.".to_string().into()),
+                ChatCompletionMessage::User("Hi".to_string().into()),
+            ])
+            .build()
+            .unwrap()
+    }
+
+    fn client(server: &MockServer) -> GeminiClient {
+        GeminiClient::new(reqwest::Client::new(), &server.uri(), "test-key", None)
+    }
+
+    #[test]
+    fn parses_stream_chunks() {
+        assert_eq!(
+            parse_stream_event(r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Gu"}]}}]}"#).unwrap(),
+            StreamEvent::Token("Gu".to_string())
+        );
+        assert_eq!(
+            parse_stream_event(r#"{"candidates":[{"content":{"parts":[]},"finishReason":"STOP"}]}"#).unwrap(),
+            StreamEvent::Skip
+        );
+
+        let error = parse_stream_event(r#"{"error":{"code":503,"message":"The model is overloaded.","status":"UNAVAILABLE"}}"#)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Gemini API error (UNAVAILABLE): The model is overloaded.");
+
+        let blocked = parse_stream_event(
+            r#"{"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[{"category":"HARM_CATEGORY_HARASSMENT","probability":"HIGH"}]}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            blocked.to_string(),
+            "Gemini blocked the prompt (SAFETY): flagged HARM_CATEGORY_HARASSMENT (HIGH)"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_the_key_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/gemini-2.5-flash:generateContent"))
+            .and(header("x-goog-api-key", "test-key"))
+            .and(body_partial_json(json!({ "systemInstruction": { "parts": [{ "text": "Be brief." }] } })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Hallo" }] }, "finishReason": "STOP" }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(client(&server).generate_content(&request()).await.unwrap(), "Hallo");
+    }
+
+    #[tokio::test]
+    async fn maps_error_responses_to_http_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/gemini-2.5-flash:generateContent"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "error": { "code": 403, "message": "API key not valid.", "status": "PERMISSION_DENIED" },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/gemini-2.5-flash:streamGenerateContent"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let error = client(&server).generate_content(&request()).await.unwrap_err();
+        assert!(!retry::is_transient(&error));
+        assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, 403);
+        assert_eq!(error.to_string(), "Gemini API error (PERMISSION_DENIED 403): API key not valid.");
+
+        // An empty 503 is still worth retrying
+        let error = client(&server).stream_generate_content(&request()).await.err().unwrap();
+        assert!(retry::is_transient(&error));
+    }
+
+    #[tokio::test]
+    async fn streams_server_sent_events() {
+        let server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Gu\"}]}}]}\r\n\r\n",
+            "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"ten Tag\"}]},\"finishReason\":\"STOP\"}]}\r\n\r\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/gemini-2.5-flash:streamGenerateContent"))
+            .and(query_param("alt", "sse"))
+            .and(header("x-goog-api-key", "test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let stream = client(&server).stream_generate_content(&request()).await.unwrap();
+        let tokens: Vec<String> = stream.map(|token| token.unwrap()).collect().await;
+        assert_eq!(tokens, vec!["Gu", "ten Tag"]);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::tokens::{self, DiffBudget};
 use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
 use std::process::Command;
 
 /// Represents a segment of diff content for parallel processing
@@ -8,23 +10,62 @@ pub struct DiffSegment {
     pub content: String,       // Complete diff content for this segment
     #[allow(dead_code)]
     pub char_count: usize,     // Character count for this segment (used for debugging/monitoring)
+    #[allow(dead_code)]
+    pub token_count: usize,    // Estimated tokens in this segment
 }
 
 /// Summary of changes for a single file generated by AI
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FileSummary {
     pub filename: String,      // File path
     pub summary: String,       // AI-generated change summary
 }
 
 /// Statistics about the overall diff
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DiffStats {
     pub files_changed: usize,
     pub lines_added: usize,
     pub lines_deleted: usize,
-    #[allow(dead_code)]
-    pub file_types: Vec<String>, // Primary file types found (available for future enhancements)
+    pub file_types: Vec<String>, // Extensions of the changed files
+}
+
+impl DiffStats {
+    /// One-line summary in `git diff --stat` wording
+    pub fn summary(&self) -> String {
+        format!(
+            "{} files changed, {} insertions(+), {} deletions(-)",
+            self.files_changed, self.lines_added, self.lines_deleted
+        )
+    }
+}
+
+/// Repository context used to fill the commit prompt placeholders
+#[derive(Debug, Clone)]
+pub struct CommitContext {
+    pub recent_commits: Vec<String>, // Subjects of the latest commits, newest first
+    pub branch: String,              // Current branch name (e.g. feat/JIRA-123-foo) or "(detached HEAD)"
+    pub scopes: Vec<String>,         // Top-level areas touched by the diff
+    pub stats: DiffStats,
+}
+
+impl CommitContext {
+    /// Substitute `{diff}`, `{recent_commits}`, `{branch}`, `{scopes}` and `{stats}` in a prompt
+    pub fn fill_prompt(&self, template: &str, diff: &str) -> String {
+        let recent_commits = if self.recent_commits.is_empty() {
+            "(no commits yet)".to_string()
+        } else {
+            self.recent_commits.iter().map(|s| format!("- {}", s)).collect::<Vec<_>>().join("\n")
+        };
+
+        // {diff} goes last so placeholder-like text inside the diff is left alone
+        template
+            .replace("{recent_commits}", &recent_commits)
+            .replace("{branch}", &self.branch)
+            .replace("{scopes}", &self.scopes.join(", "))
+            .replace("{stats}", &self.stats.summary())
+            .replace("{diff}", diff)
+    }
 }
 
 pub struct GitOperations;
@@ -51,7 +92,6 @@ impl GitOperations {
         Ok(String::from_utf8(output.stdout)?)
     }
 
-    #[allow(dead_code)]
     pub fn get_unstaged_diff() -> Result<String> {
         let output = Command::new("git")
             .args(["diff"])
@@ -65,6 +105,20 @@ impl GitOperations {
         Ok(String::from_utf8(output.stdout)?)
     }
 
+    /// Diff of all tracked changes (staged and unstaged) against HEAD
+    pub fn get_head_diff() -> Result<String> {
+        let output = Command::new("git")
+            .args(["diff", "HEAD"])
+            .output()
+            .context("Failed to run git diff HEAD")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("git diff HEAD failed"));
+        }
+
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
     pub fn get_status() -> Result<String> {
         let output = Command::new("git")
             .args(["status", "--porcelain"])
@@ -101,10 +155,25 @@ impl GitOperations {
         Ok(())
     }
 
+    /// Commit staged changes; the message may span several lines (subject, body, trailers)
     pub fn commit(message: &str) -> Result<()> {
-        let output = Command::new("git")
-            .args(["commit", "-m", message])
-            .output()
+        use std::io::Write;
+        use std::process::Stdio;
+
+        // Pass the message on stdin so multi-line bodies and trailers survive intact
+        let mut child = Command::new("git")
+            .args(["commit", "--cleanup=whitespace", "-F", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run git commit")?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(message.as_bytes())?;
+        }
+
+        let output = child.wait_with_output()
             .context("Failed to run git commit")?;
 
         if !output.status.success() {
@@ -169,6 +238,46 @@ impl GitOperations {
         Ok(combined_output.trim().to_string())
     }
 
+    /// Run `git push --dry-run` and return what would be pushed
+    pub fn push_dry_run(force: bool) -> Result<String> {
+        let mut args = vec!["push", "--dry-run"];
+        if force {
+            args.push("-f");
+        }
+
+        let output = Command::new("git")
+            .args(&args)
+            .output()
+            .context("Failed to run git push --dry-run")?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("git push --dry-run failed: {}", error));
+        }
+
+        // Git push reports to stderr
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.trim().is_empty() {
+            Ok("Everything up-to-date".to_string())
+        } else {
+            Ok(stderr.trim().to_string())
+        }
+    }
+
+    /// Full SHA of the current commit
+    pub fn get_head_sha() -> Result<String> {
+        let output = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .context("Failed to get HEAD commit")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to get HEAD commit"));
+        }
+
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    }
+
     pub fn has_remote() -> bool {
         Command::new("git")
             .args(["remote"])
@@ -185,6 +294,46 @@ impl GitOperations {
             .unwrap_or(false)
     }
 
+    /// Names of the configured remotes
+    pub fn get_remotes() -> Result<Vec<String>> {
+        let output = Command::new("git")
+            .args(["remote"])
+            .output()
+            .context("Failed to list remotes")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to list remotes"));
+        }
+
+        Ok(String::from_utf8(output.stdout)?.lines().map(|line| line.trim().to_string()).collect())
+    }
+
+    /// The current branch's upstream (e.g. `origin/main`) and how many commits
+    /// the branch is ahead of and behind it
+    pub fn get_upstream_status() -> Result<(String, usize, usize)> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--abbrev-ref", "@{upstream}"])
+            .output()
+            .context("Failed to get upstream branch")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("The current branch has no upstream"));
+        }
+        let upstream = String::from_utf8(output.stdout)?.trim().to_string();
+
+        let output = Command::new("git")
+            .args(["rev-list", "--left-right", "--count", "HEAD...@{upstream}"])
+            .output()
+            .context("Failed to compare with upstream")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to compare with upstream"));
+        }
+        let counts = String::from_utf8(output.stdout)?;
+        let mut counts = counts.split_whitespace().map(|count| count.parse().unwrap_or(0));
+        Ok((upstream, counts.next().unwrap_or(0), counts.next().unwrap_or(0)))
+    }
+
     pub fn set_upstream(remote: &str, branch: &str) -> Result<()> {
         let output = Command::new("git")
             .args(["push", "-u", remote, branch])
@@ -212,6 +361,57 @@ impl GitOperations {
         Ok(String::from_utf8(output.stdout)?.trim().to_string())
     }
 
+    /// Subjects of the last `count` commits, newest first (empty for a fresh repository)
+    pub fn get_recent_commit_subjects(count: usize) -> Result<Vec<String>> {
+        let output = Command::new("git")
+            .args(["log", "-n", &count.to_string(), "--format=%s"])
+            .output()
+            .context("Failed to run git log")?;
+
+        // git log fails when there are no commits yet
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    /// Gather branch, recent history, scopes and stats for the commit prompt
+    pub fn gather_commit_context(diff: &str, recent_count: usize) -> Result<CommitContext> {
+        Ok(CommitContext {
+            recent_commits: Self::get_recent_commit_subjects(recent_count)?,
+            branch: Self::get_current_branch()
+                .ok()
+                .filter(|branch| !branch.is_empty())
+                .unwrap_or_else(|| "(detached HEAD)".to_string()),
+            scopes: Self::touched_scopes(diff),
+            stats: Self::generate_diff_stats(diff),
+        })
+    }
+
+    /// Top-level areas touched by a diff, usable as conventional-commit scopes.
+    /// For container directories like `src/` or `packages/` the next level is used.
+    pub fn touched_scopes(diff: &str) -> Vec<String> {
+        const CONTAINERS: &[&str] = &["src", "lib", "crates", "packages", "apps", "modules"];
+
+        let mut scopes = std::collections::BTreeSet::new();
+        for (filename, _) in Self::split_diff_by_files(diff) {
+            let parts: Vec<&str> = filename.split('/').collect();
+            let scope = match parts.as_slice() {
+                [file] => file.to_string(),
+                [container, inner, _, ..] if CONTAINERS.contains(container) => inner.to_string(),
+                [dir, ..] => dir.to_string(),
+                [] => continue,
+            };
+            scopes.insert(scope);
+        }
+        scopes.into_iter().collect()
+    }
+
     pub fn add_remote(name: &str, url: &str) -> Result<()> {
         let output = Command::new("git")
             .args(["remote", "add", name, url])
@@ -239,76 +439,81 @@ impl GitOperations {
         Ok(repo_name)
     }
 
-    /// Segment a large diff by files for parallel AI processing
-    pub fn segment_diff_by_files(diff: &str, max_length: usize) -> Vec<DiffSegment> {
+    /// Segment a large diff by files for parallel AI processing.
+    /// A file too large for `budget` on its own is cut to fit in its own segment.
+    pub fn segment_diff_by_files(diff: &str, budget: DiffBudget) -> Vec<DiffSegment> {
         let mut segments = Vec::new();
         let mut current_segment_files = Vec::new();
         let mut current_segment_content = String::new();
-        let mut current_char_count = 0;
+        let mut current_token_count = 0;
 
         // Split diff by file boundaries
         let file_diffs = Self::split_diff_by_files(diff);
 
         for (filename, file_diff) in file_diffs {
-            let file_diff_len = file_diff.len();
-            
-            // If this single file exceeds max_length, it gets its own segment
-            if file_diff_len > max_length {
-                // First, finish current segment if it has content
-                if !current_segment_content.is_empty() {
-                    segments.push(DiffSegment {
-                        files: current_segment_files.clone(),
-                        content: current_segment_content.clone(),
-                        char_count: current_char_count,
-                    });
-                    current_segment_files.clear();
-                    current_segment_content.clear();
-                    current_char_count = 0;
-                }
-                
-                // Add the large file as its own segment
+            let file_tokens = tokens::count(&file_diff);
+            let fits_with_current = budget.fits_counted(
+                current_segment_content.len() + file_diff.len(),
+                current_token_count + file_tokens,
+            );
+
+            if !fits_with_current && !current_segment_content.is_empty() {
+                // Current segment is full, start a new one
+                segments.push(DiffSegment {
+                    files: std::mem::take(&mut current_segment_files),
+                    char_count: current_segment_content.len(),
+                    token_count: current_token_count,
+                    content: std::mem::take(&mut current_segment_content),
+                });
+                current_token_count = 0;
+            }
+
+            if !budget.fits_counted(file_diff.len(), file_tokens) {
+                // Too large even on its own: keep as much of the file as fits
+                let content = Self::truncate_file_diff(&file_diff, budget);
                 segments.push(DiffSegment {
                     files: vec![filename],
-                    content: file_diff,
-                    char_count: file_diff_len,
+                    char_count: content.len(),
+                    token_count: tokens::count(&content),
+                    content,
                 });
-            } else if current_char_count + file_diff_len > max_length {
-                // Current segment is full, start a new one
-                if !current_segment_content.is_empty() {
-                    segments.push(DiffSegment {
-                        files: current_segment_files.clone(),
-                        content: current_segment_content.clone(),
-                        char_count: current_char_count,
-                    });
-                    current_segment_files.clear();
-                    current_segment_content.clear();
-                    current_char_count = 0;
-                }
-                
-                // Start new segment with this file
-                current_segment_files.push(filename);
-                current_segment_content.push_str(&file_diff);
-                current_char_count += file_diff_len;
-            } else {
-                // Add to current segment
-                current_segment_files.push(filename);
-                current_segment_content.push_str(&file_diff);
-                current_char_count += file_diff_len;
+                continue;
             }
+
+            current_segment_files.push(filename);
+            current_segment_content.push_str(&file_diff);
+            current_token_count += file_tokens;
         }
 
         // Add final segment if it has content
         if !current_segment_content.is_empty() {
             segments.push(DiffSegment {
                 files: current_segment_files,
+                char_count: current_segment_content.len(),
+                token_count: current_token_count,
                 content: current_segment_content,
-                char_count: current_char_count,
             });
         }
 
         segments
     }
 
+    /// The head of a single file's diff that fits in `budget`, with a note on what was cut
+    fn truncate_file_diff(file_diff: &str, budget: DiffBudget) -> String {
+        const NOTE_TOKENS: usize = 20;
+        let mut kept = tokens::truncate_lines(file_diff, budget.max_tokens.saturating_sub(NOTE_TOKENS));
+        if let Some(max_chars) = budget.max_chars {
+            if kept.len() > max_chars {
+                // Searched on bytes: `max_chars` may fall inside a multi-byte character
+                let end = kept.as_bytes()[..max_chars].iter().rposition(|&byte| byte == b'\n').map_or(0, |i| i + 1);
+                kept = &kept[..end];
+            }
+        }
+
+        let omitted = file_diff[kept.len()..].lines().count();
+        format!("{}... [{} more lines of this file omitted]\n", kept, omitted)
+    }
+
     /// Split diff content by individual files
     fn split_diff_by_files(diff: &str) -> Vec<(String, String)> {
         let mut files = Vec::new();
@@ -324,11 +529,7 @@ impl GitOperations {
                 
                 // Extract filename from "diff --git a/path/file b/path/file"
                 if let Some(file_part) = line.split_whitespace().nth(2) {
-                    current_file = if file_part.starts_with("a/") {
-                        file_part[2..].to_string()
-                    } else {
-                        file_part.to_string()
-                    };
+                    current_file = file_part.strip_prefix("a/").unwrap_or(file_part).to_string();
                 } else {
                     current_file = "unknown".to_string();
                 }
@@ -356,22 +557,18 @@ impl GitOperations {
         let mut files_changed = std::collections::HashSet::new();
         let mut lines_added = 0;
         let mut lines_deleted = 0;
-        let mut file_extensions = std::collections::HashSet::new();
+        let mut file_extensions = std::collections::BTreeSet::new();
 
         for line in lines {
             if line.starts_with("diff --git") {
                 // Extract filename and extension
                 if let Some(file_part) = line.split_whitespace().nth(2) {
-                    let filename = if file_part.starts_with("a/") {
-                        &file_part[2..]
-                    } else {
-                        file_part
-                    };
+                    let filename = file_part.strip_prefix("a/").unwrap_or(file_part);
                     
                     files_changed.insert(filename.to_string());
                     
                     // Extract file extension for type analysis
-                    if let Some(ext) = filename.split('.').last() {
+                    if let Some(ext) = filename.split('.').next_back() {
                         if ext != filename && !ext.is_empty() {
                             file_extensions.insert(ext.to_string());
                         }
@@ -392,4 +589,24 @@ impl GitOperations {
         }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_file_diff_cuts_non_ascii_diff_at_a_line() {
+        let diff = "diff --git a/README.md b/README.md\n+第一行：你好\n+第二行：世界\n+第三行：再见\n";
+        // Inside the second "第" of the diff, two bytes into the three-byte character
+        let max_chars = diff.find("第二").unwrap() + 2;
+        assert!(!diff.is_char_boundary(max_chars));
+
+        let budget = DiffBudget { max_tokens: 10_000, max_chars: Some(max_chars) };
+        let truncated = GitOperations::truncate_file_diff(diff, budget);
+        assert_eq!(
+            truncated,
+            "diff --git a/README.md b/README.md\n+第一行：你好\n... [2 more lines of this file omitted]\n"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::Notify;
+
+/// Exit status of a process killed by SIGINT, as shells report it
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Returned when Ctrl-C stops a request while it waits to be retried
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Owns the Ctrl-C handler once something has asked for it
+struct Watcher {
+    /// `cancelled()` futures waiting right now
+    waiting: AtomicUsize,
+    notify: Notify,
+}
+
+static WATCHER: OnceLock<Watcher> = OnceLock::new();
+
+/// Completes when the user presses Ctrl-C while this future is being awaited.
+///
+/// Tokio can't uninstall its SIGINT handler, so the first call starts one task that
+/// keeps it for the rest of the process: Ctrl-C wakes whatever is waiting here (a
+/// streamed reply, a retry backoff), and when nothing is, it exits the way the default
+/// handler would instead of being swallowed.
+pub async fn cancelled() {
+    let watcher = WATCHER.get_or_init(|| {
+        tokio::spawn(watch());
+        Watcher { waiting: AtomicUsize::new(0), notify: Notify::new() }
+    });
+
+    // Registered before counting ourselves in, so a Ctrl-C in between isn't lost
+    let notified = watcher.notify.notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
+
+    let _waiting = Waiting::enter(watcher);
+    notified.await;
+}
+
+async fn watch() {
+    while tokio::signal::ctrl_c().await.is_ok() {
+        let Some(watcher) = WATCHER.get() else { continue };
+        if watcher.waiting.load(Ordering::SeqCst) == 0 {
+            eprintln!();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        watcher.notify.notify_waiters();
+    }
+}
+
+/// Counts a `cancelled()` future as waiting until it completes or is dropped
+struct Waiting(&'static Watcher);
+
+impl Waiting {
+    fn enter(watcher: &'static Watcher) -> Self {
+        watcher.waiting.fetch_add(1, Ordering::SeqCst);
+        Self(watcher)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.0.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -1,88 +1,145 @@
 mod cli;
 mod config;
 mod ai_client;
+mod anthropic;
+mod gemini;
 mod git_ops;
+mod commit_message;
 mod history;
 mod utils;
+mod streaming;
+mod ollama_api;
+mod openai_compat;
+mod retry;
+mod interrupt;
+mod tokens;
+mod attachments;
+mod chat_context;
+mod chat_input;
+mod sessions;
+mod output;
 mod commands;
 
-// use clap::Parser;
-// use cli::{Cli, Commands};
-use anyhow::Result;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Commands, ConfigCommands, GlobalArgs, ModelsCommands};
+use config::{Config, ConfigOverrides};
+use output::OutputFormat;
+use anyhow::{anyhow, Result};
 use commands::*;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = std::env::args().collect::<Vec<_>>();
-    
-    // Handle no arguments or unrecognized commands
-    if args.len() == 1 {
-        show_help();
+    let cli = Cli::parse();
+    let format = cli.global.format;
+    apply_global_args(cli.global);
+
+    // Show help when no subcommand is given
+    let Some(command) = cli.command else {
+        Cli::command().print_help()?;
         return Ok(());
+    };
+    let format = if matches!(command, Commands::Doctor { json: true }) { OutputFormat::Json } else { format };
+
+    let result = run(command, format).await;
+    // Scripts reading stdout get an object either way; the error still goes to stderr
+    if let Err(e) = &result {
+        if format.is_json() && e.downcast_ref::<output::Reported>().is_none() {
+            output::print_json(&serde_json::json!({ "error": format!("{:#}", e) }))?;
+        }
     }
+    result
+}
 
-    // Parse command manually to handle unrecognized commands
-    let subcommand = args.get(1).map(|s| s.as_str()).unwrap_or("");
-    let remaining_args: Vec<String> = args.iter().skip(2).map(|s| s.clone()).collect();
+async fn run(command: Commands, format: OutputFormat) -> Result<()> {
+    if format.is_json() && !command.supports_json() {
+        return Err(anyhow!(
+            "--format json is supported by ask, commit, push, fix, doctor, models list and config get"
+        ));
+    }
 
-    match subcommand {
-        "help" => show_help(),
-        "ask" => {
-            if remaining_args.is_empty() {
-                eprintln!("Error: 'ask' command requires a question");
-                show_help();
-                return Ok(());
+    match command {
+        Commands::Ask { files, quiet, raw, question } => {
+            handle_ask(&question.join(" "), AskOptions { files, quiet, raw, format }).await?;
+        }
+        Commands::Chat { resume, list, export, output, export_format } => {
+            if list {
+                handle_chat_list().await?;
+            } else if let Some(key) = export {
+                handle_chat_export(&key, output.as_deref(), export_format).await?;
+            } else {
+                let resume = match resume {
+                    None => Resume::New,
+                    Some(None) => Resume::Latest,
+                    Some(Some(key)) => Resume::Session(key),
+                };
+                handle_chat(resume).await?;
             }
-            let question = remaining_args.join(" ");
-            handle_ask(&question).await?;
         }
-        "chat" => handle_chat().await?,
-        "commit" => {
-            let all = remaining_args.contains(&"all".to_string());
-            handle_commit(all).await?;
+        Commands::Commit { all, dry_run, yes } => {
+            handle_commit(CommitOptions { all, dry_run, yes, format }).await?;
         }
-        "push" => {
-            let force = remaining_args.contains(&"force".to_string());
-            handle_push(force).await?;
+        Commands::Push { force, dry_run } => {
+            handle_push(force, dry_run, format).await?;
         }
-        "publish" => handle_publish().await?,
-        "fix" => {
-            let user_context = remaining_args.join(" ");
-            handle_fix(&user_context).await?;
+        Commands::Publish { dry_run } => {
+            handle_publish(dry_run).await?;
         }
-        _ => {
-            eprintln!("Error: Unknown command '{}'", subcommand);
-            show_help();
+        Commands::Fix { context, dry_run } => {
+            handle_fix(&context.join(" "), dry_run, format).await?;
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Show { origin } => {
+                handle_config_show(origin).await?;
+            }
+            ConfigCommands::Get { key } => {
+                handle_config_get(&key, format).await?;
+            }
+            ConfigCommands::Set { key, value, project } => {
+                handle_config_set(&key, &value, project).await?;
+            }
+            ConfigCommands::Unset { key, project } => {
+                handle_config_unset(&key, project).await?;
+            }
+            ConfigCommands::Edit { project } => {
+                handle_config_edit(project).await?;
+            }
+            ConfigCommands::Validate => {
+                handle_config_validate().await?;
+            }
+            ConfigCommands::Path { project } => {
+                handle_config_path(project).await?;
+            }
+        },
+        Commands::Models { command } => match command {
+            ModelsCommands::List => {
+                handle_models_list(format).await?;
+            }
+            ModelsCommands::Pull { model } => {
+                handle_models_pull(model.as_deref()).await?;
+            }
+            ModelsCommands::Ping => {
+                handle_models_ping().await?;
+            }
+            ModelsCommands::Set { command_type, model, project } => {
+                handle_models_set(command_type.key(), model.as_deref(), project).await?;
+            }
+        },
+        Commands::Doctor { .. } => {
+            handle_doctor(format.is_json()).await?;
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "ai", &mut std::io::stdout());
         }
     }
 
     Ok(())
 }
 
-fn show_help() {
-    println!("ai - Personal AI CLI tool");
-    println!();
-    println!("USAGE:");
-    println!("    ai <COMMAND>");
-    println!();
-    println!("COMMANDS:");
-    println!("    help       Display this help message");
-    println!("    ask        Ask AI a single question");
-    println!("    chat       Start interactive chat session");
-    println!("    commit     Commit changes with AI-generated message");
-    println!("    push       Push changes to remote repository");
-    println!("    publish    Publish project to appropriate registry");
-    println!("    fix        Analyze terminal history and fix the last error");
-    println!();
-    println!("EXAMPLES:");
-    println!("    ai ask \"How do I write a Rust function?\"");
-    println!("    ai chat");
-    println!("    ai commit all");
-    println!("    ai push force");
-    println!("    ai fix");
-    println!("    ai fix \"cargo build failed with linking error\"");
+fn apply_global_args(global: GlobalArgs) {
+    Config::set_overrides(ConfigOverrides {
+        provider: global.provider,
+        model: global.model,
+        base_url: global.base_url,
+        config_path: global.config,
+    });
 }
-
-
-
-
@@ -0,0 +1,387 @@
+use crate::retry::HttpError;
+use crate::streaming::{self, LineBuffer, TokenStream};
+use ai::chat_completions::ChatCompletionRequest;
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Width of the `/api/pull` progress bar in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Context window Ollama allocates when neither the Modelfile nor the request sets `num_ctx`
+pub const OLLAMA_DEFAULT_CONTEXT_WINDOW: usize = 4096;
+
+/// Client for the Ollama server's chat endpoint (`/api/chat`) and its model
+/// management endpoints (`/api/tags`, `/api/show` and `/api/pull`)
+#[derive(Clone)]
+pub struct OllamaApi {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+/// A model installed on the server, from `/api/tags`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalModel {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<LocalModel>,
+}
+
+/// What `/api/show` reports about a model's context size
+#[derive(Debug, Clone, Default)]
+pub struct ModelInfo {
+    /// `num_ctx` set in the model's Modelfile parameters
+    pub num_ctx: Option<usize>,
+    /// `<family>.context_length` the model was trained with
+    pub context_length: Option<usize>,
+}
+
+impl ModelInfo {
+    /// The context window a request without `options.num_ctx` gets: the Modelfile's
+    /// `num_ctx`, else the server default, never more than the trained length
+    pub fn context_window(&self) -> usize {
+        let window = self.num_ctx.unwrap_or(OLLAMA_DEFAULT_CONTEXT_WINDOW);
+        self.context_length.map_or(window, |length| window.min(length))
+    }
+}
+
+/// One line of `/api/pull` progress
+#[derive(Debug, Deserialize)]
+pub struct PullProgress {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl OllamaApi {
+    pub fn new(http: reqwest::Client, base_url: &str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Send a request and return the text of the reply; `num_ctx` sets the context
+    /// window the server allocates for it
+    pub async fn chat(&self, request: &ChatCompletionRequest, num_ctx: Option<usize>) -> Result<String> {
+        let response = self.send_chat(request, false, num_ctx).await?;
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Ollama /api/chat: {}", e))?;
+
+        if let Some(error) = body["error"].as_str() {
+            return Err(anyhow!("Ollama API error: {}", error));
+        }
+        body["message"]["content"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("No response from AI"))
+    }
+
+    /// Send a request and stream the reply's tokens from the NDJSON response
+    pub async fn chat_stream(&self, request: &ChatCompletionRequest, num_ctx: Option<usize>) -> Result<TokenStream> {
+        let response = self.send_chat(request, true, num_ctx).await?;
+
+        Ok(streaming::ndjson_tokens(response.bytes_stream(), streaming::parse_ollama_line))
+    }
+
+    async fn send_chat(&self, request: &ChatCompletionRequest, stream: bool, num_ctx: Option<usize>) -> Result<reqwest::Response> {
+        let mut body = serde_json::to_value(request)?;
+        body["stream"] = Value::Bool(stream);
+        if let Some(num_ctx) = num_ctx {
+            body["options"] = json!({ "num_ctx": num_ctx });
+        }
+
+        let response = self.http
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("{:#}", anyhow::Error::from(e)))?;
+        Self::check(response).await
+    }
+
+    /// Models installed on the server
+    pub async fn list_models(&self) -> Result<Vec<LocalModel>> {
+        let response = self.http
+            .get(format!("{}/api/tags", self.base_url))
+            .send()
+            .await
+            .map_err(|e| anyhow!("{:#}", anyhow::Error::from(e)))?;
+        let response = Self::check(response).await?;
+
+        let tags: TagsResponse = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Ollama /api/tags: {}", e))?;
+        Ok(tags.models)
+    }
+
+    /// Whether `model` is installed; a name without a tag means `:latest`
+    pub async fn is_installed(&self, model: &str) -> Result<bool> {
+        let wanted = normalize_name(model);
+        Ok(self.list_models().await?
+            .iter()
+            .any(|installed| normalize_name(&installed.name) == wanted))
+    }
+
+    /// Context details of an installed model, or `None` if the server doesn't have it
+    pub async fn show(&self, model: &str) -> Result<Option<ModelInfo>> {
+        let response = self.http
+            .post(format!("{}/api/show", self.base_url))
+            .json(&json!({ "model": model }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("{:#}", anyhow::Error::from(e)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = Self::check(response).await?;
+
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from Ollama /api/show: {}", e))?;
+        Ok(Some(parse_model_info(&body)))
+    }
+
+    /// Download `model`, reporting each progress line to `on_progress`
+    pub async fn pull(&self, model: &str, mut on_progress: impl FnMut(&PullProgress)) -> Result<()> {
+        let response = self.http
+            .post(format!("{}/api/pull", self.base_url))
+            .json(&json!({ "model": model, "stream": true }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("{:#}", anyhow::Error::from(e)))?;
+        let response = Self::check(response).await?;
+
+        let mut bytes = response.bytes_stream();
+        let mut lines = LineBuffer::new();
+        let mut succeeded = false;
+        while let Some(chunk) = bytes.next().await {
+            let chunk = chunk.map_err(|e| anyhow!("Pull interrupted: {}", e))?;
+            for line in lines.push(&chunk) {
+                succeeded |= Self::handle_progress_line(model, &line, &mut on_progress)?;
+            }
+        }
+        if let Some(line) = lines.finish() {
+            succeeded |= Self::handle_progress_line(model, &line, &mut on_progress)?;
+        }
+
+        if !succeeded {
+            return Err(anyhow!("Pull of {} ended before it finished", model));
+        }
+        Ok(())
+    }
+
+    /// Download `model`, drawing a progress bar on stderr
+    pub async fn pull_with_progress(&self, model: &str) -> Result<()> {
+        let mut last_status = String::new();
+        let result = self.pull(model, |progress| {
+            let mut err = std::io::stderr();
+            let changed = progress.status != last_status;
+            if changed && !last_status.is_empty() {
+                let _ = writeln!(err);
+            }
+            match progress.total {
+                // Layers being downloaded redraw a bar on their line
+                Some(total) if total > 0 => {
+                    let completed = progress.completed.unwrap_or(0);
+                    let _ = write!(err, "\r{}", render_progress(&progress.status, completed, total));
+                }
+                _ if changed => {
+                    let _ = write!(err, "{}", progress.status);
+                }
+                _ => {}
+            }
+            let _ = err.flush();
+            last_status = progress.status.clone();
+        }).await;
+
+        eprintln!();
+        result
+    }
+
+    /// Report one NDJSON progress line; returns whether it marks the pull as finished
+    fn handle_progress_line(model: &str, line: &str, on_progress: &mut impl FnMut(&PullProgress)) -> Result<bool> {
+        let progress: PullProgress = serde_json::from_str(line)
+            .map_err(|e| anyhow!("Invalid pull progress from Ollama: {} ({})", e, line))?;
+        if let Some(error) = &progress.error {
+            return Err(anyhow!("Failed to pull {}: {}", model, error));
+        }
+        on_progress(&progress);
+        Ok(progress.status == "success")
+    }
+
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let headers = response.headers().clone();
+        let text = response.text().await.unwrap_or_default();
+        Err(HttpError::new(status.as_u16(), &headers, format!("Ollama API error {}: {}", status, text.trim())).into())
+    }
+}
+
+/// `gemma2` and `gemma2:latest` name the same model
+pub fn normalize_name(model: &str) -> String {
+    if model.contains(':') {
+        model.to_string()
+    } else {
+        format!("{}:latest", model)
+    }
+}
+
+/// Read `num_ctx` from the Modelfile parameters and the trained context length from `model_info`
+fn parse_model_info(body: &Value) -> ModelInfo {
+    let num_ctx = body["parameters"]
+        .as_str()
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("num_ctx"), Some(value)) => value.parse().ok(),
+                _ => None,
+            }
+        });
+
+    let context_length = body["model_info"]
+        .as_object()
+        .and_then(|info| {
+            info.iter()
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+        })
+        .map(|length| length as usize);
+
+    ModelInfo { num_ctx, context_length }
+}
+
+/// `pulling 6a0746a1ec1a [#########-----------]  45%  1.2 GB / 2.6 GB`
+fn render_progress(status: &str, completed: u64, total: u64) -> String {
+    let fraction = (completed as f64 / total as f64).clamp(0.0, 1.0);
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "{} [{}{}] {:>3.0}%  {} / {}",
+        status,
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        fraction * 100.0,
+        format_size(completed),
+        format_size(total)
+    )
+}
+
+/// Human-readable byte count, e.g. `4.7 GB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use ai::chat_completions::{ChatCompletionMessage, ChatCompletionRequestBuilder};
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn context_window_falls_back_to_the_server_default() {
+        let body = json!({
+            "parameters": "stop \"<end>\"",
+            "model_info": { "llama.context_length": 131072 },
+        });
+        assert_eq!(parse_model_info(&body).context_window(), OLLAMA_DEFAULT_CONTEXT_WINDOW);
+
+        let body = json!({
+            "parameters": "num_ctx 16384\nstop \"<end>\"",
+            "model_info": { "llama.context_length": 131072 },
+        });
+        assert_eq!(parse_model_info(&body).context_window(), 16384);
+
+        let info = ModelInfo { num_ctx: None, context_length: Some(2048) };
+        assert_eq!(info.context_window(), 2048);
+    }
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequestBuilder::default()
+            .model("gemma2:9b")
+            .messages(vec![ChatCompletionMessage::User("Hi".to_string().into())])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_sends_the_context_window() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "model": "gemma2:9b", "stream": false, "options": { "num_ctx": 8192 } })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "Hello" },
+                "done": true,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = OllamaApi::new(reqwest::Client::new(), &server.uri());
+        assert_eq!(api.chat(&request(), Some(8192)).await.unwrap(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn maps_error_responses_to_http_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(503).set_body_json(json!({ "error": "server busy, please try again" })))
+            .mount(&server)
+            .await;
+
+        let api = OllamaApi::new(reqwest::Client::new(), &server.uri());
+        let error = api.chat_stream(&request(), None).await.err().unwrap();
+        assert!(retry::is_transient(&error));
+        assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, 503);
+        assert!(error.to_string().contains("server busy"));
+    }
+
+    #[tokio::test]
+    async fn chat_stream_reads_ndjson() {
+        let server = MockServer::start().await;
+        let body = concat!(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"lo\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/x-ndjson"))
+            .mount(&server)
+            .await;
+
+        let api = OllamaApi::new(reqwest::Client::new(), &server.uri());
+        let stream = api.chat_stream(&request(), None).await.unwrap();
+        let tokens: Vec<String> = stream.map(|token| token.unwrap()).collect().await;
+        assert_eq!(tokens, vec!["Hel", "lo"]);
+    }
+}
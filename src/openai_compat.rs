@@ -0,0 +1,270 @@
+use crate::retry::HttpError;
+use crate::streaming::{self, StreamEvent, TokenStream};
+use ai::chat_completions::ChatCompletionRequest;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+pub const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Client for OpenAI-compatible chat completion APIs (`POST {base_url}/chat/completions`)
+#[derive(Clone)]
+pub struct OpenAiClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+/// `{"error": {"message": "Rate limit reached", "type": "requests"}}`
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    message: String,
+}
+
+impl OpenAiClient {
+    pub fn new(http: reqwest::Client, base_url: &str, api_key: &str) -> Self {
+        let base_url = if base_url.is_empty() { DEFAULT_OPENAI_BASE_URL } else { base_url };
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Send a request and return the text of the reply
+    pub async fn chat_completions(&self, request: &ChatCompletionRequest) -> Result<String> {
+        let response = self.send(request, false).await?;
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from OpenAI API: {}", e))?;
+
+        // Some gateways answer 200 with an error object
+        if body.get("error").is_some_and(|error| !error.is_null()) {
+            return Err(api_error(None, &body.to_string()));
+        }
+        body["choices"][0]["message"]["content"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("No response from AI"))
+    }
+
+    /// Send a request and stream the reply's content deltas
+    pub async fn stream_chat_completions(&self, request: &ChatCompletionRequest) -> Result<TokenStream> {
+        let response = self.send(request, true).await?;
+
+        Ok(streaming::sse_tokens(response.bytes_stream(), parse_stream_event))
+    }
+
+    /// IDs of the models the API offers (`GET {base_url}/models`)
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.authorize(self.http.get(format!("{}/models", self.base_url)))
+            .send()
+            .await
+            .map_err(|e| anyhow!("OpenAI API error: {:#}", anyhow::Error::from(e)))?;
+        let response = Self::check(response).await?;
+
+        let body: Value = response.json().await
+            .map_err(|e| anyhow!("Invalid response from {}/models: {}", self.base_url, e))?;
+        Ok(body["data"]
+            .as_array()
+            .map(|models| models.iter().filter_map(|m| m["id"].as_str().map(String::from)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn send(&self, request: &ChatCompletionRequest, stream: bool) -> Result<reqwest::Response> {
+        let mut body = serde_json::to_value(request)?;
+        body["stream"] = Value::Bool(stream);
+
+        let response = self.authorize(self.http.post(format!("{}/chat/completions", self.base_url)))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("OpenAI API error: {:#}", anyhow::Error::from(e)))?;
+        Self::check(response).await
+    }
+
+    /// Local servers often need no key, so the header is only sent when there is one
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if self.api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(&self.api_key)
+        }
+    }
+
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let headers = response.headers().clone();
+        let mut text = response.text().await.unwrap_or_default();
+        // Proxies in front of the API often fail with an empty body
+        if text.trim().is_empty() {
+            text = status.canonical_reason().unwrap_or_default().to_string();
+        }
+        Err(HttpError::new(status.as_u16(), &headers, api_error(Some(status.as_u16()), &text)).into())
+    }
+}
+
+/// Turn an OpenAI-style error response into a readable error
+fn api_error(status: Option<u16>, body: &str) -> anyhow::Error {
+    let status = status.map(|s| format!(" {}", s)).unwrap_or_default();
+    match serde_json::from_str::<ErrorBody>(body) {
+        Ok(ErrorBody { error: ErrorDetail { kind: Some(kind), message } }) => {
+            anyhow!("OpenAI API error ({}{}): {}", kind, status, message)
+        }
+        Ok(ErrorBody { error }) => anyhow!("OpenAI API error{}: {}", status, error.message),
+        Err(_) => anyhow!("OpenAI API error{}: {}", status, body.trim()),
+    }
+}
+
+/// Parse the data payload of one server-sent event from a streaming response
+pub fn parse_stream_event(data: &str) -> Result<StreamEvent> {
+    if data == "[DONE]" {
+        return Ok(StreamEvent::Done);
+    }
+
+    let chunk: Value = serde_json::from_str(data)
+        .map_err(|e| anyhow!("Invalid stream chunk from OpenAI API: {} ({})", e, data))?;
+    if chunk.get("error").is_some_and(|error| !error.is_null()) {
+        return Err(api_error(None, data));
+    }
+
+    match chunk["choices"][0]["delta"]["content"].as_str() {
+        Some(content) if !content.is_empty() => Ok(StreamEvent::Token(content.to_string())),
+        _ => Ok(StreamEvent::Skip),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use ai::chat_completions::{ChatCompletionMessage, ChatCompletionRequestBuilder};
+    use futures::StreamExt;
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::matchers::{bearer_token, body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequestBuilder::default()
+            .model("deepseek-chat")
+            .messages(vec![ChatCompletionMessage::User("Hi".to_string().into())])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_stream_events() {
+        assert_eq!(
+            parse_stream_event(r#"{"choices":[{"index":0,"delta":{"content":"Hi "},"finish_reason":null}]}"#).unwrap(),
+            StreamEvent::Token("Hi ".to_string())
+        );
+        assert_eq!(
+            parse_stream_event(r#"{"choices":[{"index":0,"delta":{"role":"assistant"}}]}"#).unwrap(),
+            StreamEvent::Skip
+        );
+        assert_eq!(parse_stream_event("[DONE]").unwrap(), StreamEvent::Done);
+
+        let error = parse_stream_event(r#"{"error":{"message":"Provider returned error","type":"server_error"}}"#)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "OpenAI API error (server_error): Provider returned error");
+    }
+
+    #[tokio::test]
+    async fn sends_the_bearer_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(bearer_token("sk-test"))
+            .and(body_partial_json(json!({ "model": "deepseek-chat", "stream": false })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Hello" } }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = OpenAiClient::new(reqwest::Client::new(), &format!("{}/v1/", server.uri()), "sk-test");
+        assert_eq!(client.chat_completions(&request()).await.unwrap(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn sends_no_authorization_without_a_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(|request: &Request| !request.headers.contains_key("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{ "id": "local-model" }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = OpenAiClient::new(reqwest::Client::new(), &server.uri(), "");
+        assert_eq!(client.list_models().await.unwrap(), vec!["local-model"]);
+    }
+
+    #[tokio::test]
+    async fn maps_error_responses_to_http_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": false })))
+            .respond_with(ResponseTemplate::new(503).insert_header("retry-after", "3"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": { "message": "Incorrect API key provided", "type": "invalid_request_error" },
+            })))
+            .mount(&server)
+            .await;
+        let client = OpenAiClient::new(reqwest::Client::new(), &server.uri(), "sk-test");
+
+        // Gateways often fail with an empty body
+        let error = client.chat_completions(&request()).await.unwrap_err();
+        assert!(retry::is_transient(&error));
+        assert_eq!(error.downcast_ref::<HttpError>().unwrap().retry_after, Some(Duration::from_secs(3)));
+        assert_eq!(error.to_string(), "OpenAI API error 503: Service Unavailable");
+
+        let error = client.stream_chat_completions(&request()).await.err().unwrap();
+        assert!(!retry::is_transient(&error));
+        assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, 401);
+        assert_eq!(error.to_string(), "OpenAI API error (invalid_request_error 401): Incorrect API key provided");
+    }
+
+    #[tokio::test]
+    async fn streams_content_deltas_until_done() {
+        let server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi \"}}]}\n\n",
+            ": OPENROUTER PROCESSING\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"there\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let client = OpenAiClient::new(reqwest::Client::new(), &server.uri(), "sk-test");
+        let stream = client.stream_chat_completions(&request()).await.unwrap();
+        let tokens: Vec<String> = stream.map(|token| token.unwrap()).collect().await;
+        assert_eq!(tokens, vec!["Hi ", "there"]);
+    }
+}
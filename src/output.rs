@@ -0,0 +1,54 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::Display;
+use std::time::Instant;
+
+/// How `ai commit`, `ai push`, `ai fix` and the other scriptable commands report what they did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Progress and results for people
+    #[default]
+    Text,
+    /// One JSON object on stdout once the command is done, `{"error": ...}` if it failed;
+    /// progress goes to stderr
+    Json,
+}
+
+impl OutputFormat {
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+
+    /// Print a progress or status line, to stderr when stdout is reserved for JSON
+    pub fn status(self, message: impl Display) {
+        if self.is_json() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+/// Print a command's result object
+pub fn print_json<T: Serialize>(result: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(result)?);
+    Ok(())
+}
+
+/// A failure whose JSON result, saying what went wrong, has already been printed,
+/// so no `{"error": ...}` object follows it
+#[derive(Debug)]
+pub struct Reported(pub String);
+
+impl Display for Reported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Reported {}
+
+/// Milliseconds since `started`
+pub fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
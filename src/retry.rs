@@ -0,0 +1,334 @@
+use crate::config::RetryConfig;
+use crate::interrupt::{self, Interrupted};
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use tokio::time::Duration;
+
+/// HTTP statuses worth retrying: timeouts, rate limits, server errors and Anthropic's "overloaded"
+const TRANSIENT_STATUSES: &[u16] = &[408, 425, 429, 500, 502, 503, 504, 529];
+
+/// Error text that marks a transient failure where there is no HTTP status to go by:
+/// error events in the middle of a stream, and connection errors from reqwest
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "rate limit",
+    "rate_limit",
+    "too many requests",
+    "overloaded",
+    "bad gateway",
+    "service unavailable",
+    "gateway timeout",
+    "temporarily unavailable",
+    "try again",
+    "timed out",
+    "timeout",
+    "connection reset",
+    "connection closed",
+    "loading model",
+    "server busy",
+];
+
+/// An HTTP error response, keeping the status and `Retry-After` for the retry logic.
+/// It displays as the provider's readable error message.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(status: u16, headers: &HeaderMap, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            retry_after: parse_retry_after(headers),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// `Retry-After` as delay-seconds (`120`) or an HTTP date; a date in the past means now
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(seconds)
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .map(Duration::from_secs_f64);
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(std::time::SystemTime::now()).unwrap_or_default())
+}
+
+/// Whether an error is worth retrying against the same provider
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(http) = error.downcast_ref::<HttpError>() {
+        return TRANSIENT_STATUSES.contains(&http.status);
+    }
+
+    let message = format!("{:#}", error).to_lowercase();
+    TRANSIENT_MESSAGES.iter().any(|marker| message.contains(marker))
+}
+
+/// Exponential backoff with jitter for transient provider errors
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &RetryConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms.max(config.initial_backoff_ms)),
+        }
+    }
+
+    /// Run `operation`, retrying transient failures; `label` names it in the retry messages
+    pub async fn run<T, F, Fut>(&self, label: &str, operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.run_until(label, operation, interrupt::cancelled).await
+    }
+
+    /// `run`, where `cancel()` completing during a backoff stops with `Interrupted`
+    async fn run_until<T, F, Fut, C, CFut>(&self, label: &str, mut operation: F, cancel: C) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        C: Fn() -> CFut,
+        CFut: Future<Output = ()>,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if attempt >= self.max_retries || !is_transient(&error) {
+                return Err(error);
+            }
+
+            let retry_after = error.downcast_ref::<HttpError>().and_then(|e| e.retry_after);
+            if retry_after.is_some_and(|wait| wait > self.max_backoff) {
+                // Waiting that long is worse than giving up (or falling back)
+                return Err(anyhow!(
+                    "{} (asked to retry after {}s)",
+                    error,
+                    retry_after.unwrap_or_default().as_secs()
+                ));
+            }
+
+            attempt += 1;
+            let delay = retry_after.unwrap_or_else(|| self.backoff(attempt));
+            eprintln!(
+                "⏳ {} failed ({}), retrying in {:.1}s ({}/{})...",
+                label,
+                first_line(&error),
+                delay.as_secs_f64(),
+                attempt,
+                self.max_retries
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = cancel() => {
+                    eprintln!("[interrupted]");
+                    return Err(Interrupted.into());
+                }
+            }
+        }
+    }
+
+    /// Delay before retry number `attempt` (1-based): a random point in the upper half
+    /// of the doubled window, so parallel requests don't retry in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        let window = self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        window / 2 + window.mul_f64(random_fraction() / 2.0)
+    }
+}
+
+/// A pseudo-random number in [0, 1) from the std hasher's per-process random keys
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The first line of an error, for one-line progress messages
+pub fn first_line(error: &anyhow::Error) -> String {
+    error.to_string().lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::cell::Cell;
+    use std::time::SystemTime;
+
+    fn policy(max_retries: u32, initial_backoff_ms: u64, max_backoff_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(initial_backoff_ms),
+            max_backoff: Duration::from_millis(max_backoff_ms),
+        }
+    }
+
+    fn retry_after(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        parse_retry_after(&headers)
+    }
+
+    fn http_error(status: u16, retry_after: Option<&str>) -> anyhow::Error {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        }
+        HttpError::new(status, &headers, format!("API error {}", status)).into()
+    }
+
+    #[test]
+    fn backoff_doubles_within_jitter_and_is_capped() {
+        let policy = policy(10, 100, 1000);
+        for _ in 0..100 {
+            for (attempt, window) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (10, 1000)] {
+                let delay = policy.backoff(attempt);
+                let window = Duration::from_millis(window);
+                assert!(delay >= window / 2 && delay <= window, "attempt {}: {:?}", attempt, delay);
+            }
+        }
+        // Huge attempt numbers must not overflow
+        assert!(policy.backoff(u32::MAX) <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn random_fraction_stays_below_one() {
+        for _ in 0..1000 {
+            let fraction = random_fraction();
+            assert!((0.0..1.0).contains(&fraction));
+        }
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(retry_after("2"), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(" 1.5 "), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_after("-1"), None);
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let wait = retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(28) && wait <= Duration::from_secs(30), "{:?}", wait);
+
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn classifies_transient_errors() {
+        assert!(is_transient(&http_error(429, None)));
+        assert!(is_transient(&http_error(503, None)));
+        assert!(is_transient(&http_error(529, None)));
+        assert!(!is_transient(&http_error(400, None)));
+        assert!(!is_transient(&http_error(401, None)));
+        assert!(is_transient(&anyhow!("Anthropic API error (overloaded_error): Overloaded")));
+        assert!(is_transient(&anyhow!("error sending request: connection reset by peer")));
+        assert!(!is_transient(&anyhow!("Invalid response from Gemini")));
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_until_success() {
+        let calls = Cell::new(0);
+        let result = policy(3, 1, 5).run("test", || {
+            calls.set(calls.get() + 1);
+            let call = calls.get();
+            async move {
+                match call {
+                    1 => Err(http_error(503, None)),
+                    2 => Err(http_error(429, Some("0"))),
+                    _ => Ok("done"),
+                }
+            }
+        }).await;
+
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let calls = Cell::new(0);
+        let result: Result<()> = policy(2, 1, 5).run("test", || {
+            calls.set(calls.get() + 1);
+            async { Err(http_error(502, None)) }
+        }).await;
+
+        assert_eq!(result.unwrap_err().to_string(), "API error 502");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let calls = Cell::new(0);
+        let result: Result<()> = policy(3, 1, 5).run("test", || {
+            calls.set(calls.get() + 1);
+            async { Err(http_error(401, None)) }
+        }).await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_at_once_when_retry_after_exceeds_the_max_backoff() {
+        let calls = Cell::new(0);
+        let result: Result<()> = policy(3, 1, 1000).run("test", || {
+            calls.set(calls.get() + 1);
+            async { Err(http_error(429, Some("60"))) }
+        }).await;
+
+        // Returned straight away so the caller can switch to a fallback
+        assert_eq!(result.unwrap_err().to_string(), "API error 429 (asked to retry after 60s)");
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_backoff_stops_retrying() {
+        let calls = Cell::new(0);
+        let started = std::time::Instant::now();
+        let result: Result<()> = policy(3, 60_000, 60_000).run_until(
+            "test",
+            || {
+                calls.set(calls.get() + 1);
+                async { Err(http_error(503, None)) }
+            },
+            || tokio::time::sleep(Duration::from_millis(20)),
+        ).await;
+
+        assert!(result.unwrap_err().is::<Interrupted>());
+        assert_eq!(calls.get(), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
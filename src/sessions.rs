@@ -0,0 +1,327 @@
+use crate::ai_client::{ChatMessage, Role};
+use crate::chat_context::{self, ChatContext};
+use crate::config::{self, Config};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Characters of the first question shown as a session's title
+const TITLE_LENGTH: usize = 60;
+
+/// A chat conversation saved to disk, so it can be resumed or exported later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatSession {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>, // Set with /save <name>
+    pub working_dir: String,
+    pub model: String, // provider/model the session was started with
+    pub created_at: u64, // Unix seconds
+    pub updated_at: u64,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "ChatContext::is_empty")]
+    pub context: ChatContext, // Turns summarized or dropped to fit the context window
+}
+
+impl ChatSession {
+    pub fn new(working_dir: &str, model: &str) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            // Milliseconds in hex: short, unique per user and sorted by creation
+            id: format!("{:x}", now.as_millis()),
+            name: None,
+            working_dir: working_dir.to_string(),
+            model: model.to_string(),
+            created_at: now.as_secs(),
+            updated_at: now.as_secs(),
+            messages: Vec::new(),
+            context: ChatContext::default(),
+        }
+    }
+
+    /// The first line of the first question
+    pub fn title(&self) -> String {
+        self.messages.iter()
+            .find(|message| message.role == Role::User)
+            .map_or_else(|| "(empty)".to_string(), |message| first_line(&message.content, TITLE_LENGTH))
+    }
+
+    /// `name (id)`, or just the id
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", name, self.id),
+            None => self.id.clone(),
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.updated_at = now();
+    }
+
+    pub fn export(&self, format: ExportFormat) -> Result<String> {
+        match format {
+            ExportFormat::Markdown => Ok(self.to_markdown()),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(self)? + "\n"),
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n", self.name.clone().unwrap_or_else(|| self.title()));
+        markdown.push_str(&format!("- Session: `{}`\n", self.id));
+        markdown.push_str(&format!("- Model: `{}`\n", self.model));
+        markdown.push_str(&format!("- Directory: `{}`\n", self.working_dir));
+
+        for message in &self.messages {
+            let pin = if message.pinned { " 📌" } else { "" };
+            let heading = chat_context::speaker(message.role);
+            markdown.push_str(&format!("\n## {}{}\n\n{}\n", heading, pin, message.content.trim_end()));
+        }
+        markdown
+    }
+}
+
+/// How a session is written by `ai chat --export` and `/export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    #[value(name = "md", alias = "markdown")]
+    Markdown,
+    Json,
+}
+
+impl ExportFormat {
+    /// JSON for `.json` files, Markdown otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ExportFormat::Json,
+            _ => ExportFormat::Markdown,
+        }
+    }
+}
+
+/// The first non-blank line of `text`, cut to `max_chars`
+pub fn first_line(text: &str, max_chars: usize) -> String {
+    let line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or_default().trim();
+    if line.chars().count() > max_chars {
+        format!("{}…", line.chars().take(max_chars).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// `just now`, `5m ago`, `3h ago`, `2d ago`
+pub fn format_age(timestamp: u64) -> String {
+    let seconds = now().saturating_sub(timestamp);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+/// Chat sessions in the history database (`~/.ai.history.db`)
+#[cfg(feature = "history")]
+pub struct SessionStore {
+    conn: rusqlite::Connection,
+}
+
+#[cfg(feature = "history")]
+impl SessionStore {
+    pub fn open() -> Result<Self> {
+        let path = Config::history_db_path()?;
+        let conn = rusqlite::Connection::open(&path)
+            .context("Failed to open the history database")?;
+        // Sessions hold whole conversations, pasted code and file contents included
+        config::make_private(&path)
+            .with_context(|| format!("Failed to restrict {}", path.display()))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chat_sessions (
+                id TEXT PRIMARY KEY,
+                name TEXT,
+                working_dir TEXT NOT NULL,
+                model TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                messages TEXT NOT NULL,
+                context TEXT
+            )",
+            [],
+        )?;
+        // Tables created before context management lack the column
+        if conn.prepare("SELECT context FROM chat_sessions LIMIT 0").is_err() {
+            conn.execute("ALTER TABLE chat_sessions ADD COLUMN context TEXT", [])?;
+        }
+        Ok(Self { conn })
+    }
+
+    pub fn save(&self, session: &ChatSession) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO chat_sessions (id, name, working_dir, model, created_at, updated_at, messages, context)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                &session.id,
+                &session.name,
+                &session.working_dir,
+                &session.model,
+                session.created_at as i64,
+                session.updated_at as i64,
+                serde_json::to_string(&session.messages)?,
+                serde_json::to_string(&session.context)?,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Every session, most recently updated first
+    pub fn list(&self) -> Result<Vec<ChatSession>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, working_dir, model, created_at, updated_at, messages, context
+             FROM chat_sessions
+             ORDER BY updated_at DESC"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                ChatSession {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    working_dir: row.get(2)?,
+                    model: row.get(3)?,
+                    created_at: row.get::<_, i64>(4)? as u64,
+                    updated_at: row.get::<_, i64>(5)? as u64,
+                    messages: Vec::new(),
+                    context: ChatContext::default(),
+                },
+                row.get::<_, String>(6)?,
+                row.get::<_, Option<String>>(7)?,
+            ))
+        })?;
+
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (mut session, messages, context) = row?;
+            session.messages = serde_json::from_str(&messages)
+                .with_context(|| format!("Session {} is corrupted", session.id))?;
+            if let Some(context) = context {
+                session.context = serde_json::from_str(&context)
+                    .with_context(|| format!("Session {} is corrupted", session.id))?;
+            }
+            sessions.push(session);
+        }
+        Ok(sessions)
+    }
+}
+
+/// Chat sessions as JSON files in `~/.ai.sessions`
+#[cfg(not(feature = "history"))]
+pub struct SessionStore {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(not(feature = "history"))]
+impl SessionStore {
+    pub fn open() -> Result<Self> {
+        Self::open_dir(Config::sessions_dir()?)
+    }
+
+    fn open_dir(dir: std::path::PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        // Sessions hold whole conversations, pasted code and file contents included
+        config::make_private(&dir)
+            .with_context(|| format!("Failed to restrict {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    pub fn save(&self, session: &ChatSession) -> Result<()> {
+        // Write a private temporary file (mode 0600) first so a crash never leaves
+        // half a session behind
+        let path = self.dir.join(format!("{}.json", session.id));
+        let partial = tempfile::Builder::new()
+            .prefix(".session-")
+            .suffix(".json.tmp")
+            .tempfile_in(&self.dir)
+            .with_context(|| format!("Failed to create a file in {}", self.dir.display()))?;
+        std::fs::write(partial.path(), serde_json::to_string_pretty(session)?)
+            .with_context(|| format!("Failed to write {}", partial.path().display()))?;
+        partial.persist(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Every session, most recently updated first; unreadable files are skipped
+    pub fn list(&self) -> Result<Vec<ChatSession>> {
+        let mut sessions: Vec<ChatSession> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated_at));
+        Ok(sessions)
+    }
+}
+
+impl SessionStore {
+    /// The session with this id or name; a unique id prefix also matches
+    pub fn find(&self, key: &str) -> Result<ChatSession> {
+        let sessions = self.list()?;
+        if let Some(session) = sessions.iter()
+            .find(|session| session.id == key || session.name.as_deref() == Some(key))
+        {
+            return Ok(session.clone());
+        }
+
+        let mut matches = sessions.into_iter().filter(|session| session.id.starts_with(key));
+        match (matches.next(), matches.next()) {
+            (Some(session), None) => Ok(session),
+            (Some(_), Some(_)) => Err(anyhow!("'{}' matches more than one session; use more of the id", key)),
+            (None, _) => Err(anyhow!("No chat session named '{}'. Run `ai chat --list` to see them.", key)),
+        }
+    }
+
+    /// The most recently updated session started in `working_dir`
+    pub fn latest_in(&self, working_dir: &str) -> Result<Option<ChatSession>> {
+        Ok(self.list()?.into_iter().find(|session| session.working_dir == working_dir))
+    }
+
+    /// Give `session` a name, taking it from any other session that had it
+    pub fn rename(&self, session: &mut ChatSession, name: &str) -> Result<()> {
+        for mut other in self.list()? {
+            if other.id != session.id && other.name.as_deref() == Some(name) {
+                other.name = None;
+                self.save(&other)?;
+            }
+        }
+        session.name = Some(name.to_string());
+        self.save(session)
+    }
+}
+
+#[cfg(all(test, unix, not(feature = "history")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_sessions_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let home = tempfile::tempdir().unwrap();
+        let dir = home.path().join(".ai.sessions");
+        let store = SessionStore::open_dir(dir.clone()).unwrap();
+        let mut session = ChatSession::new("/tmp", "ollama/llama3");
+        session.messages.push(ChatMessage::user("my api key is sk-123"));
+        store.save(&session).unwrap();
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join(format!("{}.json", session.id))), 0o600);
+        // Only the finished file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(store.find(&session.id).unwrap().messages.len(), 1);
+    }
+}
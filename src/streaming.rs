@@ -0,0 +1,374 @@
+use crate::interrupt;
+use anyhow::{anyhow, Result};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+
+/// A stream of text tokens produced by a provider
+pub type TokenStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// Splits a chunked byte stream into complete lines.
+///
+/// Partial lines (and partial UTF-8 sequences) are kept until the rest arrives,
+/// so the same buffer works for both SSE (`data: ...`) and NDJSON responses.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a chunk and return every line completed by it
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+        lines
+    }
+
+    /// Return whatever is left once the byte stream has ended
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&self.buffer).trim().to_string();
+        self.buffer.clear();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+/// One line of Ollama's NDJSON `/api/chat` stream
+#[derive(Debug, Deserialize)]
+struct OllamaStreamChunk {
+    #[serde(default)]
+    message: Option<OllamaStreamMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaStreamMessage {
+    #[serde(default)]
+    content: String,
+}
+
+/// Parsed content of a single streamed line
+#[derive(Debug, PartialEq)]
+pub enum StreamEvent {
+    Token(String),
+    Done,
+    Skip,
+}
+
+/// Parse one NDJSON line from Ollama's streaming chat endpoint
+pub fn parse_ollama_line(line: &str) -> Result<StreamEvent> {
+    let chunk: OllamaStreamChunk = serde_json::from_str(line)
+        .map_err(|e| anyhow!("Invalid stream chunk from Ollama: {} ({})", e, line))?;
+
+    if let Some(error) = chunk.error {
+        return Err(anyhow!("{}", error));
+    }
+
+    match chunk.message {
+        Some(message) if !message.content.is_empty() => Ok(StreamEvent::Token(message.content)),
+        _ if chunk.done => Ok(StreamEvent::Done),
+        _ => Ok(StreamEvent::Skip),
+    }
+}
+
+/// Tokens of a server-sent events body: each `data:` payload is parsed with `parse`,
+/// `event:` and comment lines are skipped
+pub fn sse_tokens<B, E>(
+    bytes: impl Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    parse: fn(&str) -> Result<StreamEvent>,
+) -> TokenStream
+where
+    B: AsRef<[u8]> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    line_tokens(bytes, move |line| match line.strip_prefix("data:") {
+        Some(data) => parse(data.trim()),
+        None => Ok(StreamEvent::Skip),
+    })
+}
+
+/// Tokens of a newline-delimited JSON body, each line parsed with `parse`
+pub fn ndjson_tokens<B, E>(
+    bytes: impl Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    parse: fn(&str) -> Result<StreamEvent>,
+) -> TokenStream
+where
+    B: AsRef<[u8]> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    line_tokens(bytes, parse)
+}
+
+/// Split a response body into lines and yield the tokens `parse` finds in them,
+/// until it reports the end of the reply or the body ends
+fn line_tokens<B, E>(
+    bytes: impl Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    parse: impl Fn(&str) -> Result<StreamEvent> + Send + 'static,
+) -> TokenStream
+where
+    B: AsRef<[u8]> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    let stream = async_stream::try_stream! {
+        let mut bytes = Box::pin(bytes);
+        let mut lines = LineBuffer::new();
+        while let Some(chunk) = bytes.next().await {
+            let chunk = chunk.map_err(|e| anyhow!("Stream interrupted: {}", e))?;
+            for line in lines.push(chunk.as_ref()) {
+                match parse(&line)? {
+                    StreamEvent::Token(token) => yield token,
+                    StreamEvent::Done => return,
+                    StreamEvent::Skip => {}
+                }
+            }
+        }
+        // The last line may come without a newline
+        if let Some(line) = lines.finish() {
+            if let StreamEvent::Token(token) = parse(&line)? {
+                yield token;
+            }
+        }
+    };
+
+    Box::pin(stream)
+}
+
+/// Print tokens to `out` as they arrive and return the assembled text.
+///
+/// Ctrl-C stops reading the stream; the text received so far is still returned
+/// so callers can keep it (e.g. in the chat conversation).
+pub async fn print_token_stream(stream: TokenStream, out: &mut impl Write) -> Result<String> {
+    print_tokens_until(stream, out, interrupt::cancelled()).await
+}
+
+/// Print tokens until the stream ends or `cancel` completes; a cancelled stream is
+/// dropped, closing the connection, and the partial text is returned
+async fn print_tokens_until(mut stream: TokenStream, out: &mut impl Write, cancel: impl Future) -> Result<String> {
+    let mut text = String::new();
+    tokio::pin!(cancel);
+
+    loop {
+        tokio::select! {
+            next = stream.next() => match next {
+                Some(Ok(token)) => {
+                    write!(out, "{}", token)?;
+                    out.flush()?;
+                    text.push_str(&token);
+                }
+                Some(Err(e)) => {
+                    writeln!(out)?;
+                    return Err(e);
+                }
+                None => break,
+            },
+            _ = &mut cancel => {
+                writeln!(out)?;
+                eprintln!("[interrupted]");
+                return Ok(text);
+            }
+        }
+    }
+
+    writeln!(out)?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A response body delivered in the given reads
+    fn body(chunks: &[&'static str]) -> impl Stream<Item = std::result::Result<&'static [u8], String>> + Send + 'static {
+        futures::stream::iter(chunks.iter().map(|chunk| Ok(chunk.as_bytes())).collect::<Vec<_>>())
+    }
+
+    async fn collect(mut stream: TokenStream) -> (Vec<String>, Option<String>) {
+        let mut tokens = Vec::new();
+        while let Some(token) = stream.next().await {
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(e) => return (tokens, Some(e.to_string())),
+            }
+        }
+        (tokens, None)
+    }
+
+    #[test]
+    fn line_buffer_joins_lines_split_across_reads() {
+        let mut lines = LineBuffer::new();
+        assert!(lines.push(b"data: {\"a\"").is_empty());
+        assert_eq!(lines.push(b": 1}\r\n\r\nda"), vec!["data: {\"a\": 1}"]);
+        // "é" split between two reads
+        assert!(lines.push(b"ta: caf\xc3").is_empty());
+        assert_eq!(lines.push(b"\xa9\n"), vec!["data: café"]);
+        assert_eq!(lines.push(b"tail"), Vec::<String>::new());
+        assert_eq!(lines.finish().as_deref(), Some("tail"));
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[test]
+    fn parses_ollama_lines() {
+        assert_eq!(
+            parse_ollama_line(r#"{"message":{"role":"assistant","content":"Hi"},"done":false}"#).unwrap(),
+            StreamEvent::Token("Hi".to_string())
+        );
+        assert_eq!(
+            parse_ollama_line(r#"{"message":{"role":"assistant","content":""},"done":true}"#).unwrap(),
+            StreamEvent::Done
+        );
+        let error = parse_ollama_line(r#"{"error":"model 'x' not found"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "model 'x' not found");
+        assert!(parse_ollama_line("not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn ndjson_tokens_follow_lines_across_reads() {
+        let stream = ndjson_tokens(
+            body(&[
+                r#"{"message":{"content":"Hel"},"done":false}"#,
+                "\n{\"message\":{\"con",
+                r#"tent":"lo"},"done":false}"#,
+                "\n",
+                r#"{"message":{"content":""},"done":true}"#,
+                "\n{\"message\":{\"content\":\"ignored\"}}\n",
+            ]),
+            parse_ollama_line,
+        );
+        assert_eq!(collect(stream).await, (vec!["Hel".to_string(), "lo".to_string()], None));
+    }
+
+    #[tokio::test]
+    async fn ndjson_tokens_keep_a_last_line_without_newline() {
+        let stream = ndjson_tokens(body(&[r#"{"message":{"content":"end"},"done":false}"#]), parse_ollama_line);
+        assert_eq!(collect(stream).await, (vec!["end".to_string()], None));
+    }
+
+    #[tokio::test]
+    async fn ndjson_tokens_stop_at_an_error_line() {
+        let stream = ndjson_tokens(
+            body(&["{\"message\":{\"content\":\"Hi\"}}\n", "{\"error\":\"out of memory\"}\n"]),
+            parse_ollama_line,
+        );
+        assert_eq!(collect(stream).await, (vec!["Hi".to_string()], Some("out of memory".to_string())));
+    }
+
+    #[tokio::test]
+    async fn sse_tokens_parse_data_lines_only() {
+        fn parse(data: &str) -> Result<StreamEvent> {
+            match data {
+                "[DONE]" => Ok(StreamEvent::Done),
+                "" => Ok(StreamEvent::Skip),
+                token => Ok(StreamEvent::Token(token.to_string())),
+            }
+        }
+
+        let stream = sse_tokens(
+            body(&[": keep-alive\nevent: delta\nda", "ta: one\n\ndata:", " two\n\n", "data: [DONE]\n\ndata: three\n"]),
+            parse,
+        );
+        assert_eq!(collect(stream).await, (vec!["one".to_string(), "two".to_string()], None));
+    }
+
+    #[tokio::test]
+    async fn interrupted_body_ends_the_stream_with_an_error() {
+        let chunks: Vec<std::result::Result<&'static [u8], String>> = vec![
+            Ok(b"{\"message\":{\"content\":\"par\"}}\n"),
+            Err("connection reset".to_string()),
+        ];
+        let stream = ndjson_tokens(futures::stream::iter(chunks), parse_ollama_line);
+        assert_eq!(
+            collect(stream).await,
+            (vec!["par".to_string()], Some("Stream interrupted: connection reset".to_string()))
+        );
+    }
+
+    /// Sets its flag when dropped, to tell whether a stream was released
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Yields `tokens`, then waits forever like a model that stopped mid-reply
+    fn stalled_stream(tokens: &'static [&'static str], dropped: Arc<AtomicBool>) -> TokenStream {
+        Box::pin(async_stream::try_stream! {
+            let _flag = DropFlag(dropped);
+            for token in tokens {
+                yield token.to_string();
+            }
+            futures::future::pending::<()>().await;
+        })
+    }
+
+    #[tokio::test]
+    async fn cancelling_keeps_the_partial_answer_and_drops_the_stream() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let stream = stalled_stream(&["Hel", "lo"], dropped.clone());
+
+        let mut out = Vec::new();
+        let text = print_tokens_until(stream, &mut out, tokio::time::sleep(Duration::from_millis(50)))
+            .await
+            .unwrap();
+
+        assert_eq!(text, "Hello");
+        assert_eq!(String::from_utf8(out).unwrap(), "Hello\n");
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn an_error_mid_stream_leaves_the_printed_part() {
+        let stream = ndjson_tokens(
+            body(&["{\"message\":{\"content\":\"Par\"}}\n", "{\"error\":\"model unloaded\"}\n"]),
+            parse_ollama_line,
+        );
+
+        let mut out = Vec::new();
+        let error = print_tokens_until(stream, &mut out, futures::future::pending::<()>()).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "model unloaded");
+        assert_eq!(String::from_utf8(out).unwrap(), "Par\n");
+    }
+
+    #[tokio::test]
+    async fn a_finished_stream_returns_the_whole_answer() {
+        let stream = sse_tokens(
+            body(&["data: {\"choices\":[{\"delta\":{\"content\":\"Hi \"}}]}\n\nda", "ta: {\"choices\":[{\"delta\":{\"content\":\"there\"}}]}\n\n"]),
+            |data| {
+                let chunk: serde_json::Value = serde_json::from_str(data)?;
+                Ok(StreamEvent::Token(chunk["choices"][0]["delta"]["content"].as_str().unwrap_or_default().to_string()))
+            },
+        );
+
+        let mut out = Vec::new();
+        let text = print_tokens_until(stream, &mut out, futures::future::pending::<()>()).await.unwrap();
+
+        assert_eq!(text, "Hi there");
+        assert_eq!(String::from_utf8(out).unwrap(), "Hi there\n");
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+/// Context window assumed for models that are not in any table
+pub const DEFAULT_CONTEXT_WINDOW: usize = 8192;
+
+/// Share of the context window that is budgeted; the bundled tokenizer is exact for
+/// OpenAI models and only an estimate for other model families
+const WINDOW_SAFETY_FACTOR: f64 = 0.9;
+
+/// Tokens each chat message costs on top of its content (role and separators)
+pub const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Context windows (in tokens) of well-known models. A pattern matches model names
+/// that start with it (ignoring case and any `vendor/` prefix); the longest match wins.
+const KNOWN_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    // OpenAI
+    ("gpt-5", 400000),
+    ("gpt-4.1", 1047576),
+    ("gpt-4o", 128000),
+    ("gpt-4-turbo", 128000),
+    ("gpt-4", 8192),
+    ("gpt-3.5", 16385),
+    ("o1", 200000),
+    ("o3", 200000),
+    ("o4", 200000),
+    // Hosted APIs
+    ("deepseek", 65536),
+    ("claude", 200000),
+    ("gemini", 1048576),
+    ("gemini-1.0", 32768),
+    ("mistral-large", 131072),
+    // Common Ollama models
+    ("qwen3", 40960),
+    ("qwen2.5", 32768),
+    ("qwen2", 32768),
+    ("qwen", 8192),
+    ("codeqwen", 65536),
+    ("gemma3", 131072),
+    ("gemma2", 8192),
+    ("gemma", 8192),
+    ("llama3.3", 131072),
+    ("llama3.2", 131072),
+    ("llama3.1", 131072),
+    ("llama-3.3", 131072),
+    ("llama-3.2", 131072),
+    ("llama-3.1", 131072),
+    ("llama3", 8192),
+    ("llama-3", 8192),
+    ("llama2", 4096),
+    ("codellama", 16384),
+    ("mistral", 32768),
+    ("mixtral", 32768),
+    ("deepseek-coder", 16384),
+    ("deepseek-coder-v2", 163840),
+    ("deepseek-r1", 131072),
+    ("phi3", 4096),
+    ("phi4", 16384),
+];
+
+/// Estimate how many tokens `text` takes, using the cl100k BPE vocabulary bundled in the binary
+pub fn count(text: &str) -> usize {
+    if text.is_empty() {
+        return 0;
+    }
+    tiktoken_rs::cl100k_base_singleton().encode_ordinary(text).len()
+}
+
+/// Whether `text` fits in `max_tokens`; short text is accepted without tokenizing,
+/// since a token is never shorter than one byte
+pub fn fits(text: &str, max_tokens: usize) -> bool {
+    text.len() <= max_tokens || count(text) <= max_tokens
+}
+
+/// The longest prefix of `text` made of whole lines that fits in `max_tokens`
+pub fn truncate_lines(text: &str, max_tokens: usize) -> &str {
+    let mut used = 0;
+    let mut end = 0;
+    for line in text.split_inclusive('\n') {
+        used += count(line);
+        if used > max_tokens {
+            break;
+        }
+        end += line.len();
+    }
+    &text[..end]
+}
+
+/// Context window of `model`: `overrides` (the `[context_windows]` config table) first,
+/// then the size `reported` by the server, the built-in table and `DEFAULT_CONTEXT_WINDOW`
+pub fn context_window(model: &str, overrides: &HashMap<String, usize>, reported: Option<usize>) -> usize {
+    let user = overrides.iter().map(|(pattern, window)| (pattern.as_str(), *window));
+    longest_match(model, user)
+        .or(reported)
+        .or_else(|| longest_match(model, KNOWN_CONTEXT_WINDOWS.iter().copied()))
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+/// Tokens of `window` that may be used by a request, leaving the estimator's margin
+pub fn usable(window: usize) -> usize {
+    (window as f64 * WINDOW_SAFETY_FACTOR) as usize
+}
+
+/// Tokens left for content in `window` once `overhead` tokens of prompt and the
+/// `reserved_output_tokens` of the reply are taken out; zero when they don't fit
+pub fn content_tokens(window: usize, overhead: usize, reserved_output_tokens: usize) -> usize {
+    usable(window).saturating_sub(overhead.saturating_add(reserved_output_tokens))
+}
+
+fn longest_match<'a>(model: &str, table: impl Iterator<Item = (&'a str, usize)>) -> Option<usize> {
+    let model = model.to_lowercase();
+    let name = model.rsplit('/').next().unwrap_or(&model);
+    table
+        .filter(|(pattern, _)| {
+            let pattern = pattern.to_lowercase();
+            model == pattern || name.starts_with(&pattern)
+        })
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, window)| window)
+}
+
+/// How much of a diff can be sent in one request
+#[derive(Debug, Clone, Copy)]
+pub struct DiffBudget {
+    pub max_tokens: usize,
+    /// Character cap from `max_diff_length`, when configured
+    pub max_chars: Option<usize>,
+}
+
+impl DiffBudget {
+    pub fn fits(&self, text: &str) -> bool {
+        self.max_chars.is_none_or(|max| text.len() <= max) && fits(text, self.max_tokens)
+    }
+
+    /// Like `fits`, for text whose length and token count are already known
+    pub fn fits_counted(&self, chars: usize, tokens: usize) -> bool {
+        self.max_chars.is_none_or(|max| chars <= max) && tokens <= self.max_tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_overrides() -> HashMap<String, usize> {
+        HashMap::new()
+    }
+
+    #[test]
+    fn known_models_match_by_their_longest_prefix() {
+        assert_eq!(context_window("gpt-4", &no_overrides(), None), 8192);
+        assert_eq!(context_window("gpt-4o-mini", &no_overrides(), None), 128000);
+        assert_eq!(context_window("gpt-4-turbo-preview", &no_overrides(), None), 128000);
+        assert_eq!(context_window("deepseek-chat", &no_overrides(), None), 65536);
+        assert_eq!(context_window("deepseek-coder-v2:16b", &no_overrides(), None), 163840);
+        assert_eq!(context_window("deepseek-coder:6.7b", &no_overrides(), None), 16384);
+        assert_eq!(context_window("gemini-1.0-pro", &no_overrides(), None), 32768);
+        assert_eq!(context_window("gemini-2.5-flash", &no_overrides(), None), 1048576);
+    }
+
+    #[test]
+    fn vendor_prefixes_and_case_are_ignored() {
+        assert_eq!(context_window("openai/GPT-4o", &no_overrides(), None), 128000);
+        assert_eq!(context_window("meta-llama/Llama-3.1-70B-Instruct", &no_overrides(), None), 131072);
+        assert_eq!(context_window("Qwen/Qwen2.5-Coder-32B", &no_overrides(), None), 32768);
+        // Only the start of the name counts
+        assert_eq!(context_window("my-gpt-4o", &no_overrides(), None), DEFAULT_CONTEXT_WINDOW);
+    }
+
+    #[test]
+    fn unknown_models_get_the_default_window() {
+        assert_eq!(context_window("totally-new-model", &no_overrides(), None), 8192);
+        assert_eq!(context_window("", &no_overrides(), None), 8192);
+    }
+
+    #[test]
+    fn overrides_beat_the_reported_window_which_beats_the_table() {
+        let overrides = HashMap::from([
+            ("llama3.1".to_string(), 16384),
+            ("llama3.1:70b".to_string(), 65536),
+            ("Internal/Model".to_string(), 4096),
+        ]);
+
+        // The server's num_ctx is ignored when the user set a window for the model
+        assert_eq!(context_window("llama3.1:8b", &overrides, Some(131072)), 16384);
+        assert_eq!(context_window("llama3.1:70b", &overrides, Some(131072)), 65536);
+        // A full `vendor/name` pattern matches the whole model name, in any case
+        assert_eq!(context_window("internal/model", &overrides, None), 4096);
+        // Without an override the reported size wins over the built-in table
+        assert_eq!(context_window("gemma2:9b", &overrides, Some(4096)), 4096);
+        assert_eq!(context_window("gemma2:9b", &overrides, None), 8192);
+    }
+
+    #[test]
+    fn content_budget_leaves_room_for_the_prompt_and_reply() {
+        // 90% of the window, then the prompt and the reserved reply come off
+        assert_eq!(usable(10000), 9000);
+        assert_eq!(content_tokens(10000, 500, 1024), 9000 - 500 - 1024);
+        assert_eq!(content_tokens(10000, 0, 0), 9000);
+        // Exactly full, then over: never wraps around
+        assert_eq!(content_tokens(10000, 8000, 1000), 0);
+        assert_eq!(content_tokens(10000, 8000, 4000), 0);
+        assert_eq!(content_tokens(0, 1, 1), 0);
+        assert_eq!(content_tokens(10000, usize::MAX, usize::MAX), 0);
+    }
+
+    #[test]
+    fn truncates_to_whole_lines_within_the_budget() {
+        let text = "first line\nsecond line\nthird line\n";
+        assert_eq!(truncate_lines(text, count("first line\nsecond line\n")), "first line\nsecond line\n");
+        assert_eq!(truncate_lines(text, 1), "");
+        assert_eq!(truncate_lines(text, 1000), text);
+        assert!(fits("short", 5));
+        assert!(!fits(&"word ".repeat(100), 10));
+    }
+}
@@ -3,6 +3,15 @@ use skim::prelude::*;
 use std::io::Cursor;
 use std::process::Command;
 
+/// Clipboard tools tried in order, with their arguments
+const CLIPBOARD_TOOLS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]), // macOS
+    ("xclip", &["-selection", "clipboard"]), // Linux X11
+    ("wl-copy", &[]), // Linux Wayland
+];
+
+pub const CLIPBOARD_INSTALL_HINT: &str = "Install pbcopy (macOS), xclip (Linux X11), or wl-copy (Linux Wayland)";
+
 pub struct Utils;
 
 impl Utils {
@@ -82,6 +91,60 @@ impl Utils {
         Ok(matches!(input.as_str(), "y" | "yes"))
     }
 
+    /// Prompt the user for a line of free-form input
+    pub fn prompt_input(message: &str) -> Result<String> {
+        println!("{}", message);
+        
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        
+        Ok(input.trim().to_string())
+    }
+
+    /// Open `initial` in $VISUAL/$EDITOR and return the edited text.
+    /// Lines starting with '#' are dropped, like git does for commit messages.
+    pub fn edit_text(initial: &str) -> Result<String> {
+        // Created with a random name and mode 0600, and removed when dropped
+        let file = tempfile::Builder::new()
+            .prefix("ai-edit-")
+            .suffix(".txt")
+            .tempfile()
+            .context("Failed to create temporary file for editing")?;
+        std::fs::write(file.path(), format!("{}\n\n# Lines starting with '#' are ignored.\n", initial))
+            .context("Failed to write temporary file for editing")?;
+
+        Self::open_in_editor(file.path())?;
+        let content = std::fs::read_to_string(file.path());
+
+        let text = content?
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(text.trim().to_string())
+    }
+
+    /// Open a file in $VISUAL/$EDITOR (falling back to vi) and wait for the editor to exit
+    pub fn open_in_editor(path: &std::path::Path) -> Result<()> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+
+        // Run through the shell so editors with arguments (e.g. "code --wait") work
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path)
+            .status()
+            .context(format!("Failed to launch editor '{}'", editor))?;
+
+        if !status.success() {
+            return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+        }
+        Ok(())
+    }
+
     /// Get shell history commands
     pub fn get_shell_history(limit: usize) -> Result<Vec<String>> {
         // Try to read history from file directly
@@ -115,8 +178,8 @@ impl Utils {
                             }
                             
                             // Handle fish format: "- cmd: command"
-                            if line.starts_with("- cmd: ") {
-                                return Some(line[7..].to_string());
+                            if let Some(cmd) = line.strip_prefix("- cmd: ") {
+                                return Some(cmd.to_string());
                             }
                             
                             // Regular history line
@@ -146,11 +209,7 @@ impl Utils {
                     .lines()
                     .filter_map(|line| {
                         // Parse fc output format: " 1234  command"
-                        if let Some(pos) = line.find(char::is_alphabetic) {
-                            Some(line[pos..].to_string())
-                        } else {
-                            None
-                        }
+                        line.find(char::is_alphabetic).map(|pos| line[pos..].to_string())
                     })
                     .collect();
 
@@ -168,7 +227,7 @@ impl Utils {
     /// Detect the current shell
     pub fn get_current_shell() -> Result<String> {
         if let Ok(shell) = std::env::var("SHELL") {
-            Ok(shell.split('/').last().unwrap_or("unknown").to_string())
+            Ok(shell.split('/').next_back().unwrap_or("unknown").to_string())
         } else {
             Err(anyhow!("Could not determine current shell"))
         }
@@ -369,16 +428,16 @@ impl Utils {
         None
     }
 
+    /// The first clipboard tool that is installed
+    pub fn clipboard_tool() -> Option<&'static str> {
+        CLIPBOARD_TOOLS.iter()
+            .map(|(tool, _)| *tool)
+            .find(|tool| Self::is_command_available(tool))
+    }
+
     /// Copy text to clipboard
     pub fn copy_to_clipboard(text: &str) -> Result<()> {
-        // Try different clipboard tools
-        let clipboard_tools = [
-            ("pbcopy", vec![]), // macOS
-            ("xclip", vec!["-selection", "clipboard"]), // Linux X11
-            ("wl-copy", vec![]), // Linux Wayland
-        ];
-
-        for (tool, args) in &clipboard_tools {
+        for &(tool, args) in CLIPBOARD_TOOLS {
             if Self::is_command_available(tool) {
                 let mut cmd = Command::new(tool);
                 for arg in args {
@@ -402,7 +461,7 @@ impl Utils {
             }
         }
 
-        Err(anyhow!("No supported clipboard tool found. Install pbcopy (macOS), xclip (Linux X11), or wl-copy (Linux Wayland)"))
+        Err(anyhow!("No supported clipboard tool found. {}", CLIPBOARD_INSTALL_HINT))
     }
 
     /// Create a GitHub repository using gh CLI